@@ -0,0 +1,580 @@
+use crate::context::{self, BuildContext};
+use crate::engine::ContainerEngine;
+use crate::modules;
+use crate::secrets::{self, Secret};
+use anyhow::{bail, Context, Result};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::fs;
+use std::io::prelude::*;
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+const RELEASE_BUILD: &str = include_str!("template/Release.Dockerfile");
+const LOCAL_BUILD: &str = include_str!("template/Dev.Dockerfile");
+const TEST_BUILD: &str = include_str!("template/Libtest.Dockerfile");
+
+/// Folder, relative to the project, where Dockerfile templates can be overridden.
+pub const TEMPLATE_DIR: &str = ".roche/templates";
+
+/// `CARGO_HOME` of the build images, whose `registry` is cached by [`BuildCache::mounts`].
+pub const CARGO_HOME: &str = "/usr/local/cargo";
+
+/// The kind of image a `BuildRequest` produces.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum BuildKind {
+    /// A debug build of the function for local development.
+    #[default]
+    Dev,
+    /// Runs the lib tests found in `lib.rs`.
+    Test,
+    /// An optimised build of the function for deployment.
+    Release,
+}
+
+impl BuildKind {
+    /// The prefix used when generating an image tag for this kind of build.
+    pub fn tag_prefix(self) -> &'static str {
+        match self {
+            BuildKind::Dev => "dev-",
+            BuildKind::Test => "test-",
+            BuildKind::Release => "",
+        }
+    }
+
+    /// Lowercase name of the kind, as exposed to templates.
+    pub fn name(self) -> &'static str {
+        match self {
+            BuildKind::Dev => "dev",
+            BuildKind::Test => "test",
+            BuildKind::Release => "release",
+        }
+    }
+
+    /// File name of the Dockerfile template for this kind, also used for overrides in `TEMPLATE_DIR`.
+    pub fn template_name(self) -> &'static str {
+        match self {
+            BuildKind::Dev => "Dev.Dockerfile",
+            BuildKind::Test => "Libtest.Dockerfile",
+            BuildKind::Release => "Release.Dockerfile",
+        }
+    }
+
+    /// The Dockerfile template shipped with roche.
+    pub fn builtin_template(self) -> &'static str {
+        match self {
+            BuildKind::Dev => LOCAL_BUILD,
+            BuildKind::Test => TEST_BUILD,
+            BuildKind::Release => RELEASE_BUILD,
+        }
+    }
+}
+
+/// Everything needed to turn a folder containing `functions.rs` into an image.
+#[derive(Debug, Clone, Default)]
+pub struct BuildRequest {
+    pub kind: BuildKind,
+    /// Image the function is compiled in.
+    pub build_image: String,
+    /// Image the compiled service is copied into. Unused for `BuildKind::Test`.
+    pub runtime_image: String,
+    /// Name the resulting image is tagged with.
+    pub tag: String,
+    /// Folder holding `functions.rs` (or `functions/`) and the optional `lib.rs`.
+    pub context_dir: PathBuf,
+    /// Passed to the engine as `--build-arg KEY=VALUE`.
+    pub build_args: BTreeMap<String, String>,
+    /// Crates added to the service `Cargo.toml`, as they would appear under `[dependencies]`.
+    pub dependencies: BTreeMap<String, toml::Value>,
+    /// Mount prefixes of the modules in `functions/`, keyed by module name. When empty every
+    /// module is mounted at `/<module>`.
+    pub functions: BTreeMap<String, String>,
+    /// Platforms such as `linux/arm64` the image is built for, the engine's own when empty. More
+    /// than one builds a manifest list with an image for each.
+    pub platforms: Vec<String>,
+    /// Whether the image is pushed once built. docker can't keep a manifest list locally, so it
+    /// pushes one as part of the build instead.
+    pub push: bool,
+    /// How the build reuses the work of earlier ones.
+    pub cache: BuildCache,
+    /// Secrets the cargo steps read, by the variable they are read into, see [`crate::secrets`].
+    pub secrets: BTreeMap<String, Secret>,
+    /// Whether the release image keeps the service's `Cargo.lock` in `/app`, for the SBOM of
+    /// [`crate::sbom`].
+    pub sbom: bool,
+}
+
+/// Caches that carry cargo's work and the image layers from one build to the next.
+#[derive(Debug, Clone, Default)]
+pub struct BuildCache {
+    /// Mounts BuildKit caches over the cargo registry and `/app-build/target` of the build
+    /// image, seeded from the image, so crates are only downloaded and compiled once.
+    pub mounts: bool,
+    /// `CARGO_HOME` of the build image, [`CARGO_HOME`] when not set.
+    pub cargo_home: Option<String>,
+    /// Passed to the engine as `--cache-from`, e.g. `type=registry,ref=quay.io/myorg/hello:cache`.
+    pub from: Option<String>,
+    /// Passed to the engine as `--cache-to`, e.g.
+    /// `type=registry,ref=quay.io/myorg/hello:cache,mode=max`.
+    pub to: Option<String>,
+}
+
+impl BuildCache {
+    /// The `RUN` flags mounting the caches over a build from `build_image`, each followed by a
+    /// space, or nothing when [`BuildCache::mounts`] is off.
+    pub fn run_mounts(&self, build_image: &str) -> String {
+        if !self.mounts {
+            return String::new();
+        }
+        let registry = format!(
+            "{}/registry",
+            self.cargo_home
+                .as_deref()
+                .unwrap_or(CARGO_HOME)
+                .trim_end_matches('/')
+        );
+        [
+            ("roche-cargo-registry", registry.as_str(), "shared"),
+            ("roche-target", "/app-build/target", "locked"),
+        ]
+        .iter()
+        .map(|(id, target, sharing)| {
+            format!(
+                "--mount=type=cache,id={id},target={target},from={image},source={target},sharing={sharing} ",
+                id = id,
+                target = target,
+                image = build_image,
+                sharing = sharing
+            )
+        })
+        .collect()
+    }
+}
+
+impl BuildRequest {
+    /// Renders the Dockerfile for this request based on the files present in `context_dir`.
+    ///
+    /// A template found in `TEMPLATE_DIR` replaces the built in one. See `template_values`
+    /// for the variables a template can use.
+    pub fn render_dockerfile(&self) -> Result<String> {
+        let (name, source) = match self.template_override() {
+            Some(path) => {
+                let source = fs::read_to_string(&path)
+                    .with_context(|| format!("Couldn't read template {}", path.display()))?;
+                (path.display().to_string(), source)
+            }
+            None => (
+                self.kind.template_name().to_string(),
+                self.kind.builtin_template().to_string(),
+            ),
+        };
+        let template = liquid::ParserBuilder::with_stdlib()
+            .build()?
+            .parse(&source)
+            .with_context(|| format!("Couldn't parse template {}", name))?;
+        template
+            .render(&self.template_values()?)
+            .with_context(|| format!("Couldn't render template {}", name))
+    }
+
+    /// The variables available to Dockerfile templates.
+    ///
+    /// `build_image`, `runtime_image`, `tag` and `kind` are strings. `lib` and `env` are true
+    /// when `lib.rs` and `.env` are in the build context, see [`BuildRequest::context_files`];
+    /// built in templates never copy `.env`. `sources` lists the other Rust files the function
+    /// declares with `mod`, relative to the context, except those in a `functions/` folder of
+    /// handlers which is copied whole. `service` when the service crate has been ejected next
+    /// to `functions.rs` and `tests` is true when the lib tests should run. `manifest` is set when
+    /// the service `Cargo.toml` differs from the one in the build image, holding it as quoted
+    /// `printf` arguments. `functions` is set the same way to the generated `functions.rs` when
+    /// the project keeps its handlers in a `functions/` folder. `cache` is true when the cargo
+    /// caches are mounted and `mounts` holds the flags mounting them, to put after `RUN`. As the
+    /// target dir is then only there during the `RUN`, the service binary is copied to
+    /// `/app-build/roche-service` in the same step. `secrets` holds the flags and assignments
+    /// that read the build secrets into their variables, to put after `mounts`. `sbom` is true
+    /// when the release image should keep `Cargo.lock`, see [`BuildRequest::sbom`].
+    pub fn template_values(&self) -> Result<liquid::Object> {
+        let files = self.context_files()?;
+        let included = |file: &str| files.iter().any(|f| f == Path::new(file));
+        let has_lib = included("lib.rs");
+        let multi = modules::is_multi(&self.context_dir);
+        let sources: Vec<String> = files
+            .iter()
+            .filter(|file| file.extension().is_some_and(|ext| ext == "rs"))
+            .filter(|file| {
+                !["functions.rs", "lib.rs", "main.rs"]
+                    .iter()
+                    .any(|f| file == &Path::new(f))
+            })
+            .filter(|file| !(multi && file.starts_with(modules::FUNCTIONS_DIR)))
+            .map(|file| file.display().to_string())
+            .collect();
+        let manifest = match crate::service::manifest(&self.context_dir, &self.dependencies)? {
+            Some(manifest) => liquid::model::Value::scalar(printf_args(&manifest)),
+            None => liquid::model::Value::Nil,
+        };
+        let functions = match modules::modules(&self.context_dir, &self.functions)? {
+            Some(modules) => liquid::model::Value::scalar(printf_args(&modules::render_functions(
+                &modules, None,
+            ))),
+            None => liquid::model::Value::Nil,
+        };
+        Ok(liquid::object!({
+            "kind": self.kind.name(),
+            "build_image": self.build_image.clone(),
+            "runtime_image": self.runtime_image.clone(),
+            "tag": self.tag.clone(),
+            "lib": has_lib,
+            "env": included(".env"),
+            "service": crate::service::ejected(&self.context_dir),
+            "tests": has_lib,
+            "manifest": manifest,
+            "functions": functions,
+            "sources": sources,
+            "cache": self.cache.mounts,
+            "mounts": self.cache.run_mounts(&self.build_image),
+            "secrets": secrets::run_prefix(&self.secrets),
+            "sbom": self.sbom,
+        }))
+    }
+
+    /// The files of `context_dir` sent to the engine, relative to it. Built in templates only get
+    /// the files they use, a user template the whole folder, see [`crate::context`].
+    pub fn context_files(&self) -> Result<Vec<PathBuf>> {
+        context::files(
+            &self.context_dir,
+            &self.functions,
+            self.template_override().is_some(),
+        )
+    }
+
+    /// Finds a user template for this kind in `TEMPLATE_DIR` of the context folder, or of the
+    /// project folder when the function lives in `src`.
+    pub fn template_override(&self) -> Option<PathBuf> {
+        let mut dirs = vec![self.context_dir.clone()];
+        if self.context_dir.ends_with("src") {
+            if let Some(parent) = self.context_dir.parent() {
+                dirs.push(parent.to_path_buf());
+            }
+        }
+        dirs.into_iter()
+            .map(|dir| dir.join(TEMPLATE_DIR).join(self.kind.template_name()))
+            .find(|path| path.exists())
+    }
+
+    /// Flags passed to the engine in addition to the tag and Dockerfile.
+    pub fn engine_flags(&self) -> Vec<String> {
+        let mut flags: Vec<String> = self
+            .build_args
+            .iter()
+            .flat_map(|(key, value)| vec!["--build-arg".to_string(), format!("{}={}", key, value)])
+            .collect();
+        if let [platform] = self.platforms.as_slice() {
+            flags.push("--platform".to_string());
+            flags.push(platform.clone());
+        }
+        if let Some(from) = &self.cache.from {
+            flags.push("--cache-from".to_string());
+            flags.push(from.clone());
+        }
+        if let Some(to) = &self.cache.to {
+            flags.push("--cache-to".to_string());
+            flags.push(to.clone());
+        }
+        for (name, secret) in &self.secrets {
+            flags.push("--secret".to_string());
+            flags.push(secret.engine_flag(name));
+        }
+        flags
+    }
+
+    /// Fails when a release image would contain a file that looks like it holds credentials,
+    /// such as `.env` or a private key. Dev and test images aren't shipped and aren't checked.
+    pub fn check_image(&self) -> Result<()> {
+        if self.kind != BuildKind::Release {
+            return Ok(());
+        }
+        secrets::check_image_files(&self.context_files()?)
+    }
+
+    /// Whether the request builds a manifest list rather than a single image.
+    pub fn multi_platform(&self) -> bool {
+        self.platforms.len() > 1
+    }
+
+    /// Whether `engine` pushes the image while building it, leaving nothing to push afterwards.
+    pub fn pushed_by_build(&self, engine: &dyn ContainerEngine) -> bool {
+        self.push && self.multi_platform() && !engine.stores_manifest_lists()
+    }
+
+    /// Sends the rendered Dockerfile to `engine` and builds the image in `context_dir`.
+    ///
+    /// The engine output is streamed as it arrives and the returned [`CacheStats`] are printed
+    /// at the end. A failed build returns a [`BuildFailed`] error carrying the engine exit code
+    /// and the Dockerfile step that was running.
+    pub fn execute(&self, engine: &dyn ContainerEngine) -> Result<CacheStats> {
+        self.check_image()?;
+        for (name, secret) in &self.secrets {
+            secret.check(name)?;
+        }
+        let tmp_docker_file = self.render_dockerfile()?;
+        let context = BuildContext::create(&self.context_dir, self.context_files()?)?;
+        println!(
+            "Roche: Build context {} files, {}",
+            context.files.len(),
+            context.human_size()
+        );
+        let mut command = if self.multi_platform() {
+            if engine.stores_manifest_lists() {
+                // A previous build's list would otherwise keep its images next to the new ones.
+                let _ = engine.remove_manifest(&self.tag).status();
+            }
+            engine.build_manifest(
+                &self.tag,
+                &self.platforms,
+                self.push,
+                &self.engine_flags(),
+                ".",
+            )?
+        } else {
+            engine.build(&self.tag, &self.engine_flags(), ".")
+        };
+        let mut process = command
+            .current_dir(&context.dir)
+            .stderr(Stdio::piped())
+            .spawn()
+            .with_context(|| format!("couldn't spawn {}", engine.binary()))?;
+
+        // Take stdin so it is closed once the Dockerfile is written.
+        let sent = process
+            .stdin
+            .take()
+            .unwrap()
+            .write_all(tmp_docker_file.as_bytes());
+        if sent.is_ok() {
+            println!("Roche: Sent file to builder for {}", &self.tag);
+        }
+
+        let progress = Arc::new(Mutex::new(Progress::default()));
+        let stderr = process.stderr.take().unwrap();
+        let stderr_progress = Arc::clone(&progress);
+        let stderr_thread = thread::spawn(move || {
+            for line in BufReader::new(stderr).lines().map_while(|l| l.ok()) {
+                eprintln!("{}", line);
+                stderr_progress.lock().unwrap().track(&line);
+            }
+        });
+        for line in BufReader::new(process.stdout.take().unwrap())
+            .lines()
+            .map_while(|l| l.ok())
+        {
+            println!("{}", line);
+            progress.lock().unwrap().track(&line);
+        }
+        let _ = stderr_thread.join();
+
+        let status = process
+            .wait()
+            .with_context(|| format!("couldn't wait for {}", engine.binary()))?;
+        if !status.success() {
+            return Err(BuildFailed {
+                tag: self.tag.clone(),
+                engine: engine.binary(),
+                code: status.code(),
+                step: progress.lock().unwrap().step.take(),
+            }
+            .into());
+        }
+        sent.with_context(|| format!("couldn't write to {} stdin", engine.binary()))?;
+        println!("Roche: Build complete for {}", &self.tag);
+        let stats = progress.lock().unwrap().stats.clone();
+        if stats.steps > 0 {
+            println!("Roche: Cache {}", stats);
+        }
+        Ok(stats)
+    }
+}
+
+/// Returned by [`BuildRequest::execute`] when the container engine exits unsuccessfully.
+#[derive(Debug)]
+pub struct BuildFailed {
+    pub tag: String,
+    pub engine: &'static str,
+    /// Exit code of the engine, `None` if it was killed by a signal.
+    pub code: Option<i32>,
+    /// The last Dockerfile step the engine reported before failing.
+    pub step: Option<String>,
+}
+
+impl fmt::Display for BuildFailed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} build failed for {}", self.engine, self.tag)?;
+        if let Some(code) = self.code {
+            write!(f, " with exit code {}", code)?;
+        }
+        if let Some(step) = &self.step {
+            write!(f, " at step '{}'", step)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for BuildFailed {}
+
+/// How much of a build the engine and cargo took from their caches.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    /// Dockerfile steps run or reused, not counting `FROM`.
+    pub steps: usize,
+    /// Steps the engine reused from its layer cache.
+    pub cached: usize,
+    /// Crates cargo compiled rather than found up to date in the target dir.
+    pub compiled: usize,
+    seen: BTreeSet<String>,
+    vertices: BTreeSet<String>,
+}
+
+impl CacheStats {
+    /// Counts the step, cache hit or compiled crate an engine output line reports.
+    ///
+    /// Cache hits are `#7 CACHED` for BuildKit, ` ---> Using cache` for the classic docker
+    /// builder and `--> Using cache` for podman and buildah.
+    pub fn track(&mut self, line: &str) {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        if let Some(step) = parse_step(line) {
+            let instruction = match step.split_once(']') {
+                Some((_, instruction)) => instruction,
+                None => step
+                    .split_once(':')
+                    .map_or("", |(_, instruction)| instruction),
+            };
+            let internal = step.starts_with("[internal]") || step.starts_with("[auth]");
+            if internal || instruction.trim_start().starts_with("FROM ") {
+                return;
+            }
+            // BuildKit numbers its steps, later lines of the step start with the same `#7`.
+            if words[0].starts_with('#') {
+                self.vertices.insert(words[0].to_string());
+            }
+            if self.seen.insert(step) {
+                self.steps += 1;
+            }
+            return;
+        }
+        match words.as_slice() {
+            [vertex, "CACHED"] if self.vertices.contains(*vertex) => self.cached += 1,
+            ["--->" | "-->", "Using", "cache", ..] => self.cached += 1,
+            ["Compiling", _, ..] => self.compiled += 1,
+            // BuildKit prefixes the output of a step with its number and a timestamp.
+            [vertex, _, "Compiling", _, ..] if vertex.starts_with('#') => self.compiled += 1,
+            _ => {}
+        }
+    }
+
+    /// The share of steps reused from the cache, from 0 to 100.
+    pub fn hit_rate(&self) -> usize {
+        if self.steps == 0 {
+            return 0;
+        }
+        self.cached * 100 / self.steps
+    }
+}
+
+impl fmt::Display for CacheStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "hits {} of {} steps ({}%), {} {} compiled",
+            self.cached,
+            self.steps,
+            self.hit_rate(),
+            self.compiled,
+            if self.compiled == 1 {
+                "crate"
+            } else {
+                "crates"
+            }
+        )
+    }
+}
+
+/// What a running build has reported so far.
+#[derive(Default)]
+struct Progress {
+    step: Option<String>,
+    stats: CacheStats,
+}
+
+impl Progress {
+    fn track(&mut self, line: &str) {
+        if let Some(s) = parse_step(line) {
+            self.step = Some(s);
+        }
+        self.stats.track(line);
+    }
+}
+
+/// Extracts the Dockerfile step from an engine progress line.
+///
+/// Understands the classic docker builder (`Step 2/5 : RUN cargo build`), podman and
+/// buildah (`STEP 2/5: RUN cargo build`) and BuildKit (`#7 [builder 2/3] RUN cargo build`).
+pub fn parse_step(line: &str) -> Option<String> {
+    let line = line.trim();
+    if let Some(rest) = line
+        .strip_prefix("Step ")
+        .or_else(|| line.strip_prefix("STEP "))
+    {
+        let (counter, instruction) = rest.split_once(':')?;
+        return Some(format!("Step {}: {}", counter.trim(), instruction.trim()));
+    }
+    let rest = line.strip_prefix('#')?;
+    let instruction = rest.trim_start_matches(|c: char| c.is_ascii_digit());
+    if instruction.len() < rest.len() && instruction.starts_with(" [") && instruction.contains(']')
+    {
+        return Some(instruction.trim().to_string());
+    }
+    None
+}
+
+/// Splits a comma separated list such as `linux/amd64,linux/arm64` into platforms.
+pub fn parse_platforms(list: &str) -> Result<Vec<String>> {
+    let platforms: Vec<String> = list
+        .split(',')
+        .map(|platform| platform.trim().to_string())
+        .collect();
+    for platform in &platforms {
+        check_platform(platform)?;
+    }
+    Ok(platforms)
+}
+
+/// Checks `platform` is written `os/arch` or `os/arch/variant`, e.g. `linux/arm/v7`.
+pub fn check_platform(platform: &str) -> Result<()> {
+    let parts: Vec<&str> = platform.split('/').collect();
+    let valid = (2..=3).contains(&parts.len())
+        && parts.iter().all(|part| {
+            !part.is_empty()
+                && part
+                    .chars()
+                    .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_')
+        });
+    if !valid {
+        bail!(
+            "'{}' is not a platform such as linux/amd64 or linux/arm64/v8",
+            platform
+        );
+    }
+    Ok(())
+}
+
+/// Quotes each line of `contents` for `printf '%s\n'`, one argument per Dockerfile line.
+fn printf_args(contents: &str) -> String {
+    contents
+        .lines()
+        .map(|line| format!("'{}'", line.replace('\'', r"'\''")))
+        .collect::<Vec<_>>()
+        .join(" \\\n    ")
+}
@@ -0,0 +1,271 @@
+//! A docker-compose stack running the function next to its backing services.
+//!
+//! `roche gen compose` builds the function from the release Dockerfile and starts the
+//! `[services]` of `roche.toml` on a shared network. Each service's address is handed to the
+//! function as `<NAME>_URL`, and the function only starts once the services report healthy.
+
+use crate::config::BackingService;
+use anyhow::{bail, Context, Result};
+use percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+/// File `roche gen compose` writes the stack to.
+pub const COMPOSE_FILE: &str = "docker-compose.yml";
+
+/// Network the function and its services share.
+const NETWORK: &str = "roche";
+
+/// Characters left alone in the user, password and database of a connection URL, RFC 3986's
+/// unreserved ones.
+const UNRESERVED: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'_')
+    .remove(b'~');
+
+/// The image, port, healthcheck and connection URL roche knows for a kind of service.
+#[derive(Debug, Clone)]
+pub struct Preset {
+    pub image: &'static str,
+    pub port: u16,
+    pub env: &'static [(&'static str, &'static str)],
+    /// The healthcheck command for the service's environment.
+    pub healthcheck: fn(&BTreeMap<String, String>) -> Vec<String>,
+    /// The URL handed to the function for a host, port and the service's environment.
+    pub url: fn(&str, u16, &BTreeMap<String, String>) -> String,
+}
+
+/// The preset for `kind`, one of `mongodb`, `postgres` or `redis`.
+pub fn preset(kind: &str) -> Option<Preset> {
+    match kind {
+        "mongodb" => Some(Preset {
+            image: "mongo:6",
+            port: 27017,
+            env: &[],
+            healthcheck: |_| command(&["mongosh", "--quiet", "--eval", "db.adminCommand('ping')"]),
+            url: |host, port, env| match (
+                env.get("MONGO_INITDB_ROOT_USERNAME"),
+                env.get("MONGO_INITDB_ROOT_PASSWORD"),
+            ) {
+                (Some(user), Some(password)) => format!(
+                    "mongodb://{}:{}@{}:{}",
+                    encode(user),
+                    encode(password),
+                    host,
+                    port
+                ),
+                _ => format!("mongodb://{}:{}", host, port),
+            },
+        }),
+        "postgres" => Some(Preset {
+            image: "postgres:15-alpine",
+            port: 5432,
+            env: &[("POSTGRES_PASSWORD", "postgres")],
+            healthcheck: |env| command(&["pg_isready", "-U", postgres_user(env)]),
+            url: |host, port, env| {
+                let user = postgres_user(env);
+                format!(
+                    "postgres://{}:{}@{}:{}/{}",
+                    encode(user),
+                    encode(env.get("POSTGRES_PASSWORD").map_or("", String::as_str)),
+                    host,
+                    port,
+                    encode(env.get("POSTGRES_DB").map_or(user, String::as_str))
+                )
+            },
+        }),
+        "redis" => Some(Preset {
+            image: "redis:7-alpine",
+            port: 6379,
+            env: &[],
+            healthcheck: |_| command(&["redis-cli", "ping"]),
+            url: |host, port, _| format!("redis://{}:{}", host, port),
+        }),
+        _ => None,
+    }
+}
+
+fn command(args: &[&str]) -> Vec<String> {
+    args.iter().map(|arg| arg.to_string()).collect()
+}
+
+/// The user postgres creates, `postgres` unless `POSTGRES_USER` is set.
+fn postgres_user(env: &BTreeMap<String, String>) -> &str {
+    env.get("POSTGRES_USER").map_or("postgres", String::as_str)
+}
+
+/// Percent-encodes `value` for the user, password or database of a connection URL.
+fn encode(value: &str) -> String {
+    utf8_percent_encode(value, UNRESERVED).to_string()
+}
+
+/// Whether `name` can be used as a compose service and host name.
+pub fn is_service_name(name: &str) -> bool {
+    !name.is_empty()
+        && !name.starts_with('-')
+        && name
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
+}
+
+/// The function and the services it is started with.
+#[derive(Debug, Clone)]
+pub struct ComposeRequest {
+    /// Name of the function's service, see [`crate::deploy::service_name`].
+    pub name: String,
+    pub container_port: u16,
+    pub host_port: u16,
+    /// Env files of the function, relative to the compose file.
+    pub env_files: Vec<PathBuf>,
+    pub services: BTreeMap<String, BackingService>,
+}
+
+impl ComposeRequest {
+    /// Renders the `docker-compose.yml`.
+    pub fn render(&self) -> Result<String> {
+        if self.services.contains_key(&self.name) {
+            bail!(
+                "services.{} has the same name as the function, please rename it",
+                self.name
+            );
+        }
+        let mut services = BTreeMap::new();
+        let mut environment = BTreeMap::new();
+        environment.insert("PORT".to_string(), self.container_port.to_string());
+        let mut depends_on = BTreeMap::new();
+        for (name, service) in &self.services {
+            let preset = preset(service.kind.as_deref().unwrap_or(name));
+            let port = service.port.or_else(|| preset.as_ref().map(|p| p.port));
+            let mut env: BTreeMap<String, String> = preset
+                .iter()
+                .flat_map(|p| p.env.iter())
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+                .collect();
+            env.extend(service.env.clone());
+            let healthcheck = if !service.healthcheck.is_empty() {
+                Some(service.healthcheck.clone())
+            } else {
+                preset.as_ref().map(|p| (p.healthcheck)(&env))
+            };
+
+            if let Some(port) = port {
+                environment.insert(
+                    format!("{}_URL", name.replace('-', "_").to_uppercase()),
+                    match &preset {
+                        Some(preset) => (preset.url)(name, port, &env),
+                        None => format!("tcp://{}:{}", name, port),
+                    },
+                );
+            }
+            depends_on.insert(
+                name.clone(),
+                Dependency {
+                    condition: if healthcheck.is_some() {
+                        "service_healthy"
+                    } else {
+                        "service_started"
+                    },
+                },
+            );
+            services.insert(
+                name.clone(),
+                Service {
+                    build: None,
+                    image: service
+                        .image
+                        .clone()
+                        .or_else(|| preset.as_ref().map(|p| p.image.to_string())),
+                    ports: Vec::new(),
+                    env_file: Vec::new(),
+                    environment: env,
+                    depends_on: BTreeMap::new(),
+                    healthcheck: healthcheck.map(healthcheck_of),
+                    networks: vec![NETWORK],
+                },
+            );
+        }
+
+        services.insert(
+            self.name.clone(),
+            Service {
+                build: Some(Build {
+                    context: ".",
+                    dockerfile: "Dockerfile",
+                }),
+                image: None,
+                ports: vec![format!("{}:{}", self.host_port, self.container_port)],
+                env_file: self.env_files.clone(),
+                environment,
+                depends_on,
+                healthcheck: Some(healthcheck_of(vec![
+                    "nc".to_string(),
+                    "-z".to_string(),
+                    "localhost".to_string(),
+                    self.container_port.to_string(),
+                ])),
+                networks: vec![NETWORK],
+            },
+        );
+        let mut networks = BTreeMap::new();
+        networks.insert(NETWORK, BTreeMap::new());
+        serde_yaml::to_string(&Compose { services, networks })
+            .context("Couldn't serialize the compose file")
+    }
+}
+
+fn healthcheck_of(command: Vec<String>) -> Healthcheck {
+    let mut test = vec!["CMD".to_string()];
+    test.extend(command);
+    Healthcheck {
+        test,
+        interval: "10s",
+        timeout: "5s",
+        retries: 5,
+    }
+}
+
+#[derive(Serialize)]
+struct Compose {
+    services: BTreeMap<String, Service>,
+    networks: BTreeMap<&'static str, BTreeMap<String, String>>,
+}
+
+#[derive(Serialize)]
+struct Service {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    build: Option<Build>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    image: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    ports: Vec<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    env_file: Vec<PathBuf>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    environment: BTreeMap<String, String>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    depends_on: BTreeMap<String, Dependency>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    healthcheck: Option<Healthcheck>,
+    networks: Vec<&'static str>,
+}
+
+#[derive(Serialize)]
+struct Build {
+    context: &'static str,
+    dockerfile: &'static str,
+}
+
+#[derive(Serialize)]
+struct Dependency {
+    condition: &'static str,
+}
+
+#[derive(Serialize)]
+struct Healthcheck {
+    test: Vec<String>,
+    interval: &'static str,
+    timeout: &'static str,
+    retries: u32,
+}
@@ -0,0 +1,912 @@
+//! Project configuration from `roche.toml`, the environment and built in defaults.
+//!
+//! Values are resolved with the precedence command line > environment (including
+//! `.rocherc`) > `roche.toml` > defaults and remember where they came from.
+
+use crate::build::{BuildCache, BuildKind};
+use crate::engine::{self, ContainerEngine};
+use crate::scan::Severity;
+use crate::secrets::{self, Secret};
+use anyhow::{bail, Context, Result};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::env;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// File name of the project manifest.
+pub const MANIFEST: &str = "roche.toml";
+
+/// Default port the service listens on, matching `ENV PORT 8080` in the Dockerfile templates.
+pub const DEFAULT_PORT: u16 = 8080;
+
+/// Severity from which vulnerabilities fail a scan unless `[scan] severity` says otherwise.
+pub const DEFAULT_SCAN_SEVERITY: Severity = Severity::High;
+
+/// The typed contents of a `roche.toml`.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Manifest {
+    pub images: Images,
+    pub tag: Tag,
+    pub build: Build,
+    pub cache: Cache,
+    pub scan: Scan,
+    pub env: Env,
+    pub ports: Ports,
+    /// Named deployment targets, e.g. `[deploy.production]`.
+    pub deploy: BTreeMap<String, DeployTarget>,
+    /// Crates added to the service `Cargo.toml`, written as in a `Cargo.toml`.
+    pub dependencies: BTreeMap<String, toml::Value>,
+    /// Handler modules in `functions/` and the prefixes they are mounted at, e.g.
+    /// `users = "/users"`.
+    pub functions: BTreeMap<String, String>,
+    /// Backing services started next to the function by `roche gen compose`, e.g.
+    /// `[services.mongodb]`.
+    pub services: BTreeMap<String, BackingService>,
+    /// Secrets the build reads, e.g. `[secrets.CARGO_REGISTRY_TOKEN]` with `env = "TOKEN"`.
+    pub secrets: BTreeMap<String, Secret>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Images {
+    pub dev_build: Option<String>,
+    pub test_build: Option<String>,
+    pub release_build: Option<String>,
+    pub runtime: Option<String>,
+}
+
+/// How generated image tags are named: `registry/[dev-|test-]name:version`.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Tag {
+    /// Used instead of the engine login, e.g. `quay.io/myorg`.
+    pub registry: Option<String>,
+    /// Used instead of the project folder name.
+    pub name: Option<String>,
+    pub version: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Build {
+    /// Container engine, one of `docker`, `podman` or `buildah`.
+    pub engine: Option<String>,
+    /// Passed to the engine as `--build-arg KEY=VALUE`.
+    pub args: BTreeMap<String, String>,
+    /// Platforms release images are built for, e.g. `["linux/amd64", "linux/arm64"]`.
+    pub platforms: Vec<String>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Env {
+    /// Env files, relative to the project, loaded when running the function locally.
+    pub files: Vec<PathBuf>,
+}
+
+/// How builds reuse earlier ones, see [`crate::build::BuildCache`].
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Cache {
+    /// Mounts BuildKit caches over the cargo registry and target dir of the build image.
+    pub mounts: Option<bool>,
+    /// `CARGO_HOME` of the build image, when it isn't `/usr/local/cargo`.
+    pub cargo_home: Option<String>,
+    /// Passed to the engine as `--cache-from`, e.g. `type=registry,ref=quay.io/myorg/hello:cache`.
+    pub from: Option<String>,
+    /// Passed to the engine as `--cache-to`.
+    pub to: Option<String>,
+}
+
+/// How release images are scanned for vulnerabilities, see [`crate::scan`].
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Scan {
+    /// Scanner to use, one of `trivy` or `grype`.
+    pub scanner: Option<String>,
+    /// Vulnerabilities at or above this severity fail the scan, e.g. `high`.
+    pub severity: Option<Severity>,
+    /// Vulnerability ids that never fail the scan, e.g. `CVE-2021-3711`.
+    pub ignore: Vec<String>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Ports {
+    /// Port the service listens on inside the container, handed to it as `PORT`.
+    pub container: Option<u16>,
+    /// Port published on the host when running locally.
+    pub host: Option<u16>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DeployTarget {
+    pub platform: Platform,
+    pub namespace: Option<String>,
+    /// Env file, relative to the project, whose values are set on the deployed service.
+    pub env_file: Option<PathBuf>,
+    /// CPU limit of the container, e.g. `"500m"`.
+    pub cpu: Option<String>,
+    /// Memory limit of the container, e.g. `"256Mi"`.
+    pub memory: Option<String>,
+    /// Requests a single instance handles at once. `0` leaves it unlimited.
+    pub concurrency: Option<u32>,
+    pub min_scale: Option<u32>,
+    pub max_scale: Option<u32>,
+    /// Pods of a kubernetes Deployment. Defaults to 1.
+    pub replicas: Option<u32>,
+    /// Host routed to the service by a kubernetes Ingress. No Ingress is generated without one.
+    pub host: Option<String>,
+    /// Path probed with `GET` to check the function is live and ready. Without one kubernetes only
+    /// checks the port accepts connections.
+    pub health_path: Option<String>,
+}
+
+impl DeployTarget {
+    /// A target on `platform` with nothing else configured.
+    pub fn new(platform: Platform) -> DeployTarget {
+        DeployTarget {
+            platform,
+            namespace: None,
+            env_file: None,
+            cpu: None,
+            memory: None,
+            concurrency: None,
+            min_scale: None,
+            max_scale: None,
+            replicas: None,
+            host: None,
+            health_path: None,
+        }
+    }
+}
+
+/// A container the function talks to, such as a database. `mongodb`, `postgres` and `redis`
+/// come with an image, port and healthcheck which the other keys override.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BackingService {
+    /// One of `mongodb`, `postgres` or `redis`. Defaults to the name of the table.
+    pub kind: Option<String>,
+    pub image: Option<String>,
+    /// Port the service listens on, handed to the function in `<NAME>_URL`.
+    pub port: Option<u16>,
+    /// Environment of the service's container.
+    pub env: BTreeMap<String, String>,
+    /// Command checking the service is up, e.g. `["redis-cli", "ping"]`.
+    pub healthcheck: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Platform {
+    Knative,
+    Kubernetes,
+}
+
+impl fmt::Display for Platform {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Platform::Knative => write!(f, "knative"),
+            Platform::Kubernetes => write!(f, "kubernetes"),
+        }
+    }
+}
+
+impl Manifest {
+    /// Finds `roche.toml` in `dir` or its `src` subfolder.
+    pub fn find(dir: &Path) -> Option<PathBuf> {
+        [dir.join(MANIFEST), dir.join("src").join(MANIFEST)]
+            .iter()
+            .find(|path| path.exists())
+            .cloned()
+    }
+
+    /// Reads and validates the manifest at `path`.
+    pub fn load(path: &Path) -> Result<Manifest> {
+        let source = fs::read_to_string(path)
+            .with_context(|| format!("Couldn't read {}", path.display()))?;
+        let manifest: Manifest =
+            toml::from_str(&source).with_context(|| format!("Invalid {}", path.display()))?;
+        let dir = path.parent().unwrap_or_else(|| Path::new("."));
+        manifest
+            .validate(dir)
+            .with_context(|| format!("Invalid {}", path.display()))?;
+        Ok(manifest)
+    }
+
+    /// Checks the values serde can't, resolving relative files against `dir`.
+    pub fn validate(&self, dir: &Path) -> Result<()> {
+        let images = [
+            ("images.dev_build", &self.images.dev_build),
+            ("images.test_build", &self.images.test_build),
+            ("images.release_build", &self.images.release_build),
+            ("images.runtime", &self.images.runtime),
+        ];
+        for (key, image) in images.iter() {
+            if let Some(image) = image {
+                check_image(key, image)?;
+            }
+        }
+        if let Some(engine) = &self.build.engine {
+            check_engine("build.engine", engine)?;
+        }
+        if let Some(registry) = &self.tag.registry {
+            check_registry("tag.registry", registry)?;
+        }
+        if let Some(name) = &self.tag.name {
+            check_tag_name("tag.name", name)?;
+        }
+        if let Some(version) = &self.tag.version {
+            check_tag_version("tag.version", version)?;
+        }
+        if let Some(scanner) = &self.scan.scanner {
+            check_scanner("scan.scanner", scanner)?;
+        }
+        for key in self.build.args.keys() {
+            if key.is_empty() || key.contains('=') {
+                bail!("build.args key '{}' is not a valid argument name", key);
+            }
+        }
+        for platform in &self.build.platforms {
+            if let Err(e) = crate::build::check_platform(platform) {
+                bail!("build.platforms {}", e);
+            }
+        }
+        for (key, port) in [
+            ("ports.container", self.ports.container),
+            ("ports.host", self.ports.host),
+        ]
+        .iter()
+        {
+            if let Some(port) = port {
+                check_port(key, *port)?;
+            }
+        }
+        for file in &self.env.files {
+            if !dir.join(file).exists() {
+                bail!("env.files entry {} does not exist", file.display());
+            }
+        }
+        for (name, secret) in &self.secrets {
+            if !secrets::is_name(name) {
+                bail!(
+                    "secrets.{} must be named like an environment variable, e.g. REGISTRY_TOKEN",
+                    name
+                );
+            }
+            match (&secret.env, &secret.file) {
+                (Some(_), None) => {}
+                (None, Some(file)) => {
+                    if !dir.join(file).exists() {
+                        bail!("secrets.{}.file {} does not exist", name, file.display());
+                    }
+                }
+                _ => bail!("secrets.{} needs exactly one of env or file", name),
+            }
+        }
+        for (name, prefix) in &self.functions {
+            crate::modules::check_prefix(prefix)
+                .with_context(|| format!("Invalid functions.{}", name))?;
+        }
+        for (name, value) in &self.dependencies {
+            crate::service::check_dependency(name, value)
+                .with_context(|| format!("Invalid dependencies.{}", name))?;
+        }
+        for (name, target) in &self.deploy {
+            if let Some(file) = &target.env_file {
+                if !dir.join(file).exists() {
+                    bail!("deploy.{}.env_file {} does not exist", name, file.display());
+                }
+            }
+            if let (Some(min), Some(max)) = (target.min_scale, target.max_scale) {
+                if min > max {
+                    bail!(
+                        "deploy.{}.min_scale {} is larger than max_scale {}",
+                        name,
+                        min,
+                        max
+                    );
+                }
+            }
+            if let Some(path) = &target.health_path {
+                if !path.starts_with('/') {
+                    bail!("deploy.{}.health_path {} must start with /", name, path);
+                }
+            }
+        }
+        for (name, service) in &self.services {
+            if !crate::compose::is_service_name(name) {
+                bail!(
+                    "services.{} must be a lowercase name of letters, digits and dashes",
+                    name
+                );
+            }
+            let kind = service.kind.as_deref().unwrap_or(name);
+            let preset = crate::compose::preset(kind);
+            if service.kind.is_some() && preset.is_none() {
+                bail!(
+                    "services.{}.kind '{}' is not one of mongodb, postgres or redis",
+                    name,
+                    kind
+                );
+            }
+            if preset.is_none() && service.image.is_none() {
+                bail!(
+                    "services.{} needs an image, roche only knows mongodb, postgres and redis",
+                    name
+                );
+            }
+            if service.port == Some(0) {
+                bail!("services.{}.port must be between 1 and 65535", name);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Where a resolved value came from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Source {
+    Cli,
+    /// Environment variable, possibly set by `.rocherc`.
+    Env(String),
+    Manifest(PathBuf),
+    Default,
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Source::Cli => write!(f, "command line"),
+            Source::Env(key) => write!(f, "environment variable {}", key),
+            Source::Manifest(path) => write!(f, "{}", path.display()),
+            Source::Default => write!(f, "default"),
+        }
+    }
+}
+
+/// A resolved value and its source.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Setting<T> {
+    pub value: T,
+    pub source: Source,
+}
+
+impl Setting<String> {
+    /// Replaces the value when it was given on the command line.
+    pub fn or_cli(&self, cli: Option<&str>) -> Setting<String> {
+        match cli {
+            Some(value) => Setting {
+                value: value.to_string(),
+                source: Source::Cli,
+            },
+            None => self.clone(),
+        }
+    }
+}
+
+/// Values given on the command line, which take precedence over every other source.
+#[derive(Debug, Clone, Default)]
+pub struct Cli<'a> {
+    pub engine: Option<&'a str>,
+    /// Build image of release builds, as `release --buildimage` takes it.
+    pub release_build_image: Option<&'a str>,
+    pub runtime_image: Option<&'a str>,
+    pub registry: Option<&'a str>,
+    pub scanner: Option<&'a str>,
+}
+
+/// The resolved project configuration.
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// Location of the `roche.toml` in use, if any.
+    pub manifest_path: Option<PathBuf>,
+    pub dev_build_image: Setting<String>,
+    pub test_build_image: Setting<String>,
+    pub release_build_image: Setting<String>,
+    pub runtime_image: Setting<String>,
+    pub engine: Option<Setting<String>>,
+    pub tag_registry: Option<Setting<String>>,
+    pub tag_name: Option<Setting<String>>,
+    pub tag_version: Option<Setting<String>>,
+    pub container_port: Setting<u16>,
+    pub host_port: Setting<u16>,
+    pub build_args: BTreeMap<String, String>,
+    /// Platforms release images are built for, the engine's own when empty.
+    pub platforms: Vec<String>,
+    pub cache_mounts: Setting<bool>,
+    pub cargo_home: Option<Setting<String>>,
+    pub cache_from: Option<Setting<String>>,
+    pub cache_to: Option<Setting<String>>,
+    pub scanner: Option<Setting<String>>,
+    pub scan_severity: Setting<Severity>,
+    pub scan_ignore: Vec<String>,
+    pub env_files: Setting<Vec<PathBuf>>,
+    pub deploy: BTreeMap<String, DeployTarget>,
+    pub dependencies: BTreeMap<String, toml::Value>,
+    pub functions: BTreeMap<String, String>,
+    pub services: BTreeMap<String, BackingService>,
+    /// Build secrets, their files resolved against the manifest folder.
+    pub secrets: BTreeMap<String, Secret>,
+}
+
+impl Config {
+    /// Loads `.rocherc` and `roche.toml` from the project in `dir` and resolves every value.
+    pub fn load(dir: &Path) -> Result<Config> {
+        crate::load_rocherc(dir)?;
+        match Manifest::find(dir) {
+            Some(path) => {
+                let manifest = Manifest::load(&path)?;
+                Config::resolve(&manifest, Some(path))
+            }
+            None => Config::resolve(&Manifest::default(), None),
+        }
+    }
+
+    /// Resolves `manifest` against the environment and the defaults.
+    pub fn resolve(manifest: &Manifest, manifest_path: Option<PathBuf>) -> Result<Config> {
+        let origin = Source::Manifest(
+            manifest_path
+                .clone()
+                .unwrap_or_else(|| PathBuf::from(MANIFEST)),
+        );
+        // Manifest paths are relative to the manifest, the default to the function folder.
+        let manifest_dir = manifest_path
+            .as_ref()
+            .and_then(|path| path.parent())
+            .map(Path::to_path_buf)
+            .unwrap_or_default();
+        let env_files = if manifest.env.files.is_empty() {
+            Setting {
+                value: vec![PathBuf::from(".env")],
+                source: Source::Default,
+            }
+        } else {
+            Setting {
+                value: manifest
+                    .env
+                    .files
+                    .iter()
+                    .map(|file| manifest_dir.join(file))
+                    .collect(),
+                source: origin.clone(),
+            }
+        };
+        let config = Config {
+            dev_build_image: or_default(
+                resolve("dev_build_image", &manifest.images.dev_build, &origin)?,
+                crate::DEV_BUILD_IMAGE.to_string(),
+            ),
+            test_build_image: or_default(
+                resolve("test_build_image", &manifest.images.test_build, &origin)?,
+                crate::TEST_BUILD_IMAGE.to_string(),
+            ),
+            release_build_image: or_default(
+                resolve(
+                    "release_build_image",
+                    &manifest.images.release_build,
+                    &origin,
+                )?,
+                crate::RELEASE_BUILD_IMAGE.to_string(),
+            ),
+            runtime_image: or_default(
+                resolve("runtime_image", &manifest.images.runtime, &origin)?,
+                crate::RUNTIME_IMAGE.to_string(),
+            ),
+            engine: resolve("engine", &manifest.build.engine, &origin)?,
+            tag_registry: resolve("tag_registry", &manifest.tag.registry, &origin)?,
+            tag_name: resolve("tag_name", &manifest.tag.name, &origin)?,
+            tag_version: resolve("tag_version", &manifest.tag.version, &origin)?,
+            container_port: or_default(
+                resolve("container_port", &manifest.ports.container, &origin)?,
+                DEFAULT_PORT,
+            ),
+            host_port: or_default(
+                resolve("host_port", &manifest.ports.host, &origin)?,
+                DEFAULT_PORT,
+            ),
+            build_args: manifest.build.args.clone(),
+            platforms: manifest.build.platforms.clone(),
+            cache_mounts: or_default(
+                resolve("cache_mounts", &manifest.cache.mounts, &origin)?,
+                false,
+            ),
+            cargo_home: resolve("cargo_home", &manifest.cache.cargo_home, &origin)?,
+            cache_from: resolve("cache_from", &manifest.cache.from, &origin)?,
+            cache_to: resolve("cache_to", &manifest.cache.to, &origin)?,
+            scanner: resolve("scanner", &manifest.scan.scanner, &origin)?,
+            scan_severity: or_default(
+                resolve("scan_severity", &manifest.scan.severity, &origin)?,
+                DEFAULT_SCAN_SEVERITY,
+            ),
+            scan_ignore: manifest.scan.ignore.clone(),
+            env_files,
+            deploy: manifest
+                .deploy
+                .iter()
+                .map(|(name, target)| {
+                    let mut target = target.clone();
+                    target.env_file = target.env_file.map(|file| manifest_dir.join(file));
+                    (name.clone(), target)
+                })
+                .collect(),
+            dependencies: manifest.dependencies.clone(),
+            functions: manifest.functions.clone(),
+            services: manifest.services.clone(),
+            secrets: manifest
+                .secrets
+                .iter()
+                .map(|(name, secret)| {
+                    let mut secret = secret.clone();
+                    secret.file = secret.file.map(|file| manifest_dir.join(file));
+                    (name.clone(), secret)
+                })
+                .collect(),
+            manifest_path,
+        };
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Checks the resolved values. Those from `roche.toml` have passed [`Manifest::validate`]
+    /// already, but the environment, `.rocherc` and the command line can override them.
+    pub fn validate(&self) -> Result<()> {
+        fn check<T>(setting: Option<&Setting<T>>, check: impl Fn(&T) -> Result<()>) -> Result<()> {
+            match setting {
+                Some(setting) => check(&setting.value)
+                    .with_context(|| format!("Invalid value from {}", setting.source)),
+                None => Ok(()),
+            }
+        }
+        for (key, image) in [
+            ("dev_build_image", &self.dev_build_image),
+            ("test_build_image", &self.test_build_image),
+            ("release_build_image", &self.release_build_image),
+            ("runtime_image", &self.runtime_image),
+        ]
+        .iter()
+        {
+            check(Some(image), |image| check_image(key, image))?;
+        }
+        check(self.engine.as_ref(), |engine| {
+            check_engine("engine", engine)
+        })?;
+        check(self.scanner.as_ref(), |scanner| {
+            check_scanner("scanner", scanner)
+        })?;
+        check(self.tag_registry.as_ref(), |registry| {
+            check_registry("tag_registry", registry)
+        })?;
+        check(self.tag_name.as_ref(), |name| {
+            check_tag_name("tag_name", name)
+        })?;
+        check(self.tag_version.as_ref(), |version| {
+            check_tag_version("tag_version", version)
+        })?;
+        check(Some(&self.container_port), |port| {
+            check_port("container_port", *port)
+        })?;
+        check(Some(&self.host_port), |port| check_port("host_port", *port))
+    }
+
+    /// Applies the values given on the command line, checking the engine and scanner names.
+    pub fn with_cli(mut self, cli: &Cli<'_>) -> Result<Config> {
+        fn setting(value: &str) -> Setting<String> {
+            Setting {
+                value: value.to_string(),
+                source: Source::Cli,
+            }
+        }
+        if let Some(engine) = cli.engine {
+            self.engine = Some(setting(engine));
+        }
+        if let Some(scanner) = cli.scanner {
+            self.scanner = Some(setting(scanner));
+        }
+        self.release_build_image = self.release_build_image.or_cli(cli.release_build_image);
+        self.runtime_image = self.runtime_image.or_cli(cli.runtime_image);
+        if let Some(registry) = cli.registry {
+            self.tag_registry = Some(setting(registry));
+        }
+        self.validate()?;
+        Ok(self)
+    }
+
+    /// The build image for `kind` with an optional command line override.
+    pub fn build_image(&self, kind: BuildKind, cli: Option<&str>) -> Setting<String> {
+        match kind {
+            BuildKind::Dev => self.dev_build_image.or_cli(cli),
+            BuildKind::Test => self.test_build_image.or_cli(cli),
+            BuildKind::Release => self.release_build_image.or_cli(cli),
+        }
+    }
+
+    /// The caches builds use.
+    pub fn build_cache(&self) -> BuildCache {
+        BuildCache {
+            mounts: self.cache_mounts.value,
+            cargo_home: self.cargo_home.as_ref().map(|s| s.value.clone()),
+            from: self.cache_from.as_ref().map(|s| s.value.clone()),
+            to: self.cache_to.as_ref().map(|s| s.value.clone()),
+        }
+    }
+
+    /// Selects the container engine, preferring the `--engine` flag over the configured one.
+    pub fn engine(&self, cli: Option<&str>) -> Result<Box<dyn ContainerEngine>> {
+        engine::select(cli.or_else(|| self.engine.as_ref().map(|e| e.value.as_str())))
+    }
+
+    /// The vulnerability scanner from the `--scanner` flag, `[scan] scanner` or the PATH.
+    pub fn scanner(&self, cli: Option<&str>) -> Result<Box<dyn crate::scan::Scanner>> {
+        crate::scan::select(cli.or_else(|| self.scanner.as_ref().map(|s| s.value.as_str())))
+    }
+
+    /// Generates the image tag for a `kind` build of the project in `dir`.
+    ///
+    /// The configured registry replaces the engine login and the configured name replaces
+    /// the folder name.
+    pub fn image_tag(
+        &self,
+        kind: BuildKind,
+        dir: &Path,
+        engine: &dyn ContainerEngine,
+    ) -> Option<String> {
+        let name = match &self.tag_name {
+            Some(name) => name.value.clone(),
+            None => crate::project_name(dir)?,
+        };
+        let registry = match &self.tag_registry {
+            Some(registry) => Some(registry.value.clone()),
+            None => engine.login(),
+        };
+        let mut img = match registry {
+            Some(r) => format!("{}/{}{}", r, kind.tag_prefix(), name),
+            None => format!("{}{}", kind.tag_prefix(), name),
+        };
+        if let Some(version) = &self.tag_version {
+            img = format!("{}:{}", img, version.value);
+        }
+        Some(img)
+    }
+}
+
+impl fmt::Display for Config {
+    /// Prints the configuration as `key = value  # source` lines.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fn line<T: fmt::Debug>(
+            f: &mut fmt::Formatter<'_>,
+            key: &str,
+            setting: Option<&Setting<T>>,
+        ) -> fmt::Result {
+            match setting {
+                Some(s) => writeln!(f, "{:<22} = {:?}  # {}", key, s.value, s.source),
+                None => writeln!(f, "{:<22}   (not set)", key),
+            }
+        }
+        let origin = match &self.manifest_path {
+            Some(path) => path.display().to_string(),
+            None => "default".to_string(),
+        };
+        line(f, "images.dev_build", Some(&self.dev_build_image))?;
+        line(f, "images.test_build", Some(&self.test_build_image))?;
+        line(f, "images.release_build", Some(&self.release_build_image))?;
+        line(f, "images.runtime", Some(&self.runtime_image))?;
+        line(f, "build.engine", self.engine.as_ref())?;
+        for (key, value) in &self.build_args {
+            writeln!(
+                f,
+                "{:<22} = {:?}  # {}",
+                format!("build.args.{}", key),
+                value,
+                origin
+            )?;
+        }
+        if !self.platforms.is_empty() {
+            writeln!(
+                f,
+                "{:<22} = {:?}  # {}",
+                "build.platforms", self.platforms, origin
+            )?;
+        }
+        line(f, "cache.mounts", Some(&self.cache_mounts))?;
+        line(f, "cache.cargo_home", self.cargo_home.as_ref())?;
+        line(f, "cache.from", self.cache_from.as_ref())?;
+        line(f, "cache.to", self.cache_to.as_ref())?;
+        line(f, "scan.scanner", self.scanner.as_ref())?;
+        line(f, "scan.severity", Some(&self.scan_severity))?;
+        if !self.scan_ignore.is_empty() {
+            writeln!(
+                f,
+                "{:<22} = {:?}  # {}",
+                "scan.ignore", self.scan_ignore, origin
+            )?;
+        }
+        line(f, "tag.registry", self.tag_registry.as_ref())?;
+        line(f, "tag.name", self.tag_name.as_ref())?;
+        line(f, "tag.version", self.tag_version.as_ref())?;
+        line(f, "ports.container", Some(&self.container_port))?;
+        line(f, "ports.host", Some(&self.host_port))?;
+        line(f, "env.files", Some(&self.env_files))?;
+        for (name, target) in &self.deploy {
+            writeln!(
+                f,
+                "{:<22} = {}  # {}",
+                format!("deploy.{}.platform", name),
+                target.platform,
+                origin
+            )?;
+        }
+        for (name, prefix) in &self.functions {
+            writeln!(
+                f,
+                "{:<22} = {:?}  # {}",
+                format!("functions.{}", name),
+                prefix,
+                origin
+            )?;
+        }
+        for (name, service) in &self.services {
+            let preset = crate::compose::preset(service.kind.as_deref().unwrap_or(name));
+            writeln!(
+                f,
+                "{:<22} = {:?}  # {}",
+                format!("services.{}.image", name),
+                service
+                    .image
+                    .as_deref()
+                    .or_else(|| preset.map(|p| p.image))
+                    .unwrap_or_default(),
+                origin
+            )?;
+        }
+        for (name, value) in &self.dependencies {
+            writeln!(
+                f,
+                "{:<22} = {}  # {}",
+                format!("dependencies.{}", name),
+                inline(value),
+                origin
+            )?;
+        }
+        // Only where a secret comes from, never its value.
+        for (name, secret) in &self.secrets {
+            let from = match (&secret.env, &secret.file) {
+                (Some(var), _) => format!("env {}", var),
+                (None, Some(file)) => format!("file {}", file.display()),
+                (None, None) => String::new(),
+            };
+            writeln!(
+                f,
+                "{:<22} = {:?}  # {}",
+                format!("secrets.{}", name),
+                from,
+                origin
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Formats `value` as it would be written on one line of a `Cargo.toml`.
+fn inline(value: &toml::Value) -> String {
+    match value {
+        toml::Value::Table(table) => {
+            let entries: Vec<String> = table
+                .iter()
+                .map(|(key, value)| format!("{} = {}", key, inline(value)))
+                .collect();
+            format!("{{ {} }}", entries.join(", "))
+        }
+        toml::Value::Array(values) => {
+            let values: Vec<String> = values.iter().map(inline).collect();
+            format!("[{}]", values.join(", "))
+        }
+        other => other.to_string(),
+    }
+}
+
+fn check_image(key: &str, image: &str) -> Result<()> {
+    if image.is_empty() || image.contains(char::is_whitespace) {
+        bail!("{} '{}' is not a valid image name", key, image);
+    }
+    Ok(())
+}
+
+fn check_engine(key: &str, engine: &str) -> Result<()> {
+    if !engine::ENGINES.contains(&engine) {
+        bail!(
+            "{} '{}' is not supported, use one of {}",
+            key,
+            engine,
+            engine::ENGINES.join(", ")
+        );
+    }
+    Ok(())
+}
+
+fn check_scanner(key: &str, scanner: &str) -> Result<()> {
+    if !crate::scan::SCANNERS.contains(&scanner) {
+        bail!(
+            "{} '{}' is not supported, use one of {}",
+            key,
+            scanner,
+            crate::scan::SCANNERS.join(", ")
+        );
+    }
+    Ok(())
+}
+
+fn check_registry(key: &str, registry: &str) -> Result<()> {
+    if registry.is_empty() || registry.ends_with('/') {
+        bail!(
+            "{} '{}' should look like 'quay.io/namespace' without a trailing '/'",
+            key,
+            registry
+        );
+    }
+    Ok(())
+}
+
+fn check_tag_name(key: &str, name: &str) -> Result<()> {
+    if name.is_empty() || name.chars().any(|c| c.is_ascii_uppercase() || c == ':') {
+        bail!(
+            "{} '{}' must be a lowercase image name without a version",
+            key,
+            name
+        );
+    }
+    Ok(())
+}
+
+fn check_tag_version(key: &str, version: &str) -> Result<()> {
+    if version.is_empty() || version.contains([':', '/']) {
+        bail!("{} '{}' is not a valid tag", key, version);
+    }
+    Ok(())
+}
+
+fn check_port(key: &str, port: u16) -> Result<()> {
+    if port == 0 {
+        bail!("{} must be between 1 and 65535", key);
+    }
+    Ok(())
+}
+
+/// Resolves `key` from the environment first and then the manifest.
+fn resolve<T>(key: &str, manifest: &Option<T>, origin: &Source) -> Result<Option<Setting<T>>>
+where
+    T: FromStr + Clone,
+    T::Err: fmt::Display,
+{
+    if let Ok(value) = env::var(key) {
+        let value = value.parse::<T>().map_err(|e| {
+            anyhow::anyhow!(
+                "Invalid value '{}' for environment variable {}: {}",
+                value,
+                key,
+                e
+            )
+        })?;
+        return Ok(Some(Setting {
+            value,
+            source: Source::Env(key.to_string()),
+        }));
+    }
+    Ok(manifest.clone().map(|value| Setting {
+        value,
+        source: origin.clone(),
+    }))
+}
+
+fn or_default<T>(setting: Option<Setting<T>>, default: T) -> Setting<T> {
+    setting.unwrap_or(Setting {
+        value: default,
+        source: Source::Default,
+    })
+}
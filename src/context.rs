@@ -0,0 +1,287 @@
+//! The build context handed to the container engine.
+//!
+//! Rather than the function folder itself, with its `target/`, `.git` and whatever else sits
+//! next to `functions.rs`, the engine is given a fresh folder holding only the files the
+//! Dockerfile uses: the handler, `lib.rs`, the ejected service and the modules they declare
+//! with `mod`, so `.env` stays out. A user template in [`crate::build::TEMPLATE_DIR`] may copy
+//! anything, so it gets the whole function folder instead, less `.git`, `target` and `.roche`.
+//! That can include `.env`, which is why release builds check the context for secrets, see
+//! [`crate::secrets::check_image_files`]. Either way paths listed in `.rocheignore` are left out.
+
+use crate::modules;
+use anyhow::{Context, Result};
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use std::collections::{BTreeMap, BTreeSet};
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// File next to `functions.rs` listing paths to keep out of the build context, written like a
+/// `.gitignore`.
+pub const IGNORE_FILE: &str = ".rocheignore";
+
+/// Folders never sent with a user template.
+const SKIPPED_DIRS: [&str; 3] = [".git", "target", ".roche"];
+
+/// Files whose modules live next to them rather than in a folder named after them: the crate
+/// roots of the service and `mod.rs`.
+const MODULE_ROOTS: [&str; 3] = ["lib.rs", "main.rs", "mod.rs"];
+
+static CONTEXTS: AtomicUsize = AtomicUsize::new(0);
+
+/// The files of the function folder `dir` that go into the build context, relative to it.
+///
+/// `full` sends every file, for user templates, otherwise only the sources and files the
+/// built in templates copy. Modules of the functions in `functions/` are found through
+/// `prefixes`, see [`modules::modules`].
+pub fn files(dir: &Path, prefixes: &BTreeMap<String, String>, full: bool) -> Result<Vec<PathBuf>> {
+    let ignored = ignored(dir)?;
+    let mut files = BTreeSet::new();
+    if full {
+        walk(dir, Path::new(""), &ignored, &mut files)?;
+        return Ok(files.into_iter().collect());
+    }
+
+    files.extend(sources(dir, prefixes, &ignored)?);
+    let run = Path::new("run.sh");
+    if dir.join(run).is_file() && !is_ignored(&ignored, run, false) {
+        files.insert(run.to_path_buf());
+    }
+    Ok(files.into_iter().collect())
+}
+
+/// The Rust sources of the function folder `dir` relative to it: the handler or the modules in
+/// `functions/`, `lib.rs` and `main.rs` and every module they declare with `mod`, less the
+/// paths in `.rocheignore`.
+pub fn source_files(dir: &Path, prefixes: &BTreeMap<String, String>) -> Result<Vec<PathBuf>> {
+    Ok(sources(dir, prefixes, &ignored(dir)?)?
+        .into_iter()
+        .collect())
+}
+
+fn sources(
+    dir: &Path,
+    prefixes: &BTreeMap<String, String>,
+    ignored: &Gitignore,
+) -> Result<BTreeSet<PathBuf>> {
+    let mut files = BTreeSet::new();
+    let mut sources = match modules::modules(dir, prefixes)? {
+        Some(modules) => modules
+            .iter()
+            .map(|module| Path::new(modules::FUNCTIONS_DIR).join(format!("{}.rs", module.name)))
+            .collect(),
+        None => vec![PathBuf::from("functions.rs")],
+    };
+    sources.extend(
+        ["lib.rs", "main.rs"]
+            .iter()
+            .map(PathBuf::from)
+            .filter(|file| dir.join(file).is_file()),
+    );
+    while let Some(source) = sources.pop() {
+        // A missing module is left for the compiler to report.
+        if !dir.join(&source).is_file()
+            || is_ignored(ignored, &source, false)
+            || !files.insert(source.clone())
+        {
+            continue;
+        }
+        sources.extend(declared_modules(dir, &source));
+    }
+    Ok(files)
+}
+
+/// A folder holding a copy of the build context, removed when dropped.
+#[derive(Debug)]
+pub struct BuildContext {
+    pub dir: PathBuf,
+    /// The files in the context, relative to `dir`.
+    pub files: Vec<PathBuf>,
+    /// Total size of the files in bytes.
+    pub size: u64,
+}
+
+impl BuildContext {
+    /// Copies `files` of the function folder `source` to a new folder in the temp dir.
+    pub fn create(source: &Path, files: Vec<PathBuf>) -> Result<BuildContext> {
+        let dir = env::temp_dir().join(format!(
+            "roche-context-{}-{}",
+            std::process::id(),
+            CONTEXTS.fetch_add(1, Ordering::SeqCst)
+        ));
+        if dir.exists() {
+            fs::remove_dir_all(&dir)
+                .with_context(|| format!("Couldn't clear {}", dir.display()))?;
+        }
+        // Created first so a failed copy removes the folder again.
+        let mut context = BuildContext {
+            dir,
+            files,
+            size: 0,
+        };
+        fs::create_dir_all(&context.dir)
+            .with_context(|| format!("Couldn't create {}", context.dir.display()))?;
+        let mut size = 0;
+        for file in &context.files {
+            let target = context.dir.join(file);
+            if let Some(parent) = target.parent() {
+                fs::create_dir_all(parent)
+                    .with_context(|| format!("Couldn't create {}", parent.display()))?;
+            }
+            size += fs::copy(source.join(file), &target).with_context(|| {
+                format!("Couldn't copy {} to the build context", file.display())
+            })?;
+        }
+        context.size = size;
+        Ok(context)
+    }
+
+    /// The size of the context for people, e.g. `3.2 KiB`.
+    pub fn human_size(&self) -> String {
+        let units = ["B", "KiB", "MiB", "GiB"];
+        let mut size = self.size as f64;
+        let mut unit = 0;
+        while size >= 1024.0 && unit < units.len() - 1 {
+            size /= 1024.0;
+            unit += 1;
+        }
+        if unit == 0 {
+            format!("{} B", self.size)
+        } else {
+            format!("{:.1} {}", size, units[unit])
+        }
+    }
+}
+
+impl Drop for BuildContext {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
+
+/// The patterns of `.rocheignore` in `dir`, matching nothing when there is none.
+fn ignored(dir: &Path) -> Result<Gitignore> {
+    let mut builder = GitignoreBuilder::new(dir);
+    let file = dir.join(IGNORE_FILE);
+    if file.exists() {
+        if let Some(e) = builder.add(&file) {
+            return Err(e).with_context(|| format!("Couldn't read {}", file.display()));
+        }
+    }
+    builder
+        .build()
+        .with_context(|| format!("Invalid pattern in {}", file.display()))
+}
+
+fn is_ignored(ignored: &Gitignore, path: &Path, is_dir: bool) -> bool {
+    ignored
+        .matched_path_or_any_parents(path, is_dir)
+        .is_ignore()
+}
+
+/// Adds the files below `dir/relative` that aren't ignored to `files`.
+fn walk(
+    dir: &Path,
+    relative: &Path,
+    ignored: &Gitignore,
+    files: &mut BTreeSet<PathBuf>,
+) -> Result<()> {
+    let folder = dir.join(relative);
+    let entries =
+        fs::read_dir(&folder).with_context(|| format!("Couldn't read {}", folder.display()))?;
+    for entry in entries {
+        let entry = entry.with_context(|| format!("Couldn't read {}", folder.display()))?;
+        let path = relative.join(entry.file_name());
+        let is_dir = entry.path().is_dir();
+        if is_ignored(ignored, &path, is_dir) {
+            continue;
+        }
+        if is_dir {
+            let skipped = relative.as_os_str().is_empty()
+                && SKIPPED_DIRS
+                    .iter()
+                    .any(|skipped| entry.file_name() == *skipped);
+            if !skipped {
+                walk(dir, &path, ignored, files)?;
+            }
+        } else {
+            files.insert(path);
+        }
+    }
+    Ok(())
+}
+
+/// The files of the modules `source` declares with `mod name;`, relative to the function folder
+/// `dir`. A file that doesn't parse declares nothing, the compiler reports it in the build.
+fn declared_modules(dir: &Path, source: &Path) -> Vec<PathBuf> {
+    let syntax = match fs::read_to_string(dir.join(source))
+        .ok()
+        .and_then(|code| syn::parse_file(&code).ok())
+    {
+        Some(syntax) => syntax,
+        None => return Vec::new(),
+    };
+    let parent = source.parent().unwrap_or_else(|| Path::new(""));
+    let is_root = source
+        .file_name()
+        .is_some_and(|name| MODULE_ROOTS.iter().any(|root| name == *root));
+    // `functions.rs` keeps its modules in `functions/`, `lib.rs` and `mod.rs` next to them.
+    let children = if is_root {
+        parent.to_path_buf()
+    } else {
+        source.with_extension("")
+    };
+    let mut found = Vec::new();
+    module_files(dir, parent, &children, &syntax.items, &mut found);
+    found
+}
+
+/// Adds the files of the modules declared in `items` to `found`. Their files are in `children`,
+/// or relative to `parent` for a `#[path]` module.
+fn module_files(
+    dir: &Path,
+    parent: &Path,
+    children: &Path,
+    items: &[syn::Item],
+    found: &mut Vec<PathBuf>,
+) {
+    for item in items {
+        let module = match item {
+            syn::Item::Mod(module) => module,
+            _ => continue,
+        };
+        let name = module.ident.to_string();
+        match &module.content {
+            Some((_, items)) => {
+                module_files(dir, parent, &children.join(&name), items, found);
+            }
+            None => {
+                if let Some(path) = path_attribute(module) {
+                    found.push(parent.join(path));
+                    continue;
+                }
+                let file = children.join(format!("{}.rs", name));
+                if dir.join(&file).is_file() {
+                    found.push(file);
+                } else {
+                    found.push(children.join(&name).join("mod.rs"));
+                }
+            }
+        }
+    }
+}
+
+/// The file of a `#[path = "..."]` module.
+fn path_attribute(module: &syn::ItemMod) -> Option<String> {
+    module
+        .attrs
+        .iter()
+        .find_map(|attr| match attr.parse_meta() {
+            Ok(syn::Meta::NameValue(meta)) if meta.path.is_ident("path") => match meta.lit {
+                syn::Lit::Str(path) => Some(path.value()),
+                _ => None,
+            },
+            _ => None,
+        })
+}
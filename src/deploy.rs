@@ -0,0 +1,584 @@
+//! Deploying the release image to a container platform.
+//!
+//! roche renders the platform's manifest from the resolved configuration, so it can be committed
+//! with `roche gen knative` or applied straight away with `roche deploy knative`. Clusters without
+//! knative get a Deployment, Service and Ingress from `roche gen k8s`.
+
+use crate::config::{DeployTarget, Platform};
+use crate::engine;
+use anyhow::{bail, Context, Result};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+/// File `roche gen knative` writes the Knative Service to.
+pub const KNATIVE_MANIFEST: &str = "service.yaml";
+
+/// Folder `roche gen k8s` writes the kubernetes manifests and their `kustomization.yaml` to.
+pub const KUBERNETES_DIR: &str = "k8s";
+
+/// uid and gid of `rocheuser`, which the runtime Dockerfile templates run the function as.
+pub const RUNTIME_UID: u32 = 10001;
+
+/// Name of the container port in the kubernetes manifests.
+const PORT_NAME: &str = "http";
+
+/// The release image and the target it is deployed to.
+#[derive(Debug, Clone)]
+pub struct DeployRequest {
+    /// Name of the service, see [`service_name`].
+    pub name: String,
+    pub image: String,
+    pub container_port: u16,
+    pub target: DeployTarget,
+}
+
+impl DeployRequest {
+    /// The variables of the target's env file, in file order.
+    pub fn env(&self) -> Result<Vec<(String, String)>> {
+        let file = match &self.target.env_file {
+            Some(file) => file,
+            None => return Ok(Vec::new()),
+        };
+        // Read the file without loading it into roche's own environment.
+        #[allow(deprecated)]
+        let lines = dotenv::from_path_iter(file)
+            .with_context(|| format!("Couldn't read {}", file.display()))?;
+        let mut vars = Vec::new();
+        for line in lines {
+            vars.push(line.with_context(|| format!("Couldn't parse {}", file.display()))?);
+        }
+        Ok(vars)
+    }
+
+    /// Renders the Knative Service running the image.
+    pub fn knative(&self) -> Result<String> {
+        let mut annotations = BTreeMap::new();
+        if let Some(min) = self.target.min_scale {
+            annotations.insert("autoscaling.knative.dev/minScale", min.to_string());
+        }
+        if let Some(max) = self.target.max_scale {
+            annotations.insert("autoscaling.knative.dev/maxScale", max.to_string());
+        }
+        let service = KnativeService {
+            api_version: "serving.knative.dev/v1",
+            kind: "Service",
+            metadata: self.metadata(),
+            spec: KnativeSpec {
+                template: RevisionTemplate {
+                    metadata: if annotations.is_empty() {
+                        None
+                    } else {
+                        Some(TemplateMetadata { annotations })
+                    },
+                    spec: RevisionSpec {
+                        container_concurrency: self.target.concurrency,
+                        containers: vec![self.container()?],
+                    },
+                },
+            },
+        };
+        serde_yaml::to_string(&service).context("Couldn't serialize the Knative Service")
+    }
+
+    /// Renders the kubernetes Deployment, Service, the Ingress when the target has a `host` and a
+    /// `kustomization.yaml` listing them, as file names and contents.
+    pub fn kubernetes(&self) -> Result<Vec<(&'static str, String)>> {
+        let mut labels = BTreeMap::new();
+        labels.insert("app.kubernetes.io/name", self.name.clone());
+        let probe = match &self.target.health_path {
+            Some(path) => Probe {
+                http_get: Some(HttpGetAction {
+                    path: path.clone(),
+                    port: PORT_NAME,
+                }),
+                tcp_socket: None,
+            },
+            None => Probe {
+                http_get: None,
+                tcp_socket: Some(TcpSocketAction { port: PORT_NAME }),
+            },
+        };
+        let mut container = self.container()?;
+        container.name = Some(self.name.clone());
+        container.ports[0].name = Some(PORT_NAME);
+        container.liveness_probe = Some(probe.clone());
+        container.readiness_probe = Some(probe);
+        container.security_context = Some(ContainerSecurityContext {
+            allow_privilege_escalation: false,
+        });
+
+        let deployment = Deployment {
+            api_version: "apps/v1",
+            kind: "Deployment",
+            metadata: self.labelled_metadata(&labels),
+            spec: DeploymentSpec {
+                replicas: self.target.replicas.unwrap_or(1),
+                selector: LabelSelector {
+                    match_labels: labels.clone(),
+                },
+                template: PodTemplate {
+                    metadata: PodMetadata {
+                        labels: labels.clone(),
+                    },
+                    spec: PodSpec {
+                        security_context: PodSecurityContext {
+                            run_as_non_root: true,
+                            run_as_user: RUNTIME_UID,
+                            run_as_group: RUNTIME_UID,
+                        },
+                        containers: vec![container],
+                    },
+                },
+            },
+        };
+        let service = KubernetesService {
+            api_version: "v1",
+            kind: "Service",
+            metadata: self.labelled_metadata(&labels),
+            spec: ServiceSpec {
+                selector: labels.clone(),
+                ports: vec![ServicePort {
+                    name: PORT_NAME,
+                    port: 80,
+                    target_port: PORT_NAME,
+                }],
+            },
+        };
+
+        let mut files = vec![
+            (
+                "deployment.yaml",
+                serde_yaml::to_string(&deployment).context("Couldn't serialize the Deployment")?,
+            ),
+            (
+                "service.yaml",
+                serde_yaml::to_string(&service).context("Couldn't serialize the Service")?,
+            ),
+        ];
+        if let Some(host) = &self.target.host {
+            let ingress = Ingress {
+                api_version: "networking.k8s.io/v1",
+                kind: "Ingress",
+                metadata: self.labelled_metadata(&labels),
+                spec: IngressSpec {
+                    rules: vec![IngressRule {
+                        host: host.clone(),
+                        http: HttpIngressRule {
+                            paths: vec![IngressPath {
+                                path: "/",
+                                path_type: "Prefix",
+                                backend: IngressBackend {
+                                    service: ServiceBackend {
+                                        name: self.name.clone(),
+                                        port: ServiceBackendPort { name: PORT_NAME },
+                                    },
+                                },
+                            }],
+                        },
+                    }],
+                },
+            };
+            files.push((
+                "ingress.yaml",
+                serde_yaml::to_string(&ingress).context("Couldn't serialize the Ingress")?,
+            ));
+        }
+        let kustomization = Kustomization {
+            api_version: "kustomize.config.k8s.io/v1beta1",
+            kind: "Kustomization",
+            resources: files.iter().map(|(file, _)| *file).collect(),
+        };
+        files.push((
+            "kustomization.yaml",
+            serde_yaml::to_string(&kustomization)
+                .context("Couldn't serialize the kustomization")?,
+        ));
+        Ok(files)
+    }
+
+    /// Applies the Knative Service with kubectl, or with kn when kubectl isn't installed.
+    pub fn deploy_knative(&self) -> Result<()> {
+        let manifest = self.knative()?;
+        let (binary, status) = if engine::on_path("kubectl") {
+            let mut kubectl = Command::new("kubectl")
+                .args(["apply", "-f", "-"])
+                .stdin(Stdio::piped())
+                .spawn()
+                .context("Couldn't run kubectl")?;
+            // Take stdin so it is closed once the manifest is written.
+            let sent = kubectl.stdin.take().unwrap().write_all(manifest.as_bytes());
+            let status = kubectl.wait().context("Couldn't wait for kubectl")?;
+            if status.success() {
+                sent.context("Couldn't write to kubectl stdin")?;
+            }
+            ("kubectl", status)
+        } else if engine::on_path("kn") {
+            let file = env::temp_dir().join(format!("roche-{}-{}", self.name, KNATIVE_MANIFEST));
+            fs::write(&file, &manifest)
+                .with_context(|| format!("Couldn't write {}", file.display()))?;
+            let mut kn = Command::new("kn");
+            kn.args(["service", "create", &self.name, "--force", "--filename"])
+                .arg(&file);
+            if let Some(namespace) = &self.target.namespace {
+                kn.args(["--namespace", namespace]);
+            }
+            let status = kn.status().context("Couldn't run kn");
+            let _ = fs::remove_file(&file);
+            ("kn", status?)
+        } else {
+            bail!(
+                "Neither kubectl nor kn was found. Please install one of them to deploy to knative"
+            );
+        };
+        if !status.success() {
+            bail!("{} couldn't deploy {} ({})", binary, self.name, status);
+        }
+        println!("Roche: Deployed {} as {}", self.image, self.name);
+        Ok(())
+    }
+
+    fn metadata(&self) -> Metadata {
+        Metadata {
+            name: self.name.clone(),
+            namespace: self.target.namespace.clone(),
+            labels: BTreeMap::new(),
+        }
+    }
+
+    fn labelled_metadata(&self, labels: &BTreeMap<&'static str, String>) -> Metadata {
+        Metadata {
+            labels: labels.clone(),
+            ..self.metadata()
+        }
+    }
+
+    fn container(&self) -> Result<Container> {
+        let mut limits = BTreeMap::new();
+        if let Some(cpu) = &self.target.cpu {
+            limits.insert("cpu", cpu.clone());
+        }
+        if let Some(memory) = &self.target.memory {
+            limits.insert("memory", memory.clone());
+        }
+        Ok(Container {
+            name: None,
+            image: self.image.clone(),
+            ports: vec![ContainerPort {
+                name: None,
+                container_port: self.container_port,
+            }],
+            env: self
+                .env()?
+                .into_iter()
+                .map(|(name, value)| EnvVar { name, value })
+                .collect(),
+            resources: if limits.is_empty() {
+                None
+            } else {
+                Some(Resources { limits })
+            },
+            liveness_probe: None,
+            readiness_probe: None,
+            security_context: None,
+        })
+    }
+}
+
+/// Picks the target to deploy to `platform`: the `[deploy.<name>]` table when `name` is given,
+/// otherwise the only table for the platform, or an empty target when there is none.
+pub fn target(
+    deploy: &BTreeMap<String, DeployTarget>,
+    platform: Platform,
+    name: Option<&str>,
+) -> Result<DeployTarget> {
+    if let Some(name) = name {
+        return match deploy.get(name) {
+            Some(target) if target.platform == platform => Ok(target.clone()),
+            Some(target) => bail!(
+                "deploy.{} is a {} target, not {}",
+                name,
+                target.platform,
+                platform
+            ),
+            None => bail!("No [deploy.{}] found in roche.toml", name),
+        };
+    }
+    let matching: Vec<&String> = deploy
+        .iter()
+        .filter(|(_, target)| target.platform == platform)
+        .map(|(name, _)| name)
+        .collect();
+    match matching.as_slice() {
+        [] => Ok(DeployTarget::new(platform)),
+        [name] => Ok(deploy[*name].clone()),
+        names => bail!(
+            "Several {} targets are configured ({}). Please pick one with --target",
+            platform,
+            names
+                .iter()
+                .map(|name| name.as_str())
+                .collect::<Vec<&str>>()
+                .join(", ")
+        ),
+    }
+}
+
+/// A name the platforms accept for `name`: a DNS-1035 label of lowercase letters, digits and
+/// dashes that starts with a letter.
+pub fn service_name(name: &str) -> Result<String> {
+    let mut service = String::new();
+    for c in name.to_lowercase().chars() {
+        match c {
+            'a'..='z' | '0'..='9' => service.push(c),
+            _ if !service.is_empty() && !service.ends_with('-') => service.push('-'),
+            _ => {}
+        }
+    }
+    let service = service.trim_end_matches('-');
+    if !service.starts_with(|c: char| c.is_ascii_lowercase()) || service.len() > 63 {
+        bail!(
+            "'{}' doesn't make a valid service name: it must start with a letter and have at most 63 letters, digits and dashes. Please set name in the [tag] section of roche.toml",
+            name
+        );
+    }
+    Ok(service.to_string())
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct KnativeService {
+    api_version: &'static str,
+    kind: &'static str,
+    metadata: Metadata,
+    spec: KnativeSpec,
+}
+
+#[derive(Serialize)]
+struct Metadata {
+    name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    namespace: Option<String>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    labels: BTreeMap<&'static str, String>,
+}
+
+#[derive(Serialize)]
+struct KnativeSpec {
+    template: RevisionTemplate,
+}
+
+#[derive(Serialize)]
+struct RevisionTemplate {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    metadata: Option<TemplateMetadata>,
+    spec: RevisionSpec,
+}
+
+#[derive(Serialize)]
+struct TemplateMetadata {
+    annotations: BTreeMap<&'static str, String>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct RevisionSpec {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    container_concurrency: Option<u32>,
+    containers: Vec<Container>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Container {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    name: Option<String>,
+    image: String,
+    ports: Vec<ContainerPort>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    env: Vec<EnvVar>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    resources: Option<Resources>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    liveness_probe: Option<Probe>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    readiness_probe: Option<Probe>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    security_context: Option<ContainerSecurityContext>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ContainerPort {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    name: Option<&'static str>,
+    container_port: u16,
+}
+
+#[derive(Serialize)]
+struct EnvVar {
+    name: String,
+    value: String,
+}
+
+#[derive(Serialize)]
+struct Resources {
+    limits: BTreeMap<&'static str, String>,
+}
+
+#[derive(Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Probe {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    http_get: Option<HttpGetAction>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tcp_socket: Option<TcpSocketAction>,
+}
+
+#[derive(Clone, Serialize)]
+struct HttpGetAction {
+    path: String,
+    port: &'static str,
+}
+
+#[derive(Clone, Serialize)]
+struct TcpSocketAction {
+    port: &'static str,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ContainerSecurityContext {
+    allow_privilege_escalation: bool,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Deployment {
+    api_version: &'static str,
+    kind: &'static str,
+    metadata: Metadata,
+    spec: DeploymentSpec,
+}
+
+#[derive(Serialize)]
+struct DeploymentSpec {
+    replicas: u32,
+    selector: LabelSelector,
+    template: PodTemplate,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct LabelSelector {
+    match_labels: BTreeMap<&'static str, String>,
+}
+
+#[derive(Serialize)]
+struct PodTemplate {
+    metadata: PodMetadata,
+    spec: PodSpec,
+}
+
+#[derive(Serialize)]
+struct PodMetadata {
+    labels: BTreeMap<&'static str, String>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct PodSpec {
+    security_context: PodSecurityContext,
+    containers: Vec<Container>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct PodSecurityContext {
+    run_as_non_root: bool,
+    run_as_user: u32,
+    run_as_group: u32,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct KubernetesService {
+    api_version: &'static str,
+    kind: &'static str,
+    metadata: Metadata,
+    spec: ServiceSpec,
+}
+
+#[derive(Serialize)]
+struct ServiceSpec {
+    selector: BTreeMap<&'static str, String>,
+    ports: Vec<ServicePort>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ServicePort {
+    name: &'static str,
+    port: u16,
+    target_port: &'static str,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Ingress {
+    api_version: &'static str,
+    kind: &'static str,
+    metadata: Metadata,
+    spec: IngressSpec,
+}
+
+#[derive(Serialize)]
+struct IngressSpec {
+    rules: Vec<IngressRule>,
+}
+
+#[derive(Serialize)]
+struct IngressRule {
+    host: String,
+    http: HttpIngressRule,
+}
+
+#[derive(Serialize)]
+struct HttpIngressRule {
+    paths: Vec<IngressPath>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct IngressPath {
+    path: &'static str,
+    path_type: &'static str,
+    backend: IngressBackend,
+}
+
+#[derive(Serialize)]
+struct IngressBackend {
+    service: ServiceBackend,
+}
+
+#[derive(Serialize)]
+struct ServiceBackend {
+    name: String,
+    port: ServiceBackendPort,
+}
+
+#[derive(Serialize)]
+struct ServiceBackendPort {
+    name: &'static str,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Kustomization {
+    api_version: &'static str,
+    kind: &'static str,
+    resources: Vec<&'static str>,
+}
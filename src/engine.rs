@@ -0,0 +1,390 @@
+use anyhow::{bail, Result};
+use std::env;
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+/// The engines roche knows how to drive, in auto-detection order.
+pub const ENGINES: [&str; 3] = ["docker", "podman", "buildah"];
+
+/// A container engine that can build images from a Dockerfile sent over stdin.
+pub trait ContainerEngine {
+    /// Name of the executable that is invoked for this engine.
+    fn binary(&self) -> &'static str;
+
+    /// Arguments that build `tag` from a Dockerfile read on stdin using `context` as the build context.
+    /// `flags` such as `--build-arg` are passed through before the context.
+    fn build_args(&self, tag: &str, flags: &[String], context: &str) -> Vec<String>;
+
+    /// The username the engine is logged in with, used to prefix generated tags.
+    fn login(&self) -> Option<String>;
+
+    /// Arguments that run `tag` in the foreground as a container called `name`, removing it on exit.
+    fn run_args(&self, name: &str, tag: &str, flags: &[String]) -> Result<Vec<String>> {
+        let mut args = vec![
+            "run".to_string(),
+            "--rm".to_string(),
+            "--name".to_string(),
+            name.to_string(),
+        ];
+        args.extend_from_slice(flags);
+        args.push(tag.to_string());
+        Ok(args)
+    }
+
+    /// Arguments that push `tag` to its registry, writing the pushed digest to `digest_file` when
+    /// the engine supports it.
+    fn push_args(&self, tag: &str, digest_file: &str) -> Vec<String> {
+        vec![
+            "push".to_string(),
+            "--digestfile".to_string(),
+            digest_file.to_string(),
+            tag.to_string(),
+        ]
+    }
+
+    /// Arguments that build `tag` as a manifest list holding an image for each of `platforms`.
+    /// `push` is set when the list is pushed once built.
+    fn manifest_build_args(
+        &self,
+        tag: &str,
+        platforms: &[String],
+        _push: bool,
+        flags: &[String],
+        context: &str,
+    ) -> Result<Vec<String>> {
+        let mut args = self.build_args(tag, flags, context);
+        // podman and buildah take `<verb> -t TAG`, `--manifest TAG` collects the images in a list
+        // instead of tagging a single one.
+        args.splice(
+            1..3,
+            vec![
+                "--platform".to_string(),
+                platforms.join(","),
+                "--manifest".to_string(),
+                tag.to_string(),
+            ],
+        );
+        Ok(args)
+    }
+
+    /// Whether manifest lists are kept in the engine's local storage until they are pushed.
+    fn stores_manifest_lists(&self) -> bool {
+        true
+    }
+
+    /// Arguments that push the manifest list `tag` and all of its images, writing the pushed
+    /// digest to `digest_file`.
+    fn manifest_push_args(&self, tag: &str, digest_file: &str) -> Vec<String> {
+        vec![
+            "manifest".to_string(),
+            "push".to_string(),
+            "--all".to_string(),
+            "--digestfile".to_string(),
+            digest_file.to_string(),
+            tag.to_string(),
+            format!("docker://{}", tag),
+        ]
+    }
+
+    /// Arguments that create a container from `tag` without starting it, printing its id, so
+    /// files can be copied out of the image.
+    fn create_args(&self, tag: &str) -> Result<Vec<String>> {
+        Ok(vec!["create".to_string(), tag.to_string()])
+    }
+
+    /// Arguments that write `path` in `container` to stdout as a tar archive.
+    fn copy_out_args(&self, container: &str, path: &str) -> Vec<String> {
+        vec![
+            "cp".to_string(),
+            format!("{}:{}", container, path),
+            "-".to_string(),
+        ]
+    }
+
+    /// Arguments that stop and remove the container called `name`.
+    fn remove_args(&self, name: &str) -> Vec<String> {
+        vec!["rm".to_string(), "-f".to_string(), name.to_string()]
+    }
+
+    /// A `Command` ready to build `tag`, with stdin and stdout piped.
+    fn build(&self, tag: &str, flags: &[String], context: &str) -> Command {
+        let mut cmd = Command::new(self.binary());
+        cmd.args(self.build_args(tag, flags, context))
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped());
+        cmd
+    }
+
+    /// A `Command` ready to build the manifest list `tag`, with stdin and stdout piped.
+    fn build_manifest(
+        &self,
+        tag: &str,
+        platforms: &[String],
+        push: bool,
+        flags: &[String],
+        context: &str,
+    ) -> Result<Command> {
+        let mut cmd = Command::new(self.binary());
+        cmd.args(self.manifest_build_args(tag, platforms, push, flags, context)?)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped());
+        Ok(cmd)
+    }
+
+    /// A quiet `Command` that removes the manifest list `tag` if there is one.
+    fn remove_manifest(&self, tag: &str) -> Command {
+        let mut cmd = Command::new(self.binary());
+        cmd.args(["manifest", "rm", tag])
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null());
+        cmd
+    }
+
+    /// A `Command` ready to push the manifest list `tag`, see
+    /// [`ContainerEngine::manifest_push_args`].
+    fn push_manifest(&self, tag: &str, digest_file: &str) -> Command {
+        let mut cmd = Command::new(self.binary());
+        cmd.args(self.manifest_push_args(tag, digest_file))
+            .stdin(Stdio::null());
+        cmd
+    }
+
+    /// A `Command` ready to push `tag`, see [`ContainerEngine::push_args`].
+    fn push(&self, tag: &str, digest_file: &str) -> Command {
+        let mut cmd = Command::new(self.binary());
+        cmd.args(self.push_args(tag, digest_file))
+            .stdin(Stdio::null());
+        cmd
+    }
+}
+
+pub struct Docker;
+pub struct Podman;
+pub struct Buildah;
+
+impl ContainerEngine for Docker {
+    fn binary(&self) -> &'static str {
+        "docker"
+    }
+
+    fn build_args(&self, tag: &str, flags: &[String], context: &str) -> Vec<String> {
+        let mut args = vec!["build".to_string(), format!("-t{}", tag), "-f-".to_string()];
+        args.extend_from_slice(flags);
+        args.push(context.to_string());
+        args
+    }
+
+    /// Multi-platform images are built with buildx, which can only send a manifest list straight
+    /// to the registry.
+    fn manifest_build_args(
+        &self,
+        tag: &str,
+        platforms: &[String],
+        push: bool,
+        flags: &[String],
+        context: &str,
+    ) -> Result<Vec<String>> {
+        if !push {
+            bail!(
+                "docker can't keep an image for {} locally. Please add --push to send it to the registry, or build it with --engine podman",
+                platforms.join(", ")
+            );
+        }
+        let mut args = vec![
+            "buildx".to_string(),
+            "build".to_string(),
+            "--platform".to_string(),
+            platforms.join(","),
+            format!("-t{}", tag),
+            "--push".to_string(),
+            "-f-".to_string(),
+        ];
+        args.extend_from_slice(flags);
+        args.push(context.to_string());
+        Ok(args)
+    }
+
+    fn stores_manifest_lists(&self) -> bool {
+        false
+    }
+
+    /// docker has no digest file, the digest is read from its output instead.
+    fn push_args(&self, tag: &str, _digest_file: &str) -> Vec<String> {
+        vec!["push".to_string(), tag.to_string()]
+    }
+
+    fn login(&self) -> Option<String> {
+        if let Ok(val) = env::var("DOCKER_USERNAME") {
+            return Some(val);
+        }
+        println!("DOCKER_USERNAME environment variable not found trying to docker cli");
+
+        let output = match Command::new("docker")
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .arg("info")
+            .output()
+        {
+            Err(why) => {
+                println!("couldn't spawn docker: {}", why);
+                return None;
+            }
+            Ok(output) => output,
+        };
+        let username = String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .rev()
+            .filter(|line| line.contains("Username"))
+            .find_map(|line| line.split_whitespace().next_back())
+            .unwrap_or_default()
+            .to_string();
+        if username.is_empty() {
+            None
+        } else {
+            Some(username)
+        }
+    }
+}
+
+impl ContainerEngine for Podman {
+    fn binary(&self) -> &'static str {
+        "podman"
+    }
+
+    fn build_args(&self, tag: &str, flags: &[String], context: &str) -> Vec<String> {
+        let mut args = vec![
+            "build".to_string(),
+            "-t".to_string(),
+            tag.to_string(),
+            "-f".to_string(),
+            "-".to_string(),
+        ];
+        args.extend_from_slice(flags);
+        args.push(context.to_string());
+        args
+    }
+
+    fn login(&self) -> Option<String> {
+        getclilogin(self.binary())
+    }
+}
+
+impl ContainerEngine for Buildah {
+    fn binary(&self) -> &'static str {
+        "buildah"
+    }
+
+    fn build_args(&self, tag: &str, flags: &[String], context: &str) -> Vec<String> {
+        let mut args = vec![
+            "bud".to_string(),
+            "-t".to_string(),
+            tag.to_string(),
+            "-f".to_string(),
+            "-".to_string(),
+        ];
+        args.extend_from_slice(flags);
+        args.push(context.to_string());
+        args
+    }
+
+    fn login(&self) -> Option<String> {
+        getclilogin(self.binary())
+    }
+
+    fn run_args(&self, _name: &str, _tag: &str, _flags: &[String]) -> Result<Vec<String>> {
+        bail!("buildah can only build images. Please use --engine podman or docker to run them")
+    }
+
+    fn create_args(&self, _tag: &str) -> Result<Vec<String>> {
+        bail!("buildah can't read files from images. Please use --engine podman, which shares its image storage")
+    }
+}
+
+/// Asks a podman compatible cli for the current registry login.
+fn getclilogin(binary: &str) -> Option<String> {
+    let output = match Command::new(binary)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .arg("login")
+        .arg("--get-login")
+        .output()
+    {
+        Err(why) => {
+            println!("No Username found with {}: {}", binary, why);
+            return None;
+        }
+        Ok(output) => output,
+    };
+    let username = String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .next()
+        .unwrap_or_default()
+        .to_string();
+    if username.is_empty() {
+        None
+    } else {
+        Some(username)
+    }
+}
+
+/// Returns the engine implementation for a name such as `docker`, `podman` or `buildah`.
+pub fn from_name(name: &str) -> Result<Box<dyn ContainerEngine>> {
+    match name {
+        "docker" => Ok(Box::new(Docker)),
+        "podman" => Ok(Box::new(Podman)),
+        "buildah" => Ok(Box::new(Buildah)),
+        other => bail!(
+            "Unknown container engine '{}'. Supported engines are {}",
+            other,
+            ENGINES.join(", ")
+        ),
+    }
+}
+
+/// Finds the first supported engine available on the PATH.
+pub fn detect() -> Option<&'static str> {
+    ENGINES.iter().find(|engine| on_path(engine)).copied()
+}
+
+/// Whether an executable called `name` is on the PATH.
+pub fn on_path(name: &str) -> bool {
+    match env::var_os("PATH") {
+        Some(path) => env::split_paths(&path).any(|dir| is_executable(&dir.join(name))),
+        None => false,
+    }
+}
+
+fn is_executable(path: &Path) -> bool {
+    match path.metadata() {
+        Ok(meta) => {
+            #[cfg(unix)]
+            {
+                use std::os::unix::fs::PermissionsExt;
+                meta.is_file() && meta.permissions().mode() & 0o111 != 0
+            }
+            #[cfg(not(unix))]
+            {
+                meta.is_file()
+            }
+        }
+        Err(_) => false,
+    }
+}
+
+/// Selects the engine from the `--engine` flag, then the `engine` key in `.rocherc`, then the PATH.
+pub fn select(flag: Option<&str>) -> Result<Box<dyn ContainerEngine>> {
+    if let Some(name) = flag {
+        return from_name(name);
+    }
+    if let Ok(name) = env::var("engine") {
+        return from_name(&name);
+    }
+    match detect() {
+        Some(name) => from_name(name),
+        None => bail!(
+            "No container engine found. Please install one of {} or pass --engine",
+            ENGINES.join(", ")
+        ),
+    }
+}
@@ -0,0 +1,364 @@
+//! Checking the function's source before it is built.
+//!
+//! A container build takes minutes to report that `functions.rs` doesn't export the handler the
+//! service mounts, so roche parses the source first and points at the offending line.
+
+use crate::modules;
+use anyhow::{anyhow, bail, Context, Result};
+use proc_macro2::Span;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use syn::spanned::Spanned;
+use syn::visit::{self, Visit};
+
+/// The handler the service mounts.
+pub const SIGNATURE: &str = "pub fn handler() -> tide::Server<()>";
+
+/// A route registered with `api.at(path).get(...)` and friends.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Route {
+    /// `GET`, `POST`, ... or `ANY` for `.all()` and `NEST` for `.nest()`.
+    pub method: String,
+    /// The full path, including the prefix of the function's module.
+    pub path: String,
+    /// The file and line registering the route.
+    pub file: PathBuf,
+    pub line: usize,
+}
+
+impl Route {
+    /// The route with its file relative to `dir`, so listings don't depend on the checkout.
+    pub fn relative_to(mut self, dir: &Path) -> Route {
+        if let Ok(file) = self.file.strip_prefix(dir) {
+            self.file = file.to_path_buf();
+        }
+        self
+    }
+
+    /// Where the route is registered, as `file:line`.
+    pub fn source(&self) -> String {
+        format!("{}:{}", self.file.display(), self.line)
+    }
+}
+
+impl fmt::Display for Route {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {}", self.method, self.path)
+    }
+}
+
+/// A parsed handler file and the endpoints it registers, for generators that need more than
+/// the routes.
+pub(crate) struct Source {
+    pub syntax: syn::File,
+    /// Each route with the endpoint passed for it, like a closure or the name of an `async fn`.
+    pub endpoints: Vec<(Route, Option<syn::Expr>)>,
+}
+
+/// Checks the handlers of the function in `dir` and returns the routes they register.
+///
+/// For a multi-function project every module in `functions/` is checked and its routes are
+/// returned under the module's prefix.
+pub fn check(dir: &Path, prefixes: &BTreeMap<String, String>) -> Result<Vec<Route>> {
+    Ok(sources(dir, prefixes)?
+        .into_iter()
+        .flat_map(|source| source.endpoints)
+        .map(|(route, _)| route)
+        .collect())
+}
+
+/// Parses and checks every handler file of the function in `dir`.
+pub(crate) fn sources(dir: &Path, prefixes: &BTreeMap<String, String>) -> Result<Vec<Source>> {
+    let modules = match modules::modules(dir, prefixes)? {
+        Some(modules) => modules,
+        None => return Ok(vec![parse(&dir.join("functions.rs"), "/")?]),
+    };
+    let mut sources: Vec<Source> = Vec::new();
+    for module in &modules {
+        let source = parse(&module.path(dir), &module.prefix)?;
+        for (route, _) in &source.endpoints {
+            let other = sources
+                .iter()
+                .flat_map(|source| source.endpoints.iter())
+                .map(|(other, _)| other)
+                .find(|other| other.method == route.method && other.path == route.path);
+            if let Some(other) = other {
+                bail!(
+                    "route collision: {} is registered at {}:{} and at {}:{}",
+                    route,
+                    other.file.display(),
+                    other.line,
+                    route.file.display(),
+                    route.line
+                );
+            }
+        }
+        sources.push(source);
+    }
+    Ok(sources)
+}
+
+/// Renders `routes` as a table of method, path and source line.
+pub fn table(routes: &[Route]) -> String {
+    let rows: Vec<[String; 3]> = routes
+        .iter()
+        .map(|route| [route.method.clone(), route.path.clone(), route.source()])
+        .collect();
+    let method = rows.iter().map(|row| row[0].len()).fold(6, usize::max);
+    let path = rows.iter().map(|row| row[1].len()).fold(4, usize::max);
+    let mut out = format!(
+        "{:method$}  {:path$}  SOURCE\n",
+        "METHOD",
+        "PATH",
+        method = method,
+        path = path
+    );
+    for [m, p, source] in rows {
+        out.push_str(&format!(
+            "{:method$}  {:path$}  {}\n",
+            m,
+            p,
+            source,
+            method = method,
+            path = path
+        ));
+    }
+    out
+}
+
+/// Renders `routes` as a JSON array of `method`, `path`, `file` and `line` objects.
+pub fn json(routes: &[Route]) -> Result<String> {
+    let mut out = serde_json::to_string_pretty(routes).context("Couldn't serialize the routes")?;
+    out.push('\n');
+    Ok(out)
+}
+
+/// Checks `file` exports the handler and returns the routes it registers under `prefix`.
+pub fn check_file(file: &Path, prefix: &str) -> Result<Vec<Route>> {
+    Ok(parse(file, prefix)?
+        .endpoints
+        .into_iter()
+        .map(|(route, _)| route)
+        .collect())
+}
+
+fn parse(file: &Path, prefix: &str) -> Result<Source> {
+    let source =
+        fs::read_to_string(file).with_context(|| format!("Couldn't read {}", file.display()))?;
+    let syntax = syn::parse_file(&source)
+        .map_err(|e| diagnostic(file, e.span(), &format!("couldn't parse: {}", e)))?;
+
+    let handler = syntax.items.iter().find_map(|item| match item {
+        syn::Item::Fn(function) if function.sig.ident == "handler" => Some(function),
+        _ => None,
+    });
+    let handler = match handler {
+        Some(handler) => handler,
+        None => bail!(
+            "{}: no handler() found. roche mounts `{}`, please add it",
+            file.display(),
+            SIGNATURE
+        ),
+    };
+    check_signature(file, &source, handler)?;
+
+    let mut routes = Routes {
+        file,
+        prefix,
+        endpoints: Vec::new(),
+    };
+    routes.visit_block(&handler.block);
+    let endpoints = routes.endpoints;
+    Ok(Source { syntax, endpoints })
+}
+
+fn check_signature(file: &Path, source: &str, handler: &syn::ItemFn) -> Result<()> {
+    let sig = &handler.sig;
+    let problem = if !matches!(handler.vis, syn::Visibility::Public(_)) {
+        Some((sig.fn_token.span(), "handler() must be pub".to_string()))
+    } else if let Some(token) = &sig.asyncness {
+        Some((token.span(), "handler() must not be async".to_string()))
+    } else if !sig.generics.params.is_empty() {
+        Some((
+            sig.generics.span(),
+            "handler() must not be generic".to_string(),
+        ))
+    } else if !sig.inputs.is_empty() {
+        Some((
+            sig.inputs.span(),
+            "handler() must take no arguments".to_string(),
+        ))
+    } else {
+        match &sig.output {
+            syn::ReturnType::Default => Some((
+                sig.paren_token.span,
+                "handler() must return tide::Server<()>, found nothing".to_string(),
+            )),
+            syn::ReturnType::Type(_, ty) if !is_server(ty) => Some((
+                ty.span(),
+                format!(
+                    "handler() must return tide::Server<()>, found `{}`",
+                    snippet(source, ty.span())
+                ),
+            )),
+            _ => None,
+        }
+    };
+    match problem {
+        Some((span, message)) => Err(diagnostic(
+            file,
+            span,
+            &format!("{}. roche mounts `{}`", message, SIGNATURE),
+        )),
+        None => Ok(()),
+    }
+}
+
+/// Whether `ty` is `tide::Server<()>`, or `Server<()>` with the type imported.
+fn is_server(ty: &syn::Type) -> bool {
+    let path = match ty {
+        syn::Type::Path(ty) if ty.qself.is_none() => &ty.path,
+        _ => return false,
+    };
+    let segments: Vec<&syn::PathSegment> = path.segments.iter().collect();
+    let qualified = match segments.len() {
+        1 => true,
+        2 => segments[0].ident == "tide" && segments[0].arguments.is_empty(),
+        _ => false,
+    };
+    let last = segments[segments.len() - 1];
+    if !qualified || last.ident != "Server" {
+        return false;
+    }
+    match &last.arguments {
+        syn::PathArguments::AngleBracketed(args) => {
+            args.args.len() == 1
+                && matches!(
+                    args.args.first(),
+                    Some(syn::GenericArgument::Type(syn::Type::Tuple(unit))) if unit.elems.is_empty()
+                )
+        }
+        _ => false,
+    }
+}
+
+/// Collects the `api.at(path).method(...)` calls in a handler.
+struct Routes<'a> {
+    file: &'a Path,
+    prefix: &'a str,
+    endpoints: Vec<(Route, Option<syn::Expr>)>,
+}
+
+impl<'ast> Visit<'ast> for Routes<'_> {
+    fn visit_expr_method_call(&mut self, call: &'ast syn::ExprMethodCall) {
+        // Visit the receiver first so chained methods are listed in the order they are written.
+        visit::visit_expr_method_call(self, call);
+        if let Some(method) = http_method(&call.method.to_string()) {
+            if let Some(path) = route_path(&call.receiver) {
+                let route = Route {
+                    method: method.to_string(),
+                    path: join(self.prefix, &path),
+                    file: self.file.to_path_buf(),
+                    line: call.method.span().start().line,
+                };
+                self.endpoints.push((route, call.args.first().cloned()));
+            }
+        }
+    }
+}
+
+/// The path of the route `expr` builds, following `.at()` calls and earlier methods.
+fn route_path(expr: &syn::Expr) -> Option<String> {
+    let call = match expr {
+        syn::Expr::MethodCall(call) => call,
+        _ => return None,
+    };
+    if call.method != "at" {
+        return http_method(&call.method.to_string()).and_then(|_| route_path(&call.receiver));
+    }
+    let path = match call.args.first() {
+        Some(syn::Expr::Lit(syn::ExprLit {
+            lit: syn::Lit::Str(path),
+            ..
+        })) => path.value(),
+        _ => return None,
+    };
+    match &*call.receiver {
+        syn::Expr::MethodCall(outer) if outer.method == "at" => {
+            route_path(&call.receiver).map(|outer| join(&outer, &path))
+        }
+        _ => Some(path),
+    }
+}
+
+fn http_method(name: &str) -> Option<&'static str> {
+    Some(match name {
+        "get" => "GET",
+        "post" => "POST",
+        "put" => "PUT",
+        "patch" => "PATCH",
+        "delete" => "DELETE",
+        "head" => "HEAD",
+        "options" => "OPTIONS",
+        "connect" => "CONNECT",
+        "trace" => "TRACE",
+        "all" => "ANY",
+        "nest" => "NEST",
+        _ => return None,
+    })
+}
+
+/// Joins a route `path` onto `prefix`, the way tide nests routes.
+fn join(prefix: &str, path: &str) -> String {
+    let joined = format!(
+        "{}/{}",
+        prefix.trim_end_matches('/'),
+        path.trim_start_matches('/')
+    );
+    match joined.trim_end_matches('/') {
+        "" => "/".to_string(),
+        trimmed => trimmed.to_string(),
+    }
+}
+
+fn diagnostic(file: &Path, span: Span, message: &str) -> anyhow::Error {
+    let start = span.start();
+    anyhow!(
+        "{}:{}:{}: {}",
+        file.display(),
+        start.line,
+        start.column + 1,
+        message
+    )
+}
+
+/// The source text `span` covers.
+fn snippet(source: &str, span: Span) -> String {
+    let (start, end) = (span.start(), span.end());
+    source
+        .lines()
+        .enumerate()
+        .skip(start.line - 1)
+        .take(end.line + 1 - start.line)
+        .map(|(idx, line)| {
+            let from = if idx + 1 == start.line {
+                start.column
+            } else {
+                0
+            };
+            let to = if idx + 1 == end.line {
+                end.column
+            } else {
+                line.chars().count()
+            };
+            line.chars()
+                .skip(from)
+                .take(to.saturating_sub(from))
+                .collect::<String>()
+        })
+        .collect::<Vec<String>>()
+        .join(" ")
+}
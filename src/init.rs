@@ -0,0 +1,76 @@
+use anyhow::Result;
+use cargo_generate::{generate, Args};
+use std::fs::File;
+use std::io::prelude::*;
+use std::path::Path;
+
+const FUNCTION: &str = include_str!("template/function.rs");
+
+pub const DEFAULT_PROJECT: &str = "https://github.com/roche-rs/default";
+pub const MONGODB_PROJECT: &str = "https://github.com/roche-rs/mongodb";
+
+// Public Args look out for this PR landing to fix this
+// https://github.com/ashleygwilliams/cargo-generate/pull/264
+
+#[derive(Debug)]
+pub struct PublicArgs {
+    /// Git repository to clone template from. Can be a URL (like
+    /// `https://github.com/rust-cli/cli-template`), a path (relative or absolute), or an
+    /// `owner/repo` abbreviated GitHub URL (like `rust-cli/cli-template`).
+    /// Note that cargo generate will first attempt to interpret the `owner/repo` form as a
+    /// relative path and only try a GitHub URL if the local path doesn't exist.
+    pub git: String,
+    /// Branch to use when installing from git
+    pub branch: Option<String>,
+    /// Directory to create / project name; if the name isn't in kebab-case, it will be converted
+    /// to kebab-case unless `--force` is given.
+    pub name: Option<String>,
+    /// Don't convert the project name to kebab-case before creating the directory.
+    /// Note that cargo generate won't overwrite an existing directory, even if `--force` is given.
+    pub force: bool,
+    /// Enables more verbose output.
+    pub verbose: bool,
+}
+
+impl From<PublicArgs> for Args {
+    fn from(args: PublicArgs) -> Args {
+        Args {
+            git: Some(args.git),
+            branch: args.branch,
+            name: args.name,
+            force: args.force,
+            verbose: args.verbose,
+            vcs: cargo_generate::Vcs::Git,
+
+            // cargo_generate::Args doesn't implement Default
+            list_favorites: false,
+            favorite: None,
+            template_values_file: None,
+            silent: false,
+            config: None,
+        }
+    }
+}
+
+/// Maps a template name such as `default` or `mongodb` to its git location.
+/// Returns `None` when the template isn't a known name or an https url.
+pub fn template_location(template: &str) -> Option<String> {
+    match template {
+        "default" => Some(DEFAULT_PROJECT.to_string()),
+        "mongodb" => Some(MONGODB_PROJECT.to_string()),
+        t if t.contains("https://") => Some(t.to_string()),
+        _ => None,
+    }
+}
+
+/// Generates a project from a git template with cargo-generate.
+pub fn generate_project(args: PublicArgs) -> Result<()> {
+    generate(args.into())
+}
+
+/// Writes the hello world `functions.rs` into `dir`.
+pub fn generate_function(dir: &Path) -> Result<()> {
+    let mut file = File::create(dir.join("functions.rs"))?;
+    file.write_all(FUNCTION.as_bytes())?;
+    Ok(())
+}
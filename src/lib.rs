@@ -0,0 +1,107 @@
+//! roche builds [tide](https://github.com/http-rs/tide) handlers into container images.
+//!
+//! The `roche` binary is a thin front-end over this crate. A build is described by a
+//! [`BuildRequest`](build::BuildRequest) which renders the Dockerfile for the function and
+//! hands it to a [`ContainerEngine`](engine::ContainerEngine). A built image can then be started
+//! locally with a [`RunRequest`](run::RunRequest), or rebuilt and restarted on every change with a
+//! [`WatchRequest`](watch::WatchRequest). Without a container engine a
+//! [`NativeRequest`](native::NativeRequest) builds the function with the host toolchain.
+//! Before any of these, [`handler::check`] parses the function so a missing or mistyped
+//! `handler()` is reported without waiting for a compile. The same parse lists the routes and
+//! drafts an [`openapi`] document for them. The release image is pushed by
+//! [`registry::PushRequest`] and can be described for a cluster by [`deploy`], or for a local
+//! stack with its backing services by [`compose`].
+//!
+//! The engine builds in the minimal folder put together by [`context`], and [`secrets`] passes
+//! build secrets to the cargo steps while refusing to ship files that look like credentials.
+//! [`oci`] writes the release image as an OCI tarball when there is no engine at all. A release
+//! image can be checked for vulnerabilities by [`scan`] and described by an SBOM from [`sbom`].
+//! The settings of all of these are read from `roche.toml` and the environment by [`config`].
+
+use anyhow::{Context, Result};
+use std::path::{Path, PathBuf};
+
+pub mod build;
+pub mod compose;
+pub mod config;
+pub mod context;
+pub mod deploy;
+pub mod engine;
+pub mod handler;
+pub mod init;
+pub mod modules;
+pub mod native;
+pub mod oci;
+pub mod openapi;
+pub mod registry;
+pub mod run;
+pub mod sbom;
+pub mod scan;
+pub mod secrets;
+pub mod service;
+pub mod watch;
+
+pub use build::{BuildKind, BuildRequest};
+pub use config::Config;
+pub use engine::ContainerEngine;
+pub use run::RunRequest;
+
+pub const DEV_BUILD_IMAGE: &str = "quay.io/roche/dev-default:1.4.0";
+pub const TEST_BUILD_IMAGE: &str = "quay.io/roche/dev-default:1.4.0";
+pub const RELEASE_BUILD_IMAGE: &str = "quay.io/roche/default:1.4.0";
+pub const RUNTIME_IMAGE: &str = "quay.io/roche/alpine-libgcc:3.12";
+
+/// Loads `src/.rocherc` and then `.rocherc` from `dir` into the environment.
+pub fn load_rocherc(dir: &Path) -> Result<()> {
+    for location in &[dir.join("src").join(".rocherc"), dir.join(".rocherc")] {
+        if location.exists() {
+            dotenv::from_path(location)
+                .with_context(|| format!("Couldn't load {}", location.display()))?;
+        }
+    }
+    Ok(())
+}
+
+/// Returns the folder holding `functions.rs`, or a `functions/` folder of handlers, either
+/// `dir` itself or its `src` subfolder.
+pub fn function_dir(dir: &Path) -> Option<PathBuf> {
+    let has_functions =
+        |dir: &Path| dir.join("functions.rs").exists() || dir.join(modules::FUNCTIONS_DIR).is_dir();
+    if has_functions(dir) {
+        Some(dir.to_path_buf())
+    } else if has_functions(&dir.join("src")) {
+        Some(dir.join("src"))
+    } else {
+        None
+    }
+}
+
+/// The project name derived from `dir`, skipping a trailing `src` folder.
+pub fn project_name(dir: &Path) -> Option<String> {
+    let pieces: Vec<&str> = dir.to_str()?.split(std::path::MAIN_SEPARATOR).collect();
+    let mut name = pieces[pieces.len() - 1];
+    if name == "src" && pieces.len() > 1 {
+        name = pieces[pieces.len() - 2];
+    }
+    Some(name.to_string())
+}
+
+/// Generates an image name from the project folder, prefixed with the engine login if there is one.
+pub fn generateimagetag(
+    buildtype: &str,
+    dir: &Path,
+    engine: &dyn ContainerEngine,
+) -> Option<String> {
+    let dir = project_name(dir)?;
+
+    match engine.login() {
+        Some(l) => {
+            let img = format!("{}/{}{}", l, buildtype, dir);
+            Some(img)
+        }
+        None => {
+            let img = format!("{}{}", buildtype, dir);
+            Some(img)
+        }
+    }
+}
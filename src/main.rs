@@ -1,187 +1,23 @@
-use anyhow::Result;
-use cargo_generate::{generate, Args};
-use clap::{App, Arg};
-use dotenv;
+use anyhow::{Context, Result};
+use clap::{App, Arg, ArgMatches};
+use roche::build::{BuildFailed, BuildKind, BuildRequest};
+use roche::compose::ComposeRequest;
+use roche::config::{Cli, Platform};
+use roche::deploy::DeployRequest;
+use roche::init::{self, PublicArgs};
+use roche::native::NativeRequest;
+use roche::oci::OciRequest;
+use roche::registry::PushRequest;
+use roche::sbom::{self, Format, ImageFiles, SbomRequest};
+use roche::scan::{ImageSource, ScanRequest, Scanner};
+use roche::watch::WatchRequest;
+use roche::{Config, ContainerEngine, RunRequest};
 use std::env;
-use std::fs::File;
-use std::io::prelude::*;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::process;
-use std::process::{Command, Stdio};
-
-// Public Args look out for this PR landing to fix this
-// https://github.com/ashleygwilliams/cargo-generate/pull/264
-
-#[derive(Debug)]
-pub struct PublicArgs {
-    /// Git repository to clone template from. Can be a URL (like
-    /// `https://github.com/rust-cli/cli-template`), a path (relative or absolute), or an
-    /// `owner/repo` abbreviated GitHub URL (like `rust-cli/cli-template`).
-    /// Note that cargo generate will first attempt to interpret the `owner/repo` form as a
-    /// relative path and only try a GitHub URL if the local path doesn't exist.
-    pub git: String,
-    /// Branch to use when installing from git
-    pub branch: Option<String>,
-    /// Directory to create / project name; if the name isn't in kebab-case, it will be converted
-    /// to kebab-case unless `--force` is given.
-    pub name: Option<String>,
-    /// Don't convert the project name to kebab-case before creating the directory.
-    /// Note that cargo generate won't overwrite an existing directory, even if `--force` is given.
-    pub force: bool,
-    /// Enables more verbose output.
-    pub verbose: bool,
-}
-
-impl Into<Args> for PublicArgs {
-    fn into(self) -> Args {
-        Args {
-            git: Some(self.git),
-            branch: self.branch,
-            name: self.name,
-            force: self.force,
-            verbose: self.verbose,
-            vcs: cargo_generate::Vcs::Git,
-
-            // cargo_generate::Args doesn't implement Default
-            list_favorites: false,
-            favorite: None,
-            template_values_file: None,
-            silent: false,
-            config: None,
-        }
-    }
-}
-
-pub fn generateimagetag(buildtype: String) -> Option<String> {
-    let fullpath = match env::current_dir() {
-        Err(why) => panic!("Couldn't get current dir {}", why),
-        Ok(s) => s,
-    };
-    let pieces: Vec<&str> = fullpath
-        .to_str()
-        .unwrap()
-        .split(std::path::MAIN_SEPARATOR)
-        .collect();
-    let mut dir = pieces[pieces.len() - 1];
-    if dir == "src" {
-        dir = pieces[pieces.len() - 2];
-    }
-
-    match getlogin() {
-        Some(l) => {
-            let img = format!("{}/{}{}", l, buildtype, dir);
-            Some(img)
-        }
-        None => {
-            let img = format!("{}{}", buildtype, dir);
-            Some(img)
-        }
-    }
-}
 
-pub fn getdockerlogin() -> Option<String> {
-    match env::var("DOCKER_USERNAME") {
-        Ok(val) => return Some(val),
-        Err(_e) => {
-            println!("DOCKER_USERNAME environment variable not found trying to docker cli")
-        }
-    };
-
-    let process = match Command::new("docker")
-        .stdin(Stdio::piped())
-        .stdout(Stdio::piped())
-        .arg("info")
-        .spawn()
-    {
-        Err(why) => {
-            println!("couldn't spawn docker: {}", why);
-            process::exit(1);
-        }
-        Ok(process) => process,
-    };
-    let mut s = String::new();
-    let mut username: String = String::new();
-
-    match process.stdout.unwrap().read_to_string(&mut s) {
-        Err(why) => panic!("couldn't read docker stdout: {}", why),
-        Ok(_) => {
-            for line in s.lines() {
-                if line.contains("Username") {
-                    let vusername = line.split_whitespace();
-                    username = vusername.last().unwrap_or_default().to_string();
-                }
-            }
-        }
-    };
-    if username.is_empty() {
-        None
-    } else {
-        Some(username)
-    }
-}
-
-pub fn getlogin() -> Option<String> {
-    match getdockerlogin() {
-        Some(s) => Some(s),
-        None => getpodmanlogin(),
-    }
-}
-
-pub fn getpodmanlogin() -> Option<String> {
-    let podman = match Command::new("podman")
-        .stdin(Stdio::piped())
-        .stdout(Stdio::piped())
-        .arg("login")
-        .arg("--get-login")
-        .spawn()
-    {
-        Err(why) => {
-            println!("No Username found with docker or podman: {}", why);
-            process::exit(1);
-        }
-        Ok(process) => process,
-    };
-    let mut podmanoutput = String::new();
-    let username;
-    match podman.stdout.unwrap().read_to_string(&mut podmanoutput) {
-        Err(why) => panic!("couldn't read podman stdout: {}", why),
-        Ok(_) => {
-            username = podmanoutput.lines().next().unwrap_or_default().to_string();
-        }
-    }
-    if username.is_empty() {
-        None
-    } else {
-        Some(username)
-    }
-}
 fn main() -> Result<()> {
-    const FUNCTION: &str = include_str!("template/function.rs");
-    const RELEASE_BUILD: &str = include_str!("template/Release.Dockerfile");
-    const LOCAL_BUILD: &str = include_str!("template/Dev.Dockerfile");
-    const TEST_BUILD: &str = include_str!("template/Libtest.Dockerfile");
-
-    let dirname = env::current_dir().unwrap();
-    let env_src_location = format!("{}/src/.rocherc", dirname.display());
-    if Path::new(&env_src_location).exists() {
-        dotenv::from_path(env_src_location).unwrap();
-    }
-
-    let env_top_location = format!("{}/.rocherc", dirname.display());
-    if Path::new(&env_top_location).exists() {
-        dotenv::from_path(env_top_location).unwrap();
-    }
-
-    let dev_build_image =
-        env::var("dev_build_image").unwrap_or("quay.io/roche/dev-default:1.4.0".to_string());
-    let test_build_image =
-        env::var("test_build_image").unwrap_or("quay.io/roche/dev-default:1.4.0".to_string());
-    let release_build_image =
-        env::var("release_build_image").unwrap_or("quay.io/roche/default:1.4.0".to_string());
-    let runtime_image =
-        env::var("runtime_image").unwrap_or("quay.io/roche/alpine-libgcc:3.12".to_string());
-    let default_project = "https://github.com/roche-rs/default";
-    let mongodb_project = "https://github.com/roche-rs/mongodb";
+    let dirname = env::current_dir()?;
 
     let matches = App::new("roche")
     .version("0.3.1")
@@ -253,6 +89,21 @@ fn main() -> Result<()> {
                     .long("tag")
                     .required(false)
             )
+            .arg(
+                Arg::new("engine")
+                    .about("container engine to use: 'docker', 'podman' or 'buildah'. If not provided the engine key in .rocherc is used or one is detected on the PATH")
+                    .takes_value(true)
+                    .short('e')
+                    .long("engine")
+                    .required(false)
+            )
+            .arg(
+                Arg::new("native")
+                    .about("builds with the local rust toolchain instead of a container engine")
+                    .takes_value(false)
+                    .long("native")
+                    .required(false)
+            )
         )
         .subcommand(
             App::new("test").about("Runs the lib tests in an image").arg(
@@ -271,6 +122,14 @@ fn main() -> Result<()> {
                     .long("tag")
                     .required(false)
             )
+            .arg(
+                Arg::new("engine")
+                    .about("container engine to use: 'docker', 'podman' or 'buildah'. If not provided the engine key in .rocherc is used or one is detected on the PATH")
+                    .takes_value(true)
+                    .short('e')
+                    .long("engine")
+                    .required(false)
+            )
         )
         .subcommand(
             App::new("release").about("Builds a release image").arg(
@@ -297,8 +156,310 @@ fn main() -> Result<()> {
                     .long("tag")
                     .required(false)
             )
+            .arg(
+                Arg::new("engine")
+                    .about("container engine to use: 'docker', 'podman' or 'buildah'. If not provided the engine key in .rocherc is used or one is detected on the PATH")
+                    .takes_value(true)
+                    .short('e')
+                    .long("engine")
+                    .required(false)
+            )
+            .arg(
+                Arg::new("registry")
+                    .about("registry the generated tag starts with, e.g. quay.io/myorg or ghcr.io/myorg. If not provided tag.registry from roche.toml or the engine login is used")
+                    .takes_value(true)
+                    .long("registry")
+                    .required(false)
+            )
+            .arg(
+                Arg::new("push")
+                    .about("Pushes the image to its registry once it is built.")
+                    .required(false)
+                    .takes_value(false)
+                    .long("push"),
+            )
+            .arg(
+                Arg::new("platform")
+                    .about("comma separated platforms to build for, e.g. linux/amd64,linux/arm64. More than one builds a manifest list. If not provided build.platforms from roche.toml or the engine's platform is used")
+                    .takes_value(true)
+                    .long("platform")
+                    .required(false)
+            )
+            .arg(
+                Arg::new("oci")
+                    .about("Builds the service natively and writes the release image to this OCI tarball without a container engine. Unlike the release Dockerfile it doesn't run the lib tests, please run cargo test first")
+                    .takes_value(true)
+                    .long("oci")
+                    .required(false)
+            )
+            .arg(
+                Arg::new("rust-target")
+                    .about("target triple the service is compiled for with --oci, which also picks the platform of the runtime image, e.g. aarch64-unknown-linux-musl for linux/arm64. If not provided <host arch>-unknown-linux-musl is used")
+                    .takes_value(true)
+                    .long("rust-target")
+                    .required(false)
+            )
+            .arg(
+                Arg::new("sbom")
+                    .about("Writes CycloneDX and SPDX SBOMs of the image's crates and runtime packages to sbom.cdx.json and sbom.spdx.json, or next to the --oci tarball.")
+                    .required(false)
+                    .takes_value(false)
+                    .long("sbom"),
+            )
+            .arg(
+                Arg::new("attach-sbom")
+                    .about("Writes the SBOMs and attaches them to the pushed image with oras. Needs --push.")
+                    .required(false)
+                    .takes_value(false)
+                    .long("attach-sbom"),
+            )
+            .arg(
+                Arg::new("scan")
+                    .about("Scans the image for vulnerabilities once it is built and fails before pushing when one reaches scan.severity from roche.toml.")
+                    .required(false)
+                    .takes_value(false)
+                    .long("scan"),
+            )
+            .arg(
+                Arg::new("scanner")
+                    .about("vulnerability scanner to use: 'trivy' or 'grype'. If not provided scan.scanner from roche.toml is used or one is detected on the PATH")
+                    .takes_value(true)
+                    .long("scanner")
+                    .required(false)
+            )
+        ).subcommand(
+            App::new("sbom").about("Writes CycloneDX and SPDX SBOMs of the release image's crates and runtime packages")
+            .arg(
+                Arg::new("tag")
+                    .about("image to describe. If not provided the release tag from roche.toml or the engine login is used")
+                    .takes_value(true)
+                    .index(1)
+                    .required(false)
+            )
+            .arg(
+                Arg::new("engine")
+                    .about("container engine holding the image: 'docker' or 'podman'. If not provided the engine key in .rocherc is used or one is detected on the PATH")
+                    .takes_value(true)
+                    .short('e')
+                    .long("engine")
+                    .required(false)
+            )
+            .arg(
+                Arg::new("registry")
+                    .about("registry the generated tag starts with, e.g. quay.io/myorg or ghcr.io/myorg. If not provided tag.registry from roche.toml or the engine login is used")
+                    .takes_value(true)
+                    .long("registry")
+                    .required(false)
+            )
+            .arg(
+                Arg::new("runtimeimage")
+                    .about("runtime image the release was built on, recorded in the SBOM. If not provided images.runtime from roche.toml or quay.io/roche/alpine-libgcc:3.12 is used")
+                    .takes_value(true)
+                    .short('r')
+                    .long("runtime")
+                    .required(false)
+            )
+            .arg(
+                Arg::new("format")
+                    .about("'cyclonedx' or 'spdx'. If not provided both are written")
+                    .takes_value(true)
+                    .long("format")
+                    .required(false)
+            )
+            .arg(
+                Arg::new("output")
+                    .about("path the files are written to before their .cdx.json or .spdx.json extension. Defaults to sbom")
+                    .takes_value(true)
+                    .short('o')
+                    .long("output")
+                    .required(false)
+            )
+            .arg(
+                Arg::new("attach")
+                    .about("Attaches the SBOMs to the image in its registry with oras.")
+                    .required(false)
+                    .takes_value(false)
+                    .long("attach"),
+            )
+        ).subcommand(
+            App::new("scan").about("Scans the release image for vulnerabilities and fails when one reaches scan.severity from roche.toml")
+            .arg(
+                Arg::new("tag")
+                    .about("image to scan. If not provided the release tag from roche.toml or the engine login is used")
+                    .takes_value(true)
+                    .index(1)
+                    .required(false)
+            )
+            .arg(
+                Arg::new("engine")
+                    .about("container engine whose local images are scanned: 'docker', 'podman' or 'buildah'. If not provided the engine key in .rocherc is used or one is detected on the PATH")
+                    .takes_value(true)
+                    .short('e')
+                    .long("engine")
+                    .required(false)
+            )
+            .arg(
+                Arg::new("registry")
+                    .about("registry the generated tag starts with, e.g. quay.io/myorg or ghcr.io/myorg. If not provided tag.registry from roche.toml or the engine login is used")
+                    .takes_value(true)
+                    .long("registry")
+                    .required(false)
+            )
+            .arg(
+                Arg::new("scanner")
+                    .about("vulnerability scanner to use: 'trivy' or 'grype'. If not provided scan.scanner from roche.toml is used or one is detected on the PATH")
+                    .takes_value(true)
+                    .long("scanner")
+                    .required(false)
+            )
+            .arg(
+                Arg::new("severity")
+                    .about("lowest severity that fails the scan: critical, high, medium, low, negligible or unknown. If not provided scan.severity from roche.toml or high is used")
+                    .takes_value(true)
+                    .long("severity")
+                    .required(false)
+            )
+            .arg(
+                Arg::new("remote")
+                    .about("Scans the image in its registry instead of the engine's local storage.")
+                    .required(false)
+                    .takes_value(false)
+                    .long("remote"),
+            )
+        ).subcommand(
+            App::new("push").about("Pushes the release image to its registry and prints the pushed digest")
+            .arg(
+                Arg::new("tag")
+                    .about("image to push. If not provided the release tag from roche.toml or the engine login is used")
+                    .takes_value(true)
+                    .short('t')
+                    .long("tag")
+                    .required(false)
+            )
+            .arg(
+                Arg::new("engine")
+                    .about("container engine to use: 'docker', 'podman' or 'buildah'. If not provided the engine key in .rocherc is used or one is detected on the PATH")
+                    .takes_value(true)
+                    .short('e')
+                    .long("engine")
+                    .required(false)
+            )
+            .arg(
+                Arg::new("registry")
+                    .about("registry the generated tag starts with, e.g. quay.io/myorg or ghcr.io/myorg. If not provided tag.registry from roche.toml or the engine login is used")
+                    .takes_value(true)
+                    .long("registry")
+                    .required(false)
+            )
+            .arg(
+                Arg::new("manifest")
+                    .about("Pushes a manifest list built by 'release --platform' with all of its images.")
+                    .required(false)
+                    .takes_value(false)
+                    .long("manifest"),
+            )
+        ).subcommand(
+            App::new("run").about("Builds a development image and runs it locally until Ctrl-C")
+            .arg(
+                Arg::new("buildimage")
+                    .about("buildimage to use. If not provided defaults to quay.io/roche/default:1.1.0")
+                    .takes_value(true)
+                    .short('b')
+                    .long("buildimage")
+                    .required(false)
+            )
+            .arg(
+                Arg::new("runtimeimage")
+                    .about("baseimage to use. If not provided defaults to quay.io/roche/alpine-libgcc:3.12")
+                    .takes_value(true)
+                    .short('r')
+                    .long("runtime")
+                    .required(false)
+            )
+            .arg(
+                Arg::new("tag")
+                    .about("tag for the build.")
+                    .takes_value(true)
+                    .short('t')
+                    .long("tag")
+                    .required(false)
+            )
+            .arg(
+                Arg::new("engine")
+                    .about("container engine to use: 'docker' or 'podman'. If not provided the engine key in .rocherc is used or one is detected on the PATH")
+                    .takes_value(true)
+                    .short('e')
+                    .long("engine")
+                    .required(false)
+            )
+            .arg(
+                Arg::new("port")
+                    .about("host port to publish the function on. If not provided ports.host from roche.toml or 8080 is used")
+                    .takes_value(true)
+                    .short('p')
+                    .long("port")
+                    .required(false)
+            )
+            .arg(
+                Arg::new("native")
+                    .about("builds with the local rust toolchain instead of a container engine")
+                    .takes_value(false)
+                    .long("native")
+                    .required(false)
+            )
+        ).subcommand(
+            App::new("watch").about("Builds and runs a development image, rebuilding and restarting it when functions.rs, lib.rs or .env change")
+            .arg(
+                Arg::new("buildimage")
+                    .about("buildimage to use. If not provided defaults to quay.io/roche/default:1.1.0")
+                    .takes_value(true)
+                    .short('b')
+                    .long("buildimage")
+                    .required(false)
+            )
+            .arg(
+                Arg::new("runtimeimage")
+                    .about("baseimage to use. If not provided defaults to quay.io/roche/alpine-libgcc:3.12")
+                    .takes_value(true)
+                    .short('r')
+                    .long("runtime")
+                    .required(false)
+            )
+            .arg(
+                Arg::new("tag")
+                    .about("tag for the build.")
+                    .takes_value(true)
+                    .short('t')
+                    .long("tag")
+                    .required(false)
+            )
+            .arg(
+                Arg::new("engine")
+                    .about("container engine to use: 'docker' or 'podman'. If not provided the engine key in .rocherc is used or one is detected on the PATH")
+                    .takes_value(true)
+                    .short('e')
+                    .long("engine")
+                    .required(false)
+            )
+            .arg(
+                Arg::new("port")
+                    .about("host port to publish the function on. If not provided ports.host from roche.toml or 8080 is used")
+                    .takes_value(true)
+                    .short('p')
+                    .long("port")
+                    .required(false)
+            )
         ).subcommand(
             App::new("gen").about("Generates a release Dockerfile")
+            .subcommand(
+                deploy_args(App::new("knative").about("Generates a Knative Service for the release image in service.yaml"))
+            )
+            .subcommand(
+                deploy_args(App::new("k8s").about("Generates a kubernetes Deployment, Service, Ingress and kustomization.yaml for the release image in k8s/"))
+            )
+            .subcommand(
+                App::new("compose").about("Generates a docker-compose.yml running the function with the [services] from roche.toml, and the Dockerfile it is built from")
+            )
             .arg(
                 Arg::new("buildimage")
                     .about("buildimage to use. If not provided defaults to quay.io/roche/default:1.1.0")
@@ -315,388 +476,830 @@ fn main() -> Result<()> {
                     .long("runtime")
                     .required(false)
             )
+        ).subcommand(
+            App::new("deploy").about("Deploys the release image")
+            .subcommand(
+                deploy_args(App::new("knative").about("Applies the Knative Service for the release image with kubectl or kn"))
+            )
+        ).subcommand(
+            App::new("routes").about("Lists the routes handler() registers with api.at(...)")
+            .arg(
+                Arg::new("json")
+                    .about("Prints the routes as JSON instead of a table.")
+                    .required(false)
+                    .takes_value(false)
+                    .long("json"),
+            )
+        ).subcommand(
+            App::new("openapi").about("Prints an OpenAPI 3 document for the routes handler() registers")
+        ).subcommand(
+            App::new("eject").about("Writes the service crate that wraps functions.rs (Cargo.toml, main.rs and run.sh) next to it so it can be customised")
+            .arg(
+                Arg::new("force")
+                    .about("Overwrites service files that already exist.")
+                    .required(false)
+                    .takes_value(false)
+                    .short('f')
+                    .long("force"),
+            )
+        ).subcommand(
+            App::new("config").about("Inspects the project configuration")
+            .subcommand(
+                App::new("show").about("Prints the resolved configuration from the command line, environment, .rocherc and roche.toml and where each value came from")
+                .arg(
+                    Arg::new("engine")
+                        .about("container engine, as build and release take it")
+                        .takes_value(true)
+                        .short('e')
+                        .long("engine")
+                        .required(false)
+                )
+                .arg(
+                    Arg::new("buildimage")
+                        .about("build image of release builds, as release takes it")
+                        .takes_value(true)
+                        .short('b')
+                        .long("buildimage")
+                        .required(false)
+                )
+                .arg(
+                    Arg::new("runtimeimage")
+                        .about("runtime image, as release takes it")
+                        .takes_value(true)
+                        .short('r')
+                        .long("runtime")
+                        .required(false)
+                )
+                .arg(
+                    Arg::new("registry")
+                        .about("registry the generated tag starts with, as release takes it")
+                        .takes_value(true)
+                        .long("registry")
+                        .required(false)
+                )
+                .arg(
+                    Arg::new("scanner")
+                        .about("vulnerability scanner, as release --scan takes it")
+                        .takes_value(true)
+                        .long("scanner")
+                        .required(false)
+                )
+            )
         )
         .get_matches();
 
-    match matches.subcommand_name() {
-        None => println!("No subcommand was used - try 'roche help'"),
-        _ => {}
-    };
+    if matches.subcommand_name().is_none() {
+        println!("No subcommand was used - try 'roche help'");
+        return Ok(());
+    }
 
-    if matches.is_present("build") {
-        // Check we have a functions.rs to build.
-        let dirname = env::current_dir()?;
+    // init writes a new project, so it runs before a broken roche.toml in this folder can stop it.
+    if let Some(init_matches) = matches.subcommand_matches("init") {
+        return init(&dirname, init_matches);
+    }
+    let config = Config::load(&dirname)?;
 
-        let functionlocation = format!("{}/functions.rs", dirname.display());
-        if !Path::new(&functionlocation).exists() {
-            let srcfunction = format!("{}/src/functions.rs", dirname.display());
-            if !Path::new(&srcfunction).exists() {
-                println!(
-                    "Cannot find functions.rs in the current folder or in src subfolder. Exiting"
-                );
-                process::exit(1);
-            } else {
-                let srcfolder = format!("{}/src", dirname.display());
-                let srcpath = Path::new(&srcfolder);
-                assert!(env::set_current_dir(&srcpath).is_ok());
+    for kind in &[BuildKind::Dev, BuildKind::Test, BuildKind::Release] {
+        let (name, image_arg) = match kind {
+            BuildKind::Dev => ("build", "buildimage"),
+            BuildKind::Test => ("test", "libtestimage"),
+            BuildKind::Release => ("release", "buildimage"),
+        };
+        if let Some(build_matches) = matches.subcommand_matches(name) {
+            check_handler(&config, &context_dir(&dirname));
+            if *kind == BuildKind::Dev && build_matches.is_present("native") {
+                if let Err(e) = native_request(&config, &dirname)?.execute() {
+                    exit_with(e);
+                }
+                continue;
             }
-        }
-
-        if let Some(build_matches) = matches.subcommand_matches("build") {
-            let mut tag = format!("-t{}", build_matches.value_of("tag").unwrap_or(""));
-
-            //let mut tag = build_matches.value_of("tag").unwrap_or("").to_string();
-
-            if tag == "-t" {
-                tag = match generateimagetag("dev-".to_string()) {
-                    Some(s) => {
-                        println!("No tag provided using {}", s);
-                        format!("-t{}", s)
-                    }
-                    None => {
-                        panic!("No tag provided and couldn't generate a tag. Please check you have logged into docker or podman")
-                    }
+            if *kind == BuildKind::Release && build_matches.is_present("oci") {
+                if let Err(e) = oci_release(&config, &dirname, build_matches) {
+                    exit_with(e);
                 }
+                continue;
             }
-
-            let buildimage = build_matches
-                .value_of("buildimage")
-                .unwrap_or(dev_build_image.as_str());
-            let runtimeimage = build_matches
-                .value_of("runtimeimage")
-                .unwrap_or(runtime_image.as_str());
-            let mut tmp_docker_file = str::replace(LOCAL_BUILD, "DEV_BASE_IMAGE", buildimage);
-            tmp_docker_file = str::replace(tmp_docker_file.as_str(), "RUNTIME_IMAGE", runtimeimage);
-            if Path::new(".env").exists() {
-                tmp_docker_file = str::replace(
-                    tmp_docker_file.as_str(),
-                    "INCLUDE_ENV",
-                    "app-build/src/.env*",
-                );
+            let (engine, request) =
+                build_request(&config, &dirname, *kind, build_matches, image_arg)?;
+            // Resolved first so a missing scanner doesn't wait for the build.
+            let scanner = if build_matches.is_present("scan") {
+                if request.pushed_by_build(engine.as_ref()) {
+                    anyhow::bail!(
+                        "docker pushes images for several platforms while building them, so --scan can't check them first. Please build with --engine podman or scan the pushed image with 'roche scan --remote'"
+                    );
+                }
+                Some(config.scanner(build_matches.value_of("scanner"))?)
             } else {
-                tmp_docker_file = str::replace(tmp_docker_file.as_str(), "INCLUDE_ENV ", "");
-            }
-            let process = match Command::new("docker")
-                .stdin(Stdio::piped())
-                .stdout(Stdio::piped())
-                .arg("build")
-                .arg(&tag)
-                .arg("-f-")
-                .arg(".")
-                .spawn()
+                None
+            };
+            let sbom = if build_matches.is_present("sbom")
+                || build_matches.is_present("attach-sbom")
             {
-                Err(why) => {
-                    println!("couldn't spawn docker: {}", why);
-                    process::exit(1);
+                if request.pushed_by_build(engine.as_ref()) {
+                    anyhow::bail!(
+                        "docker pushes images for several platforms while building them, so --sbom can't read them locally. Please build with --engine podman"
+                    );
                 }
-                Ok(process) => process,
+                if build_matches.is_present("attach-sbom") && !request.push {
+                    anyhow::bail!("--attach-sbom attaches the SBOM to the image in its registry. Please add --push");
+                }
+                Some(SbomRequest {
+                    tag: request.tag.clone(),
+                    runtime_image: request.runtime_image.clone(),
+                    formats: Format::ALL.to_vec(),
+                    output: dirname.join(sbom::DEFAULT_NAME),
+                    attach: build_matches.is_present("attach-sbom"),
+                })
+            } else {
+                None
             };
-
-            match process.stdin.unwrap().write_all(tmp_docker_file.as_bytes()) {
-                Err(why) => panic!("couldn't write to docker stdin: {}", why),
-                Ok(_) => println!("Roche: Sent file to builder for {}", &tag),
+            if let Err(e) = request.execute(engine.as_ref()) {
+                exit_with(e);
             }
-            let mut s = String::new();
-            match process.stdout.unwrap().read_to_string(&mut s) {
-                Err(why) => panic!("couldn't read docker stdout: {}", why),
-                Ok(_) => print!("Roche: Build complete for {}\n{}", &tag, s),
+            if let Some(scanner) = scanner {
+                let source = ImageSource::engine(engine.binary());
+                scan(&config, scanner.as_ref(), &request.tag, source, None)?;
             }
-        }
-
-        //
-    }
-    if matches.is_present("test") {
-        // Check we have a functions.rs to test.
-        let dirname = env::current_dir()?;
-
-        let functionlocation = format!("{}/functions.rs", dirname.display());
-        if !Path::new(&functionlocation).exists() {
-            let srcfunction = format!("{}/src/functions.rs", dirname.display());
-            if !Path::new(&srcfunction).exists() {
+            if let Some(sbom) = &sbom {
+                sbom.write(&ImageFiles::from_engine(engine.as_ref(), &request.tag)?)?;
+            }
+            if request.pushed_by_build(engine.as_ref()) {
                 println!(
-                    "Cannot find functions.rs in the current folder or in src subfolder. Exiting"
+                    "Roche: Pushed {} for {}",
+                    request.tag,
+                    request.platforms.join(", ")
                 );
-                process::exit(1);
-            } else {
-                let srcfolder = format!("{}/src", dirname.display());
-                let srcpath = Path::new(&srcfolder);
-                assert!(env::set_current_dir(&srcpath).is_ok());
+            } else if request.push {
+                push(engine.as_ref(), &request.tag, request.multi_platform());
+            }
+            if let Some(sbom) = sbom.filter(|sbom| sbom.attach) {
+                sbom.attach()?;
             }
         }
-        let dirname = env::current_dir()?;
-        let testlocation = format!("{}/lib.rs", dirname.display());
-        if !Path::new(&testlocation).exists() {
-            println!("Cannot find lib.rs in the src folder. Exiting");
-            process::exit(1);
-        }
+    }
 
-        if let Some(build_matches) = matches.subcommand_matches("test") {
-            let mut tag = format!("-t{}", build_matches.value_of("tag").unwrap_or(""));
+    if let Some(push_matches) = matches.subcommand_matches("push") {
+        let engine = config.engine(push_matches.value_of("engine"))?;
+        let tag = image_tag(
+            &config,
+            &context_dir(&dirname),
+            BuildKind::Release,
+            engine.as_ref(),
+            push_matches,
+        )?;
+        push(engine.as_ref(), &tag, push_matches.is_present("manifest"));
+    }
 
-            //let mut tag = build_matches.value_of("tag").unwrap_or("").to_string();
+    if let Some(sbom_matches) = matches.subcommand_matches("sbom") {
+        let engine = config.engine(sbom_matches.value_of("engine"))?;
+        let tag = image_tag(
+            &config,
+            &context_dir(&dirname),
+            BuildKind::Release,
+            engine.as_ref(),
+            sbom_matches,
+        )?;
+        let formats = match sbom_matches.value_of("format") {
+            Some(format) => vec![format.parse()?],
+            None => Format::ALL.to_vec(),
+        };
+        let request = SbomRequest {
+            runtime_image: config
+                .runtime_image
+                .or_cli(sbom_matches.value_of("runtimeimage"))
+                .value,
+            formats,
+            output: PathBuf::from(
+                sbom_matches
+                    .value_of("output")
+                    .unwrap_or(sbom::DEFAULT_NAME),
+            ),
+            attach: sbom_matches.is_present("attach"),
+            tag,
+        };
+        let files = ImageFiles::from_engine(engine.as_ref(), &request.tag)?;
+        if let Err(e) = request.execute(&files) {
+            exit_with(e);
+        }
+    }
 
-            if tag == "-t" {
-                tag = match generateimagetag("test-".to_string()) {
-                    Some(s) => {
-                        println!("No tag provided using {}", s);
-                        format!("-t{}", s)
-                    }
-                    None => {
-                        panic!("No tag provided and couldn't generate a tag. Please check you have logged into docker or podman")
-                    }
-                }
-            }
+    if let Some(scan_matches) = matches.subcommand_matches("scan") {
+        let engine = config.engine(scan_matches.value_of("engine"))?;
+        let tag = image_tag(
+            &config,
+            &context_dir(&dirname),
+            BuildKind::Release,
+            engine.as_ref(),
+            scan_matches,
+        )?;
+        let source = if scan_matches.is_present("remote") {
+            ImageSource::Registry
+        } else {
+            ImageSource::engine(engine.binary())
+        };
+        let scanner = config.scanner(scan_matches.value_of("scanner"))?;
+        scan(
+            &config,
+            scanner.as_ref(),
+            &tag,
+            source,
+            scan_matches.value_of("severity"),
+        )?;
+    }
 
-            let testimage = build_matches
-                .value_of("libtestimage")
-                .unwrap_or(test_build_image.as_str());
-            let mut tmp_docker_file = str::replace(TEST_BUILD, "TEST_BASE_IMAGE", testimage);
-            if Path::new(".env").exists() {
-                tmp_docker_file = str::replace(
-                    tmp_docker_file.as_str(),
-                    "INCLUDE_ENV",
-                    "app-build/src/.env*",
-                );
-            } else {
-                tmp_docker_file = str::replace(tmp_docker_file.as_str(), "INCLUDE_ENV ", "");
+    if let Some(run_matches) = matches.subcommand_matches("run") {
+        if run_matches.is_present("native") {
+            let native = native_request(&config, &dirname)?;
+            let run = run_request(&config, &native.context_dir, "", run_matches)?;
+            if let Err(e) = native.run(&run) {
+                exit_with(e);
             }
-            let process = match Command::new("docker")
-                .stdin(Stdio::piped())
-                .stdout(Stdio::piped())
-                .arg("build")
-                .arg(&tag)
-                .arg("-f-")
-                .arg(".")
-                .spawn()
-            {
-                Err(why) => {
-                    println!("couldn't spawn docker: {}", why);
-                    process::exit(1);
-                }
-                Ok(process) => process,
-            };
+            return Ok(());
+        }
+        let (engine, request) =
+            build_request(&config, &dirname, BuildKind::Dev, run_matches, "buildimage")?;
+        if let Err(e) = request.execute(engine.as_ref()) {
+            exit_with(e);
+        }
 
-            match process.stdin.unwrap().write_all(tmp_docker_file.as_bytes()) {
-                Err(why) => panic!("couldn't write to docker stdin: {}", why),
-                Ok(_) => println!("Roche: Sent file to builder for {}", &tag),
-            }
-            let mut s = String::new();
-            match process.stdout.unwrap().read_to_string(&mut s) {
-                Err(why) => panic!("couldn't read docker stdout: {}", why),
-                Ok(_) => print!("Roche: Build complete for {}\n{}", &tag, s),
-            }
+        let run = run_request(&config, &request.context_dir, &request.tag, run_matches)?;
+        if let Err(e) = run.execute(engine.as_ref()) {
+            exit_with(e);
         }
     }
 
-    if matches.is_present("release") {
-        // Check we have a functions.rs to build.
-        println!("in release");
-        let dirname = env::current_dir()?;
+    if let Some(watch_matches) = matches.subcommand_matches("watch") {
+        let (engine, request) = build_request(
+            &config,
+            &dirname,
+            BuildKind::Dev,
+            watch_matches,
+            "buildimage",
+        )?;
+        let watch = WatchRequest {
+            run: run_request(&config, &request.context_dir, &request.tag, watch_matches)?,
+            build: request,
+            debounce: roche::watch::DEBOUNCE,
+        };
+        if let Err(e) = watch.execute(engine.as_ref()) {
+            exit_with(e);
+        }
+    }
 
-        let functionlocation = format!("{}/functions.rs", dirname.display());
-        if !Path::new(&functionlocation).exists() {
-            let srcfunction = format!("{}/src/functions.rs", dirname.display());
-            if !Path::new(&srcfunction).exists() {
+    if let Some(gen_matches) = matches.subcommand_matches("gen") {
+        if let Some(knative_matches) = gen_matches.subcommand_matches("knative") {
+            let request = deploy_request(&config, &dirname, Platform::Knative, knative_matches)?;
+            let manifest = request.knative()?;
+            let path = Path::new(roche::deploy::KNATIVE_MANIFEST);
+            if !path.exists() {
+                std::fs::write(path, manifest)?;
+                println!("Roche: Wrote {} for {}", path.display(), request.image);
+            } else {
                 println!(
-                    "Cannot find functions.rs in the current folder or in src subfolder. Exiting"
+                    "{} already exists refusing to overwrite it. Please delete it and try again.",
+                    path.display()
                 );
-                process::exit(1);
+            }
+        }
+        if let Some(k8s_matches) = gen_matches.subcommand_matches("k8s") {
+            let request = deploy_request(&config, &dirname, Platform::Kubernetes, k8s_matches)?;
+            let files = request.kubernetes()?;
+            let dir = Path::new(roche::deploy::KUBERNETES_DIR);
+            if !dir.exists() {
+                std::fs::create_dir(dir)?;
+                for (file, manifest) in files {
+                    std::fs::write(dir.join(file), manifest)?;
+                }
+                println!("Roche: Wrote {} for {}", dir.display(), request.image);
             } else {
-                let srcfolder = format!("{}/src", dirname.display());
-                let srcpath = Path::new(&srcfolder);
-                assert!(env::set_current_dir(&srcpath).is_ok());
+                println!(
+                    "{} already exists refusing to overwrite it. Please delete it and try again.",
+                    dir.display()
+                );
             }
         }
+        if gen_matches.subcommand_matches("compose").is_some() {
+            if let Some(dir) = roche::function_dir(&dirname) {
+                check_handler(&config, &dir);
+            }
+            let request = ComposeRequest {
+                name: service_name(&config, &dirname)?,
+                container_port: config.container_port.value,
+                host_port: config.host_port.value,
+                env_files: config
+                    .env_files
+                    .value
+                    .iter()
+                    .filter(|file| dirname.join(file).exists())
+                    .map(|file| file.strip_prefix(&dirname).unwrap_or(file).to_path_buf())
+                    .collect(),
+                services: config.services.clone(),
+            };
+            let compose = request.render()?;
+            gen_dockerfile(&config, &dirname, gen_matches)?;
+            let path = Path::new(roche::compose::COMPOSE_FILE);
+            if !path.exists() {
+                std::fs::write(path, compose)?;
+                println!("Roche: Wrote {} for {}", path.display(), request.name);
+            } else {
+                println!(
+                    "{} already exists refusing to overwrite it. Please delete it and try again.",
+                    path.display()
+                );
+            }
+        }
+    }
 
-        if let Some(build_matches) = matches.subcommand_matches("release") {
-            let mut tag = format!("-t{}", build_matches.value_of("tag").unwrap_or(""));
-
-            //let mut tag = build_matches.value_of("tag").unwrap_or("").to_string();
+    if let Some(gen_matches) = matches
+        .subcommand_matches("gen")
+        .filter(|gen_matches| gen_matches.subcommand_name().is_none())
+    {
+        if let Some(dir) = roche::function_dir(&dirname) {
+            check_handler(&config, &dir);
+        }
+        gen_dockerfile(&config, &dirname, gen_matches)?;
+    }
 
-            if tag == "-t" {
-                tag = match generateimagetag("".to_string()) {
-                    Some(s) => {
-                        println!("No tag provided using {}", s);
-                        format!("-t{}", s)
-                    }
-                    None => {
-                        panic!("No tag provided and couldn't generate a tag. Please check you have logged into docker or podman")
-                    }
+    if let Some(deploy_matches) = matches.subcommand_matches("deploy") {
+        match deploy_matches.subcommand_matches("knative") {
+            Some(knative_matches) => {
+                let request =
+                    deploy_request(&config, &dirname, Platform::Knative, knative_matches)?;
+                if let Err(e) = request.deploy_knative() {
+                    exit_with(e);
                 }
             }
+            None => println!("No deploy platform was used - try 'roche deploy knative'"),
+        }
+    }
 
-            let buildimage = build_matches
-                .value_of("buildimage")
-                .unwrap_or(release_build_image.as_str());
-            let runtimeimage = build_matches
-                .value_of("runtimeimage")
-                .unwrap_or(runtime_image.as_str());
+    if let Some(routes_matches) = matches.subcommand_matches("routes") {
+        let dir = context_dir(&dirname);
+        let routes: Vec<roche::handler::Route> = roche::handler::check(&dir, &config.functions)?
+            .into_iter()
+            .map(|route| route.relative_to(&dir))
+            .collect();
+        if routes_matches.is_present("json") {
+            print!("{}", roche::handler::json(&routes)?);
+        } else {
+            print!("{}", roche::handler::table(&routes));
+        }
+    }
 
-            let mut tmp_docker_file = str::replace(RELEASE_BUILD, "BASE_IMAGE", buildimage);
+    if matches.subcommand_matches("openapi").is_some() {
+        let dir = context_dir(&dirname);
+        let title = match &config.tag_name {
+            Some(name) => name.value.clone(),
+            None => roche::project_name(&dir).unwrap_or_default(),
+        };
+        let version = match &config.tag_version {
+            Some(version) => version.value.clone(),
+            None => "0.1.0".to_string(),
+        };
+        print!(
+            "{}",
+            roche::openapi::generate(&dir, &config.functions, &title, &version)?
+        );
+    }
 
-            if Path::new("lib.rs").exists() {
-                tmp_docker_file = str::replace(
-                    tmp_docker_file.as_str(),
-                    "#LIB_RS",
-                    "COPY lib.rs /app-build/src",
-                );
-                tmp_docker_file = str::replace(
-                    tmp_docker_file.as_str(),
-                    "#TEST",
-                    "RUN cargo test --lib --release",
+    if let Some(eject_matches) = matches.subcommand_matches("eject") {
+        let dir = context_dir(&dirname);
+        match roche::service::eject(&dir, eject_matches.is_present("force")) {
+            Ok(paths) => {
+                for path in paths {
+                    println!("Roche: Wrote {}", path.display());
+                }
+                println!(
+                    "Roche: Builds now use this roche-service {} crate instead of the one in the base image",
+                    roche::service::SERVICE_VERSION
                 );
             }
-            if Path::new(".env").exists() {
-                tmp_docker_file =
-                    str::replace(tmp_docker_file.as_str(), "#ENV", "COPY .env /app-build/src");
-            }
+            Err(e) => exit_with(e),
+        }
+    }
 
-            tmp_docker_file = str::replace(tmp_docker_file.as_str(), "RUNTIME_IMAGE", runtimeimage);
+    if let Some(config_matches) = matches.subcommand_matches("config") {
+        if let Some(show_matches) = config_matches.subcommand_matches("show") {
+            let config = config.with_cli(&Cli {
+                engine: show_matches.value_of("engine"),
+                release_build_image: show_matches.value_of("buildimage"),
+                runtime_image: show_matches.value_of("runtimeimage"),
+                registry: show_matches.value_of("registry"),
+                scanner: show_matches.value_of("scanner"),
+            })?;
+            match &config.manifest_path {
+                Some(path) => println!("# Using {}", path.display()),
+                None => println!("# No {} found, using defaults", roche::config::MANIFEST),
+            }
+            print!("{}", config);
+        } else {
+            println!("No config subcommand was used - try 'roche config show'");
+        }
+    }
+    Ok(())
+}
 
-            let process = match Command::new("docker")
-                .stdin(Stdio::piped())
-                .stdout(Stdio::piped())
-                .arg("build")
-                .arg(&tag)
-                .arg("-f-")
-                .arg(".")
-                .spawn()
-            {
-                Err(why) => {
-                    println!("couldn't spawn docker: {}", why);
-                    process::exit(1);
-                }
-                Ok(process) => process,
+/// Generates a project from a template, or just a functions.rs when no template is given.
+fn init(dirname: &Path, init_matches: &ArgMatches) -> Result<()> {
+    let template = init_matches.value_of("template").unwrap_or_default();
+    match init::template_location(template) {
+        Some(git) => {
+            let name = init_matches.value_of("name").map(ToOwned::to_owned);
+            let branch = match init_matches.value_of("branch").map(ToOwned::to_owned) {
+                Some(b) => b,
+                None => String::from("main"),
             };
 
-            match process.stdin.unwrap().write_all(tmp_docker_file.as_bytes()) {
-                Err(why) => panic!("couldn't write to docker stdin: {}", why),
-                Ok(_) => println!("Roche: Sent file to builder for {}", &tag),
-            }
-            let mut s = String::new();
-            match process.stdout.unwrap().read_to_string(&mut s) {
-                Err(why) => panic!("couldn't read docker stdout: {}", why),
-                Ok(_) => print!("Roche: Build complete for {}\n{}", &tag, s),
-            }
+            let force: bool = init_matches.value_of_t("force").unwrap_or(false);
+            let verbose: bool = init_matches.value_of_t("verbose").unwrap_or(false);
+
+            init::generate_project(PublicArgs {
+                git,
+                branch: Some(branch),
+                name,
+                force,
+                verbose,
+            })
         }
+        // init called but with no options so just generating a function.
+        None => init::generate_function(dirname),
     }
-    if matches.is_present("init") {
-        if let Some(init_matches) = matches.subcommand_matches("init") {
-            match init_matches.value_of("template").unwrap_or_default() {
-                "default" => {
-                    let name = init_matches.value_of("name").map(ToOwned::to_owned);
-                    let branch = match init_matches.value_of("branch").map(ToOwned::to_owned) {
-                        Some(b) => b,
-                        None => String::from("main"),
-                    };
-
-                    let force: bool = init_matches.value_of_t("force").unwrap_or(false);
-                    let verbose: bool = init_matches.value_of_t("verbose").unwrap_or(false);
-
-                    let args_exposed: PublicArgs = PublicArgs {
-                        git: default_project.to_string(),
-                        branch: Some(branch),
-                        name,
-                        force,
-                        verbose,
-                    };
-
-                    let args: Args = args_exposed.into();
-
-                    generate(args)?
-                }
-                "mongodb" => {
-                    let name = init_matches.value_of("name").map(ToOwned::to_owned);
-                    let branch = match init_matches.value_of("branch").map(ToOwned::to_owned) {
-                        Some(b) => b,
-                        None => String::from("main"),
-                    };
-
-                    let force: bool = init_matches.value_of_t("force").unwrap_or(false);
-                    let verbose: bool = init_matches.value_of_t("verbose").unwrap_or(false);
-
-                    let args_exposed: PublicArgs = PublicArgs {
-                        git: mongodb_project.to_string(),
-                        branch: Some(branch),
-                        name,
-                        force,
-                        verbose,
-                    };
-
-                    let args: Args = args_exposed.into();
-
-                    generate(args)?
-                }
-                &_ => {
-                    if init_matches
-                        .value_of("template")
-                        .unwrap_or_default()
-                        .contains("https://")
-                    {
-                        let name = init_matches.value_of("name").map(ToOwned::to_owned);
-                        let branch = match init_matches.value_of("branch").map(ToOwned::to_owned) {
-                            Some(b) => b,
-                            None => String::from("main"),
-                        };
-
-                        let force: bool = init_matches.value_of_t("force").unwrap_or(false);
-                        let verbose: bool = init_matches.value_of_t("verbose").unwrap_or(false);
-
-                        let args_exposed: PublicArgs = PublicArgs {
-                            git: init_matches
-                                .value_of("template")
-                                .unwrap_or_default()
-                                .to_string(),
-                            branch: Some(branch),
-                            name,
-                            force,
-                            verbose,
-                        };
-                        let args: Args = args_exposed.into();
-
-                        generate(args)?
-                    } else {
-                        // init called but with no options so just generating a function.
-                        let mut file = File::create("functions.rs")?;
-                        file.write_all(FUNCTION.as_bytes())?
-                    }
-                }
-            }
+}
+
+/// The folder holding functions.rs, exiting if there isn't one.
+fn context_dir(dirname: &Path) -> PathBuf {
+    match roche::function_dir(dirname) {
+        Some(dir) => dir,
+        None => {
+            println!("Cannot find functions.rs or a functions folder in the current folder or in src subfolder. Exiting");
+            process::exit(1);
         }
     }
-    if matches.is_present("gen") {
-        if let Some(build_matches) = matches.subcommand_matches("gen") {
-            let buildimage = build_matches
-                .value_of("buildimage")
-                .unwrap_or(release_build_image.as_str());
-            let runtimeimage = build_matches
-                .value_of("runtimeimage")
-                .unwrap_or(runtime_image.as_str());
-            let mut tmp_docker_file = str::replace(RELEASE_BUILD, "BASE_IMAGE", buildimage);
-            tmp_docker_file = str::replace(tmp_docker_file.as_str(), "RUNTIME_IMAGE", runtimeimage);
-            if Path::new("lib.rs").exists() {
-                tmp_docker_file = str::replace(
-                    tmp_docker_file.as_str(),
-                    "#LIB_RS",
-                    "COPY lib.rs /app-build/src",
-                );
-                tmp_docker_file = str::replace(
-                    tmp_docker_file.as_str(),
-                    "#TEST",
-                    "RUN cargo test --lib --release",
-                );
-            }
-            if Path::new(".env").exists() {
-                tmp_docker_file =
-                    str::replace(tmp_docker_file.as_str(), "#ENV", "COPY .env /app-build/src");
-            }
-            if !Path::new("Dockerfile").exists() {
-                let mut file = File::create("Dockerfile")?;
-                file.write_all(tmp_docker_file.as_bytes())?;
-            } else {
-                println!("Dockerfile already exists refusing to overwrite it. Please delete it and try again.");
+}
+
+/// Checks the handler in `dir` before a slow container build and prints the routes it serves.
+fn check_handler(config: &Config, dir: &Path) {
+    match roche::handler::check(dir, &config.functions) {
+        Ok(routes) if routes.is_empty() => {
+            println!("Roche: No api.at(...) routes found in handler()")
+        }
+        Ok(routes) => {
+            let routes: Vec<String> = routes.iter().map(ToString::to_string).collect();
+            println!("Roche: Routes {}", routes.join(", "));
+        }
+        Err(e) => exit_with(e),
+    }
+}
+
+/// Locates the function and resolves the engine, images and tag for a build subcommand.
+fn build_request(
+    config: &Config,
+    dirname: &Path,
+    kind: BuildKind,
+    build_matches: &ArgMatches,
+    image_arg: &str,
+) -> Result<(Box<dyn ContainerEngine>, BuildRequest)> {
+    let context_dir = context_dir(dirname);
+    if kind == BuildKind::Test && !context_dir.join("lib.rs").exists() {
+        println!("Cannot find lib.rs in the src folder. Exiting");
+        process::exit(1);
+    }
+
+    let engine = config.engine(build_matches.value_of("engine"))?;
+    let tag = image_tag(config, &context_dir, kind, engine.as_ref(), build_matches)?;
+    let platforms = match build_matches.value_of("platform") {
+        Some(list) => roche::build::parse_platforms(list)?,
+        None if kind == BuildKind::Release => config.platforms.clone(),
+        None => Vec::new(),
+    };
+
+    let request = BuildRequest {
+        kind,
+        build_image: config
+            .build_image(kind, build_matches.value_of(image_arg))
+            .value,
+        runtime_image: config
+            .runtime_image
+            .or_cli(build_matches.value_of("runtimeimage"))
+            .value,
+        tag,
+        context_dir,
+        build_args: config.build_args.clone(),
+        dependencies: config.dependencies.clone(),
+        functions: config.functions.clone(),
+        platforms,
+        push: build_matches.is_present("push"),
+        cache: config.build_cache(),
+        secrets: config.secrets.clone(),
+        sbom: build_matches.is_present("sbom") || build_matches.is_present("attach-sbom"),
+    };
+    Ok((engine, request))
+}
+
+/// The `--tag` given on the command line, or one generated for a `kind` build using `--registry`
+/// when it is given.
+fn image_tag(
+    config: &Config,
+    context_dir: &Path,
+    kind: BuildKind,
+    engine: &dyn ContainerEngine,
+    matches: &ArgMatches,
+) -> Result<String> {
+    if let Some(t) = matches.value_of("tag") {
+        return Ok(t.to_string());
+    }
+    let config = config.clone().with_cli(&Cli {
+        registry: matches.value_of("registry"),
+        ..Default::default()
+    })?;
+    match config.image_tag(kind, context_dir, engine) {
+        Some(s) => {
+            println!("No tag provided using {}", s);
+            Ok(s)
+        }
+        None => anyhow::bail!(
+            "No tag provided and couldn't generate a tag. Please pass -t or log in to docker or podman"
+        ),
+    }
+}
+
+/// Pushes `tag`, or the manifest list `tag` when `manifest` is set, to its registry, exiting when
+/// the push fails.
+fn push(engine: &dyn ContainerEngine, tag: &str, manifest: bool) {
+    let request = PushRequest {
+        tag: tag.to_string(),
+        manifest,
+    };
+    if let Err(e) = request.execute(engine) {
+        exit_with(e);
+    }
+}
+
+/// Scans `tag` from `source` against the configured threshold, or `severity` when given, exiting
+/// when the image fails the scan.
+fn scan(
+    config: &Config,
+    scanner: &dyn Scanner,
+    tag: &str,
+    source: ImageSource,
+    severity: Option<&str>,
+) -> Result<()> {
+    let threshold = match severity {
+        Some(severity) => severity.parse()?,
+        None => config.scan_severity.value,
+    };
+    let request = ScanRequest {
+        tag: tag.to_string(),
+        source,
+        threshold,
+        ignore: config.scan_ignore.iter().cloned().collect(),
+    };
+    if let Err(e) = request.execute(scanner) {
+        exit_with(e);
+    }
+    Ok(())
+}
+
+/// Describes how to run the function in `context_dir`, publishing it on `--port` if given.
+fn run_request(
+    config: &Config,
+    context_dir: &Path,
+    tag: &str,
+    run_matches: &ArgMatches,
+) -> Result<RunRequest> {
+    let host_port = match run_matches.value_of("port") {
+        Some(p) => p.parse().with_context(|| format!("Invalid port '{}'", p))?,
+        None => config.host_port.value,
+    };
+    Ok(RunRequest {
+        tag: tag.to_string(),
+        name: roche::run::container_name(context_dir),
+        host_port,
+        container_port: config.container_port.value,
+        env_files: config
+            .env_files
+            .value
+            .iter()
+            .map(|file| context_dir.join(file))
+            .collect(),
+    })
+}
+
+/// Writes the release Dockerfile for the function unless there already is one.
+fn gen_dockerfile(config: &Config, dirname: &Path, gen_matches: &ArgMatches) -> Result<()> {
+    let request = BuildRequest {
+        kind: BuildKind::Release,
+        build_image: config
+            .build_image(BuildKind::Release, gen_matches.value_of("buildimage"))
+            .value,
+        runtime_image: config
+            .runtime_image
+            .or_cli(gen_matches.value_of("runtimeimage"))
+            .value,
+        context_dir: dirname.to_path_buf(),
+        build_args: config.build_args.clone(),
+        dependencies: config.dependencies.clone(),
+        functions: config.functions.clone(),
+        cache: config.build_cache(),
+        secrets: config.secrets.clone(),
+        ..Default::default()
+    };
+    request.check_image()?;
+    if !Path::new("Dockerfile").exists() {
+        std::fs::write("Dockerfile", request.render_dockerfile()?)?;
+    } else {
+        println!(
+            "Dockerfile already exists refusing to overwrite it. Please delete it and try again."
+        );
+    }
+    Ok(())
+}
+
+/// The arguments shared by the subcommands that generate or apply a deployment.
+fn deploy_args(app: App) -> App {
+    app.arg(
+        Arg::new("target")
+            .about("[deploy.<target>] in roche.toml to use. Defaults to the only target for the platform")
+            .takes_value(true)
+            .long("target")
+            .required(false),
+    )
+    .arg(
+        Arg::new("tag")
+            .about("image to deploy. If not provided the release tag from roche.toml or the engine login is used")
+            .takes_value(true)
+            .short('t')
+            .long("tag")
+            .required(false),
+    )
+    .arg(
+        Arg::new("engine")
+            .about("container engine whose login names the image when no tag is given")
+            .takes_value(true)
+            .short('e')
+            .long("engine")
+            .required(false),
+    )
+}
+
+/// The service name of the function: its tag name, or the project folder name.
+fn service_name(config: &Config, dirname: &Path) -> Result<String> {
+    let name = match &config.tag_name {
+        Some(name) => name.value.clone(),
+        None => roche::project_name(&context_dir(dirname)).unwrap_or_default(),
+    };
+    roche::deploy::service_name(&name)
+}
+
+/// Describes deploying the release image of the function to `platform`.
+fn deploy_request(
+    config: &Config,
+    dirname: &Path,
+    platform: Platform,
+    deploy_matches: &ArgMatches,
+) -> Result<DeployRequest> {
+    let context_dir = context_dir(dirname);
+    let image = match deploy_matches.value_of("tag") {
+        Some(t) => t.to_string(),
+        None => {
+            let engine = config.engine(deploy_matches.value_of("engine"))?;
+            match config.image_tag(BuildKind::Release, &context_dir, engine.as_ref()) {
+                Some(tag) => tag,
+                None => anyhow::bail!("No tag provided and couldn't generate one. Please pass -t or log in to docker or podman"),
             }
         }
+    };
+    Ok(DeployRequest {
+        name: service_name(config, dirname)?,
+        image,
+        container_port: config.container_port.value,
+        target: roche::deploy::target(&config.deploy, platform, deploy_matches.value_of("target"))?,
+    })
+}
+
+/// Builds the release natively and writes its image as an OCI tarball, for `release --oci`.
+fn oci_release(config: &Config, dirname: &Path, release_matches: &ArgMatches) -> Result<()> {
+    let output = PathBuf::from(release_matches.value_of("oci").unwrap_or_default());
+    if release_matches.is_present("push") {
+        anyhow::bail!(
+            "--push needs a container engine. Please push the tarball with 'skopeo copy oci-archive:{} docker://<tag>'",
+            output.display()
+        );
+    }
+    if release_matches.is_present("platform") {
+        anyhow::bail!("--oci builds for a single platform. Please pick it with --rust-target instead of --platform");
+    }
+    if release_matches.is_present("attach-sbom") {
+        anyhow::bail!(
+            "--attach-sbom needs the image in its registry. Please push the tarball, then run 'roche sbom --attach'"
+        );
+    }
+    let scanner = if release_matches.is_present("scan") {
+        Some(config.scanner(release_matches.value_of("scanner"))?)
+    } else {
+        None
+    };
+    let tag = match release_matches.value_of("tag") {
+        Some(t) => t.to_string(),
+        None => {
+            let engine = config.engine(release_matches.value_of("engine")).context(
+                "No tag provided and no container engine to generate one. Please pass -t",
+            )?;
+            image_tag(
+                config,
+                &context_dir(dirname),
+                BuildKind::Release,
+                engine.as_ref(),
+                release_matches,
+            )?
+        }
+    };
+    let target = release_matches
+        .value_of("rust-target")
+        .map_or_else(roche::oci::default_target, str::to_string);
+    roche::oci::image_platform(&target)?;
+    let native = NativeRequest {
+        release: true,
+        target: Some(target.clone()),
+        ..native_request(config, dirname)?
+    };
+    let binary = native.execute()?;
+    let run_script = if roche::service::ejected(&native.context_dir) {
+        let file = native.context_dir.join("run.sh");
+        std::fs::read_to_string(&file)
+            .with_context(|| format!("Couldn't read {}", file.display()))?
+    } else {
+        roche::service::render_run()?
+    };
+    let oci = OciRequest {
+        tag: tag.clone(),
+        runtime_image: config
+            .runtime_image
+            .or_cli(release_matches.value_of("runtimeimage"))
+            .value,
+        binary,
+        target: Some(target),
+        run_script,
+        container_port: config.container_port.value,
+        cache_dir: native.cache_dir.clone(),
+        output: output.clone(),
+    };
+    let image = oci.execute()?;
+    if release_matches.is_present("sbom") {
+        let lock = native.crate_dir().join("Cargo.lock");
+        let text = |file: Option<Vec<u8>>| file.map(|f| String::from_utf8_lossy(&f).to_string());
+        let mut runtime = image
+            .runtime_files(&[sbom::APK_DB, sbom::DPKG_STATUS, sbom::OS_RELEASE])?
+            .into_iter()
+            .map(text);
+        let files = ImageFiles {
+            lock: Some(
+                std::fs::read_to_string(&lock)
+                    .with_context(|| format!("Couldn't read {}", lock.display()))?,
+            ),
+            apk_db: runtime.next().flatten(),
+            dpkg_status: runtime.next().flatten(),
+            os_release: runtime.next().flatten(),
+        };
+        SbomRequest {
+            tag: tag.clone(),
+            runtime_image: oci.runtime_image.clone(),
+            formats: Format::ALL.to_vec(),
+            output: output.with_extension(""),
+            attach: false,
+        }
+        .execute(&files)?;
+    }
+    if let Some(scanner) = scanner {
+        scan(
+            config,
+            scanner.as_ref(),
+            &tag,
+            ImageSource::Archive(output),
+            None,
+        )?;
     }
     Ok(())
 }
+
+/// Describes a host build of the function, for `--native`.
+fn native_request(config: &Config, dirname: &Path) -> Result<NativeRequest> {
+    Ok(NativeRequest {
+        context_dir: context_dir(dirname),
+        cache_dir: roche::native::cache_dir()?,
+        release: false,
+        target: None,
+        dependencies: config.dependencies.clone(),
+        functions: config.functions.clone(),
+    })
+}
+
+/// Prints `e` and exits, handing a failed build's engine exit code on so CI sees the same status.
+fn exit_with(e: anyhow::Error) -> ! {
+    eprintln!("Error: {:?}", e);
+    let code = match e.downcast_ref::<BuildFailed>() {
+        Some(failed) => failed.code.unwrap_or(1),
+        None => 1,
+    };
+    process::exit(code);
+}
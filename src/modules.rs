@@ -0,0 +1,179 @@
+//! Projects with several handlers.
+//!
+//! Instead of a single `functions.rs`, a project can keep one module per handler in a
+//! `functions/` folder. roche generates the `functions.rs` the service expects, nesting each
+//! module's `handler()` under its own path prefix: `/<module>` unless `[functions]` in
+//! `roche.toml` says otherwise.
+
+use anyhow::{bail, Context, Result};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Folder, next to where `functions.rs` would be, holding one module per handler.
+pub const FUNCTIONS_DIR: &str = "functions";
+
+/// A handler module and the prefix it is mounted at.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Module {
+    /// The module name, which is the file name in `functions/` without `.rs`.
+    pub name: String,
+    pub prefix: String,
+}
+
+impl Module {
+    /// The module's source file in the function folder `dir`.
+    pub fn path(&self, dir: &Path) -> PathBuf {
+        dir.join(FUNCTIONS_DIR).join(format!("{}.rs", self.name))
+    }
+}
+
+/// Whether `dir` holds a multi-function project: a `functions/` folder and no `functions.rs`.
+pub fn is_multi(dir: &Path) -> bool {
+    !dir.join("functions.rs").exists() && dir.join(FUNCTIONS_DIR).is_dir()
+}
+
+/// The modules of the multi-function project in `dir`, or `None` for a single `functions.rs`.
+///
+/// `prefixes` maps module names to mount prefixes. When it is empty every module in
+/// `functions/` is mounted at `/<module>`, otherwise exactly the listed modules are mounted.
+pub fn modules(dir: &Path, prefixes: &BTreeMap<String, String>) -> Result<Option<Vec<Module>>> {
+    if !is_multi(dir) {
+        return Ok(None);
+    }
+    let folder = dir.join(FUNCTIONS_DIR);
+    let modules: Vec<Module> = if prefixes.is_empty() {
+        let mut names = Vec::new();
+        for entry in
+            fs::read_dir(&folder).with_context(|| format!("Couldn't read {}", folder.display()))?
+        {
+            let path = entry?.path();
+            if path.extension().is_some_and(|ext| ext == "rs") {
+                if let Some(name) = path.file_stem().and_then(|stem| stem.to_str()) {
+                    names.push(name.to_string());
+                }
+            }
+        }
+        names.sort();
+        names
+            .into_iter()
+            .map(|name| Module {
+                prefix: format!("/{}", name),
+                name,
+            })
+            .collect()
+    } else {
+        prefixes
+            .iter()
+            .map(|(name, prefix)| Module {
+                name: name.clone(),
+                prefix: prefix.clone(),
+            })
+            .collect()
+    };
+
+    if modules.is_empty() {
+        bail!(
+            "{} has no modules. Add a .rs file with a handler()",
+            folder.display()
+        );
+    }
+    for module in &modules {
+        check_name(&module.name)?;
+        if !module.path(dir).exists() {
+            bail!(
+                "function '{}' is listed in roche.toml but {} does not exist",
+                module.name,
+                module.path(dir).display()
+            );
+        }
+    }
+    check_routes(&modules)?;
+    Ok(Some(modules))
+}
+
+/// Checks `prefix` can be used to mount a handler.
+pub fn check_prefix(prefix: &str) -> Result<()> {
+    if !prefix.starts_with('/') || prefix.contains(char::is_whitespace) || prefix.contains('*') {
+        bail!(
+            "prefix '{}' must start with '/' and contain no spaces or wildcards",
+            prefix
+        );
+    }
+    Ok(())
+}
+
+/// Checks no two modules are mounted at the same prefix.
+pub fn check_routes(modules: &[Module]) -> Result<()> {
+    let mut seen: BTreeMap<String, &str> = BTreeMap::new();
+    for module in modules {
+        check_prefix(&module.prefix)
+            .with_context(|| format!("Invalid prefix for function '{}'", module.name))?;
+        if let Some(other) = seen.insert(normalize(&module.prefix), &module.name) {
+            bail!(
+                "route collision: functions '{}' and '{}' are both mounted at {}",
+                other,
+                module.name,
+                module.prefix
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Renders the `functions.rs` that nests every module's handler under its prefix.
+///
+/// With `dir` set the modules are included from their files there, for builds outside the
+/// image's `src` folder.
+pub fn render_functions(modules: &[Module], dir: Option<&Path>) -> String {
+    let mut out = format!(
+        "// Generated by roche from the modules in {}/.\n",
+        FUNCTIONS_DIR
+    );
+    for module in modules {
+        if let Some(dir) = dir {
+            out.push_str(&format!(
+                "#[path = {:?}]\n",
+                module.path(dir).display().to_string()
+            ));
+        }
+        out.push_str(&format!("pub mod {};\n", module.name));
+    }
+    out.push_str("\npub fn handler() -> tide::Server<()> {\n    let mut api = tide::new();\n");
+    for module in modules {
+        out.push_str(&format!(
+            "    api.at({:?}).nest({}::handler());\n",
+            module.prefix, module.name
+        ));
+    }
+    out.push_str("    api\n}\n");
+    out
+}
+
+fn check_name(name: &str) -> Result<()> {
+    let valid = name
+        .chars()
+        .next()
+        .is_some_and(|c| c.is_ascii_lowercase() || c == '_')
+        && name
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_')
+        && name != "mod";
+    if !valid {
+        bail!(
+            "function '{}' must be a lowercase rust module name like 'users' or 'order_items'",
+            name
+        );
+    }
+    Ok(())
+}
+
+/// Drops a trailing `/` so `/users` and `/users/` compare equal.
+fn normalize(prefix: &str) -> String {
+    let trimmed = prefix.trim_end_matches('/');
+    if trimmed.is_empty() {
+        "/".to_string()
+    } else {
+        trimmed.to_string()
+    }
+}
@@ -0,0 +1,228 @@
+//! Building and running functions with the host toolchain instead of a container engine.
+//!
+//! The base images wrap `functions.rs` in a small tide service. A [`NativeRequest`] writes the
+//! same [service crate](crate::service) to a cache folder and builds it with the local `cargo`.
+
+use crate::build::BuildFailed;
+use crate::modules;
+use crate::run::RunRequest;
+use crate::service::{self, SERVICE_BINARY};
+use anyhow::{bail, Context, Result};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// The folder roche caches native builds in.
+///
+/// Uses the `cache_dir` environment variable (or `.rocherc` key), then `$XDG_CACHE_HOME/roche`
+/// and finally `$HOME/.cache/roche`.
+pub fn cache_dir() -> Result<PathBuf> {
+    if let Ok(dir) = env::var("cache_dir") {
+        return Ok(PathBuf::from(dir));
+    }
+    if let Ok(dir) = env::var("XDG_CACHE_HOME") {
+        return Ok(Path::new(&dir).join("roche"));
+    }
+    match env::var("HOME") {
+        Ok(home) => Ok(Path::new(&home).join(".cache").join("roche")),
+        Err(_) => bail!("Couldn't find a cache folder. Please set cache_dir in .rocherc"),
+    }
+}
+
+/// Describes a host build of the function in `context_dir`.
+#[derive(Debug, Clone, Default)]
+pub struct NativeRequest {
+    /// The folder holding `functions.rs`.
+    pub context_dir: PathBuf,
+    /// Where the wrapper crates are written, usually [`cache_dir`].
+    pub cache_dir: PathBuf,
+    /// Builds with `--release` when set.
+    pub release: bool,
+    /// Target triple to cross compile for, e.g. `x86_64-unknown-linux-musl` for the alpine
+    /// runtime image. Defaults to the host.
+    pub target: Option<String>,
+    /// Crates added to the service `Cargo.toml`, as they would appear under `[dependencies]`.
+    pub dependencies: BTreeMap<String, toml::Value>,
+    /// Mount prefixes of the modules in `functions/`, keyed by module name.
+    pub functions: BTreeMap<String, String>,
+}
+
+impl NativeRequest {
+    /// The wrapper crate for this project, unique to the function folder. The folder is named
+    /// after a SHA-256 of the path so it stays the same from one roche build to the next.
+    pub fn crate_dir(&self) -> PathBuf {
+        let digest = Sha256::digest(self.context_dir.to_string_lossy().as_bytes());
+        let project = crate::project_name(&self.context_dir).unwrap_or_default();
+        self.cache_dir.join("native").join(format!(
+            "{}-{}",
+            project,
+            &format!("{:x}", digest)[..16]
+        ))
+    }
+
+    /// The compiled service binary.
+    pub fn binary(&self) -> PathBuf {
+        let profile = if self.release { "release" } else { "debug" };
+        let mut dir = self.crate_dir().join("target");
+        if let Some(target) = &self.target {
+            dir.push(target);
+        }
+        dir.join(profile).join(SERVICE_BINARY)
+    }
+
+    /// Renders the service `main.rs`, which includes `functions.rs` from where it lives so
+    /// compile errors point at the user's file. A multi-function project uses the generated
+    /// `functions.rs` beside it instead.
+    pub fn render_main(&self) -> Result<String> {
+        if modules::is_multi(&self.context_dir) {
+            service::render_main(None)
+        } else {
+            service::render_main(Some(&self.context_dir.join("functions.rs")))
+        }
+    }
+
+    /// Renders the service `Cargo.toml`, using the ejected one next to `functions.rs` if there
+    /// is one, with the extra dependencies merged in.
+    pub fn render_manifest(&self) -> Result<String> {
+        let name = "service Cargo.toml";
+        let source = match service::manifest(&self.context_dir, &self.dependencies)? {
+            Some(manifest) => manifest,
+            None => service::render_manifest("src/main.rs")?,
+        };
+        let mut manifest: toml::Value =
+            toml::from_str(&source).with_context(|| format!("Invalid {}", name))?;
+        let table = match manifest.as_table_mut() {
+            Some(table) => table,
+            None => bail!("Invalid {}", name),
+        };
+
+        // An ejected main.rs is built where it is, so its `mod functions` finds functions.rs.
+        if service::ejected(&self.context_dir) {
+            let main = self.context_dir.join("main.rs").display().to_string();
+            let bin = table
+                .get_mut("bin")
+                .and_then(|bin| bin.as_array_mut())
+                .and_then(|bin| bin.first_mut())
+                .and_then(|bin| bin.as_table_mut());
+            match bin {
+                Some(bin) => {
+                    bin.insert("path".to_string(), main.into());
+                }
+                None => bail!("{} has no [[bin]] section", name),
+            }
+        }
+        // Keep the service out of any workspace the cache folder happens to live in.
+        table.insert(
+            "workspace".to_string(),
+            toml::Value::Table(Default::default()),
+        );
+        Ok(toml::to_string(&manifest)?)
+    }
+
+    /// Writes the wrapper crate to [`crate_dir`](Self::crate_dir), leaving unchanged files alone
+    /// so cargo can reuse its previous build.
+    pub fn write_crate(&self) -> Result<PathBuf> {
+        let dir = self.crate_dir();
+        let src = dir.join("src");
+        fs::create_dir_all(&src).with_context(|| format!("Couldn't create {}", src.display()))?;
+        write_if_changed(&dir.join("Cargo.toml"), &self.render_manifest()?)?;
+        if let Some(modules) = modules::modules(&self.context_dir, &self.functions)? {
+            if service::ejected(&self.context_dir) {
+                bail!("--native can't build an ejected service for a functions/ folder. Please add a functions.rs");
+            }
+            let functions = modules::render_functions(&modules, Some(&self.context_dir));
+            write_if_changed(&src.join("functions.rs"), &functions)?;
+        }
+        let main = src.join("main.rs");
+        if service::ejected(&self.context_dir) {
+            if main.exists() {
+                fs::remove_file(&main)
+                    .with_context(|| format!("Couldn't remove {}", main.display()))?;
+            }
+        } else {
+            write_if_changed(&main, &self.render_main()?)?;
+        }
+        Ok(dir)
+    }
+
+    /// Compiles the service with the host cargo and returns the path of the binary.
+    pub fn execute(&self) -> Result<PathBuf> {
+        let dir = self.write_crate()?;
+        let mut command = Command::new("cargo");
+        command
+            .arg("build")
+            .arg("--manifest-path")
+            .arg(dir.join("Cargo.toml"))
+            .arg("--target-dir")
+            .arg(dir.join("target"));
+        if self.release {
+            command.arg("--release");
+        }
+        if let Some(target) = &self.target {
+            command.arg("--target").arg(target);
+        }
+        println!(
+            "Roche: Building {} natively in {}",
+            self.function_name(),
+            dir.display()
+        );
+        let status = command
+            .status()
+            .context("Couldn't run cargo. Please check rust is installed")?;
+        if !status.success() {
+            return Err(BuildFailed {
+                tag: self.function_name(),
+                engine: "cargo",
+                code: status.code(),
+                step: None,
+            }
+            .into());
+        }
+
+        let binary = self.binary();
+        println!("Roche: Build complete for {}", binary.display());
+        Ok(binary)
+    }
+
+    /// Builds the service and runs it on the host port of `run` until it exits.
+    pub fn run(&self, run: &RunRequest) -> Result<()> {
+        let binary = self.execute()?;
+        let mut command = Command::new(&binary);
+        command
+            .current_dir(&self.context_dir)
+            .env("PORT", run.host_port.to_string());
+        // The service inherits our environment, so loading the files here passes them on.
+        for file in run.env_files.iter().filter(|file| file.exists()) {
+            dotenv::from_path(file).with_context(|| format!("Couldn't load {}", file.display()))?;
+        }
+
+        println!(
+            "Roche: Running {} on http://localhost:{}/",
+            binary.display(),
+            run.host_port
+        );
+        // Ctrl-C reaches the service too, so wait for it to shut down rather than exiting first.
+        ctrlc::set_handler(|| {}).context("Couldn't install the Ctrl-C handler")?;
+        let status = command
+            .status()
+            .with_context(|| format!("Couldn't run {}", binary.display()))?;
+        if !status.success() && status.code().is_some() {
+            bail!("{} exited with {}", SERVICE_BINARY, status);
+        }
+        Ok(())
+    }
+
+    fn function_name(&self) -> String {
+        crate::project_name(&self.context_dir).unwrap_or_else(|| SERVICE_BINARY.to_string())
+    }
+}
+
+fn write_if_changed(path: &Path, contents: &str) -> Result<()> {
+    if fs::read_to_string(path).ok().as_deref() == Some(contents) {
+        return Ok(());
+    }
+    fs::write(path, contents).with_context(|| format!("Couldn't write {}", path.display()))
+}
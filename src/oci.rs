@@ -0,0 +1,805 @@
+//! Daemonless assembly of release images.
+//!
+//! `roche release --oci` compiles the service with the host toolchain and writes the runtime
+//! image as an OCI image layout tarball, so no container engine or daemon is needed. The base
+//! layers come from the registry of `runtime_image`, or from a local OCI layout given as
+//! `oci:<dir>`. The tarball can be loaded with `docker load` or `podman load`, or pushed with
+//! `skopeo copy oci-archive:<file> docker://<tag>`.
+
+use crate::deploy::RUNTIME_UID;
+use anyhow::{bail, Context, Result};
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+/// Prefix of a `runtime_image` read from an OCI image layout folder instead of a registry.
+pub const LOCAL_PREFIX: &str = "oci:";
+
+const OCI_INDEX: &str = "application/vnd.oci.image.index.v1+json";
+const OCI_MANIFEST: &str = "application/vnd.oci.image.manifest.v1+json";
+const OCI_CONFIG: &str = "application/vnd.oci.image.config.v1+json";
+const OCI_LAYER: &str = "application/vnd.oci.image.layer.v1.tar+gzip";
+const DOCKER_LIST: &str = "application/vnd.docker.distribution.manifest.list.v2+json";
+const DOCKER_MANIFEST: &str = "application/vnd.docker.distribution.manifest.v2+json";
+
+/// Where the service lives in the image, as `WORKDIR` in the Dockerfile templates.
+const APP_DIR: &str = "app";
+
+/// Describes writing the release image of a compiled service as an OCI tarball.
+#[derive(Debug, Clone, Default)]
+pub struct OciRequest {
+    /// Name the image is tagged with inside the tarball.
+    pub tag: String,
+    /// Image the service is added to, a registry reference or `oci:<dir>`.
+    pub runtime_image: String,
+    /// The compiled `roche-service`, built for the runtime image's platform.
+    pub binary: PathBuf,
+    /// Rust target triple the service was compiled for, or `None` for the host.
+    pub target: Option<String>,
+    /// The `run.sh` that starts the service.
+    pub run_script: String,
+    /// Port the service listens on, set as `PORT` and exposed.
+    pub container_port: u16,
+    /// Where pulled base layers are kept, usually [`crate::native::cache_dir`].
+    pub cache_dir: PathBuf,
+    /// The tarball to write.
+    pub output: PathBuf,
+}
+
+impl OciRequest {
+    /// Assembles the image and writes the tarball.
+    pub fn execute(&self) -> Result<OciImage> {
+        let base = self.base()?;
+        let layer = self.service_layer(&base)?;
+        let layer_blob = gzip(&layer)?;
+
+        let mut config = base.config.clone();
+        self.configure(&mut config, &sha256(&layer))?;
+        let config_blob = serde_json::to_vec(&config).context("Couldn't serialize the config")?;
+
+        let mut layers = base.layers.clone();
+        layers.push(Descriptor::new(OCI_LAYER, &layer_blob));
+        let manifest = Manifest {
+            schema_version: 2,
+            media_type: OCI_MANIFEST.to_string(),
+            config: Descriptor::new(OCI_CONFIG, &config_blob),
+            layers,
+        };
+        let manifest_blob =
+            serde_json::to_vec(&manifest).context("Couldn't serialize the manifest")?;
+        let manifest_digest = format!("sha256:{}", sha256(&manifest_blob));
+
+        let mut annotations = BTreeMap::new();
+        annotations.insert("io.containerd.image.name".to_string(), self.tag.clone());
+        annotations.insert(
+            "org.opencontainers.image.ref.name".to_string(),
+            reference(&self.tag),
+        );
+        let index = Index {
+            schema_version: 2,
+            media_type: OCI_INDEX.to_string(),
+            manifests: vec![Descriptor {
+                annotations,
+                ..Descriptor::new(OCI_MANIFEST, &manifest_blob)
+            }],
+        };
+        // `docker load` before docker 25 only reads the docker archive manifest.
+        let docker = vec![DockerArchive {
+            config: blob_path(&manifest.config.digest),
+            repo_tags: vec![
+                if reference(&self.tag) == "latest" && !self.tag.ends_with(":latest") {
+                    format!("{}:latest", self.tag)
+                } else {
+                    self.tag.clone()
+                },
+            ],
+            layers: manifest
+                .layers
+                .iter()
+                .map(|layer| blob_path(&layer.digest))
+                .collect(),
+        }];
+
+        let file = File::create(&self.output)
+            .with_context(|| format!("Couldn't create {}", self.output.display()))?;
+        let mut archive = tar::Builder::new(file);
+        append(
+            &mut archive,
+            "oci-layout",
+            br#"{"imageLayoutVersion":"1.0.0"}"#,
+            0o644,
+            0,
+        )?;
+        append(
+            &mut archive,
+            "index.json",
+            &serde_json::to_vec(&index)?,
+            0o644,
+            0,
+        )?;
+        append(
+            &mut archive,
+            "manifest.json",
+            &serde_json::to_vec(&docker)?,
+            0o644,
+            0,
+        )?;
+        for layer in &base.layers {
+            let path = base.blob(&layer.digest);
+            let blob =
+                fs::read(&path).with_context(|| format!("Couldn't read {}", path.display()))?;
+            append(&mut archive, &blob_path(&layer.digest), &blob, 0o644, 0)?;
+        }
+        for (digest, blob) in [
+            (&manifest.layers.last().unwrap().digest, &layer_blob),
+            (&manifest.config.digest, &config_blob),
+            (&manifest_digest, &manifest_blob),
+        ]
+        .iter()
+        {
+            append(&mut archive, &blob_path(digest), blob, 0o644, 0)?;
+        }
+        archive
+            .into_inner()
+            .and_then(|mut file| file.flush())
+            .with_context(|| format!("Couldn't write {}", self.output.display()))?;
+        println!(
+            "Roche: Wrote {} for {}@{}",
+            self.output.display(),
+            self.tag,
+            manifest_digest
+        );
+        Ok(OciImage {
+            digest: manifest_digest,
+            base,
+        })
+    }
+
+    /// The base image, read from a local OCI layout or pulled into the cache.
+    fn base(&self) -> Result<Base> {
+        let platform = platform(self.target.as_deref())?;
+        match self.runtime_image.strip_prefix(LOCAL_PREFIX) {
+            Some(dir) => Base::local(Path::new(dir), &platform),
+            None => Base::pull(&self.runtime_image, &platform, &self.cache_dir.join("oci")),
+        }
+    }
+
+    /// The layer holding the service, `run.sh` and the `rocheuser` account.
+    fn service_layer(&self, base: &Base) -> Result<Vec<u8>> {
+        let binary = fs::read(&self.binary)
+            .with_context(|| format!("Couldn't read {}", self.binary.display()))?;
+        let mut passwd = base.file("etc/passwd")?.unwrap_or_default();
+        let mut group = base.file("etc/group")?.unwrap_or_default();
+        if !has_entry(&passwd, "rocheuser") {
+            add_line(
+                &mut passwd,
+                &format!(
+                    "rocheuser:x:{uid}:{uid}::/home/rocheuser:/sbin/nologin",
+                    uid = RUNTIME_UID
+                ),
+            );
+        }
+        if !has_entry(&group, "rocheuser") {
+            add_line(&mut group, &format!("rocheuser:x:{}:", RUNTIME_UID));
+        }
+
+        let mut layer = tar::Builder::new(Vec::new());
+        append(&mut layer, "etc/passwd", &passwd, 0o644, 0)?;
+        append(&mut layer, "etc/group", &group, 0o644, 0)?;
+        append_dir(&mut layer, "home/rocheuser/", RUNTIME_UID)?;
+        append_dir(&mut layer, &format!("{}/", APP_DIR), RUNTIME_UID)?;
+        append(
+            &mut layer,
+            &format!("{}/run.sh", APP_DIR),
+            self.run_script.as_bytes(),
+            0o755,
+            RUNTIME_UID,
+        )?;
+        append(
+            &mut layer,
+            &format!("{}/{}", APP_DIR, crate::service::SERVICE_BINARY),
+            &binary,
+            0o755,
+            RUNTIME_UID,
+        )?;
+        layer
+            .into_inner()
+            .context("Couldn't write the service layer")
+    }
+
+    /// Sets the user, port, working dir and command as the release Dockerfile does.
+    fn configure(&self, config: &mut serde_json::Value, diff_id: &str) -> Result<()> {
+        let root = match config.as_object_mut() {
+            Some(root) => root,
+            None => bail!("The config of {} is not an object", self.runtime_image),
+        };
+        let port = self.container_port;
+        let settings = root
+            .entry("config")
+            .or_insert_with(|| serde_json::json!({}));
+        if settings.is_null() {
+            *settings = serde_json::json!({});
+        }
+        let settings = settings.as_object_mut().unwrap();
+        let mut env: Vec<serde_json::Value> = settings
+            .get("Env")
+            .and_then(|env| env.as_array())
+            .cloned()
+            .unwrap_or_default();
+        env.retain(|var| !var.as_str().unwrap_or_default().starts_with("PORT="));
+        env.push(format!("PORT={}", port).into());
+        settings.insert("Env".to_string(), env.into());
+        settings.insert("User".to_string(), "rocheuser".into());
+        settings.insert("WorkingDir".to_string(), format!("/{}", APP_DIR).into());
+        settings.insert("Entrypoint".to_string(), serde_json::Value::Null);
+        settings.insert("Cmd".to_string(), serde_json::json!(["./run.sh"]));
+        let exposed = settings
+            .entry("ExposedPorts")
+            .or_insert_with(|| serde_json::json!({}));
+        if let Some(exposed) = exposed.as_object_mut() {
+            exposed.insert(format!("{}/tcp", port), serde_json::json!({}));
+        } else {
+            *exposed = serde_json::json!({ format!("{}/tcp", port): {} });
+        }
+
+        let rootfs = root
+            .entry("rootfs")
+            .or_insert_with(|| serde_json::json!({ "type": "layers", "diff_ids": [] }));
+        match rootfs
+            .get_mut("diff_ids")
+            .and_then(|ids| ids.as_array_mut())
+        {
+            Some(ids) => ids.push(format!("sha256:{}", diff_id).into()),
+            None => bail!("The config of {} has no rootfs", self.runtime_image),
+        }
+        if let Some(history) = root.get_mut("history").and_then(|h| h.as_array_mut()) {
+            history.push(serde_json::json!({ "created_by": "roche release --oci" }));
+        }
+        Ok(())
+    }
+}
+
+/// An image written by [`OciRequest::execute`].
+pub struct OciImage {
+    /// Digest of the image manifest.
+    pub digest: String,
+    base: Base,
+}
+
+impl OciImage {
+    /// The contents of each of `paths` in the runtime image, `None` for those it doesn't have.
+    pub fn runtime_files(&self, paths: &[&str]) -> Result<Vec<Option<Vec<u8>>>> {
+        paths
+            .iter()
+            .map(|path| self.base.file(path.trim_start_matches('/')))
+            .collect()
+    }
+}
+
+/// The config and layers of the runtime image, with the folder their blobs are stored in.
+struct Base {
+    config: serde_json::Value,
+    layers: Vec<Descriptor>,
+    blobs: PathBuf,
+}
+
+impl Base {
+    /// Reads the image for `platform` from the OCI layout in `dir`.
+    fn local(dir: &Path, platform: &Platform) -> Result<Base> {
+        let blobs = dir.join("blobs");
+        let index = dir.join("index.json");
+        let index: Index = serde_json::from_slice(
+            &fs::read(&index).with_context(|| format!("Couldn't read {}", index.display()))?,
+        )
+        .with_context(|| format!("Couldn't parse {}", index.display()))?;
+        let mut manifest = match pick(&index.manifests, platform) {
+            Some(manifest) => manifest.clone(),
+            None => bail!("{} has no image for {}", dir.display(), platform),
+        };
+        // The layout may point at a further index, e.g. one per platform.
+        loop {
+            let blob = read_blob(&blobs, &manifest.digest)?;
+            if manifest.media_type == OCI_INDEX || manifest.media_type == DOCKER_LIST {
+                let index: Index = serde_json::from_slice(&blob)?;
+                manifest = match pick(&index.manifests, platform) {
+                    Some(manifest) => manifest.clone(),
+                    None => bail!("{} has no image for {}", dir.display(), platform),
+                };
+                continue;
+            }
+            return Base::from_manifest(&blob, blobs, |digest| {
+                read_blob(&dir.join("blobs"), digest)
+            });
+        }
+    }
+
+    /// Pulls the image for `platform` from its registry, keeping the blobs in `cache`.
+    fn pull(image: &str, platform: &Platform, cache: &Path) -> Result<Base> {
+        let registry = Registry::new(image);
+        println!("Roche: Pulling {} from {}", image, registry.host);
+        let (mut blob, mut media_type) = registry.manifest(&registry.reference)?;
+        if media_type == OCI_INDEX || media_type == DOCKER_LIST {
+            let index: Index = serde_json::from_slice(&blob)
+                .with_context(|| format!("Couldn't parse the index of {}", image))?;
+            let manifest = match pick(&index.manifests, platform) {
+                Some(manifest) => manifest.digest.clone(),
+                None => bail!("{} has no image for {}", image, platform),
+            };
+            let (found, found_type) = registry.manifest(&manifest)?;
+            blob = found;
+            media_type = found_type;
+        }
+        if media_type != OCI_MANIFEST && media_type != DOCKER_MANIFEST {
+            bail!("{} has an unsupported manifest type {}", image, media_type);
+        }
+        let blobs = cache.join("blobs");
+        Base::from_manifest(&blob, blobs.clone(), |digest| {
+            match read_blob(&blobs, digest) {
+                Ok(blob) => Ok(blob),
+                Err(_) => {
+                    let blob = registry.blob(digest)?;
+                    let path = blob_file(&blobs, digest);
+                    fs::create_dir_all(path.parent().unwrap())?;
+                    fs::write(&path, &blob)
+                        .with_context(|| format!("Couldn't write {}", path.display()))?;
+                    Ok(blob)
+                }
+            }
+        })
+    }
+
+    /// Reads the config and makes sure every layer is in `blobs`, using `fetch`.
+    fn from_manifest(
+        manifest: &[u8],
+        blobs: PathBuf,
+        fetch: impl Fn(&str) -> Result<Vec<u8>>,
+    ) -> Result<Base> {
+        let manifest: Manifest =
+            serde_json::from_slice(manifest).context("Couldn't parse the image manifest")?;
+        let config = serde_json::from_slice(&fetch(&manifest.config.digest)?)
+            .context("Couldn't parse the image config")?;
+        let mut layers = Vec::new();
+        for layer in manifest.layers {
+            if !layer.media_type.ends_with("gzip") {
+                bail!("Layers of type {} are not supported", layer.media_type);
+            }
+            fetch(&layer.digest)?;
+            // Docker and OCI gzip layers are the same bytes.
+            layers.push(Descriptor {
+                media_type: OCI_LAYER.to_string(),
+                ..layer
+            });
+        }
+        Ok(Base {
+            config,
+            layers,
+            blobs,
+        })
+    }
+
+    fn blob(&self, digest: &str) -> PathBuf {
+        blob_file(&self.blobs, digest)
+    }
+
+    /// The contents of `path` in the image, from the last layer that has it.
+    fn file(&self, path: &str) -> Result<Option<Vec<u8>>> {
+        let (dir, name) = path.rsplit_once('/').unwrap_or(("", path));
+        let whiteout = format!("{}/.wh.{}", dir, name);
+        let mut found = None;
+        for layer in &self.layers {
+            let file = File::open(self.blob(&layer.digest))?;
+            let mut archive = tar::Archive::new(GzDecoder::new(file));
+            for entry in archive.entries()? {
+                let mut entry = entry?;
+                let entry_path = entry.path()?.to_string_lossy().to_string();
+                let entry_path = entry_path.trim_start_matches("./");
+                if entry_path == path {
+                    let mut contents = Vec::new();
+                    entry.read_to_end(&mut contents)?;
+                    found = Some(contents);
+                } else if entry_path == whiteout {
+                    found = None;
+                }
+            }
+        }
+        Ok(found)
+    }
+}
+
+/// A registry repository pulled anonymously over the v2 API.
+struct Registry {
+    host: String,
+    repository: String,
+    reference: String,
+    token: std::cell::RefCell<Option<String>>,
+}
+
+impl Registry {
+    fn new(image: &str) -> Registry {
+        let host = crate::registry::host(image);
+        let name = if image.starts_with(&format!("{}/", host)) {
+            &image[host.len() + 1..]
+        } else {
+            image
+        };
+        let (repository, reference) = match name.split_once('@') {
+            Some((repository, digest)) => (repository, digest),
+            None => match name.rsplit_once(':') {
+                Some((repository, tag)) if !tag.contains('/') => (repository, tag),
+                _ => (name, "latest"),
+            },
+        };
+        let (host, repository) = if host == crate::registry::DEFAULT_REGISTRY {
+            let repository = if repository.contains('/') {
+                repository.to_string()
+            } else {
+                format!("library/{}", repository)
+            };
+            ("registry-1.docker.io".to_string(), repository)
+        } else {
+            (host.to_string(), repository.to_string())
+        };
+        Registry {
+            host,
+            repository,
+            reference: reference.to_string(),
+            token: std::cell::RefCell::new(None),
+        }
+    }
+
+    /// The manifest or index for `reference` and its media type.
+    fn manifest(&self, reference: &str) -> Result<(Vec<u8>, String)> {
+        let accept = [OCI_INDEX, OCI_MANIFEST, DOCKER_LIST, DOCKER_MANIFEST].join(", ");
+        let (body, headers) = self.get(
+            &format!(
+                "https://{}/v2/{}/manifests/{}",
+                self.host, self.repository, reference
+            ),
+            Some(&accept),
+        )?;
+        let media_type = header(&headers, "content-type")
+            .map(|value| {
+                value
+                    .split(';')
+                    .next()
+                    .unwrap_or_default()
+                    .trim()
+                    .to_string()
+            })
+            .or_else(|| {
+                serde_json::from_slice::<serde_json::Value>(&body)
+                    .ok()
+                    .and_then(|m| m["mediaType"].as_str().map(str::to_string))
+            })
+            .unwrap_or_default();
+        Ok((body, media_type))
+    }
+
+    /// The blob `digest`, checked against its digest.
+    fn blob(&self, digest: &str) -> Result<Vec<u8>> {
+        let (body, _) = self.get(
+            &format!(
+                "https://{}/v2/{}/blobs/{}",
+                self.host, self.repository, digest
+            ),
+            None,
+        )?;
+        if format!("sha256:{}", sha256(&body)) != digest {
+            bail!("{} from {} doesn't match its digest", digest, self.host);
+        }
+        Ok(body)
+    }
+
+    /// GETs `url`, fetching an anonymous token when the registry asks for one.
+    fn get(&self, url: &str, accept: Option<&str>) -> Result<(Vec<u8>, Vec<String>)> {
+        let (code, body, headers) = self.request(url, accept)?;
+        let (code, body, headers) = if code == 401 && self.token.borrow().is_none() {
+            let challenge = match header(&headers, "www-authenticate") {
+                Some(challenge) => challenge,
+                None => bail!("{} requires a login", self.host),
+            };
+            *self.token.borrow_mut() = Some(self.fetch_token(&challenge)?);
+            self.request(url, accept)?
+        } else {
+            (code, body, headers)
+        };
+        if code != 200 {
+            bail!(
+                "GET {} returned {}. Private base images can be fetched with 'skopeo copy docker://<image> oci:<dir>' and used as runtime image oci:<dir>",
+                url,
+                code
+            );
+        }
+        Ok((body, headers))
+    }
+
+    fn request(&self, url: &str, accept: Option<&str>) -> Result<(u32, Vec<u8>, Vec<String>)> {
+        let mut easy = curl::easy::Easy::new();
+        easy.url(url)?;
+        easy.follow_location(true)?;
+        easy.useragent(concat!("roche/", env!("CARGO_PKG_VERSION")))?;
+        let mut list = curl::easy::List::new();
+        if let Some(accept) = accept {
+            list.append(&format!("Accept: {}", accept))?;
+        }
+        if let Some(token) = self.token.borrow().as_ref() {
+            list.append(&format!("Authorization: Bearer {}", token))?;
+        }
+        easy.http_headers(list)?;
+        let mut body = Vec::new();
+        let mut headers = Vec::new();
+        {
+            let mut transfer = easy.transfer();
+            transfer.write_function(|data| {
+                body.extend_from_slice(data);
+                Ok(data.len())
+            })?;
+            transfer.header_function(|line| {
+                headers.push(String::from_utf8_lossy(line).trim().to_string());
+                true
+            })?;
+            transfer
+                .perform()
+                .with_context(|| format!("Couldn't GET {}", url))?;
+        }
+        Ok((easy.response_code()?, body, headers))
+    }
+
+    /// Exchanges a `Bearer realm="..",service="..",scope=".."` challenge for a token.
+    fn fetch_token(&self, challenge: &str) -> Result<String> {
+        let params: BTreeMap<String, String> = challenge
+            .trim_start_matches("Bearer ")
+            .split(',')
+            .filter_map(|param| param.split_once('='))
+            .map(|(key, value)| (key.trim().to_string(), value.trim_matches('"').to_string()))
+            .collect();
+        let realm = match params.get("realm") {
+            Some(realm) => realm,
+            None => bail!("{} sent no token realm", self.host),
+        };
+        let mut url = format!("{}?", realm);
+        for key in ["service", "scope"].iter() {
+            if let Some(value) = params.get(*key) {
+                url.push_str(&format!("{}={}&", key, value));
+            }
+        }
+        let (code, body, _) = self.request(url.trim_end_matches('&'), None)?;
+        if code != 200 {
+            bail!("{} refused an anonymous token ({})", self.host, code);
+        }
+        let response: serde_json::Value =
+            serde_json::from_slice(&body).context("Couldn't parse the registry token")?;
+        match response["token"]
+            .as_str()
+            .or_else(|| response["access_token"].as_str())
+        {
+            Some(token) => Ok(token.to_string()),
+            None => bail!("{} sent no token", self.host),
+        }
+    }
+}
+
+/// The Rust target `release --oci` builds for when none is given: a static musl build for the
+/// host architecture, which runs on alpine as well as glibc runtime images.
+pub fn default_target() -> String {
+    format!("{}-unknown-linux-musl", std::env::consts::ARCH)
+}
+
+/// The `os/architecture` of the image for a service built for `target`, e.g. `linux/arm64` for
+/// `aarch64-unknown-linux-musl`. Fails when `target` doesn't build for linux.
+pub fn image_platform(target: &str) -> Result<String> {
+    Ok(platform(Some(target))?.to_string())
+}
+
+/// The OCI platform the service binary runs on: that of the Rust `target` triple, e.g.
+/// `linux/arm64` for `aarch64-unknown-linux-musl`, or of the host when there is none.
+fn platform(target: Option<&str>) -> Result<Platform> {
+    let (arch, os) = match target {
+        Some(target) => {
+            let mut parts = target.split('-');
+            let arch = parts.next().unwrap_or_default();
+            let os = if parts.any(|part| part == "linux") {
+                "linux"
+            } else {
+                target
+            };
+            (arch, os)
+        }
+        None => (std::env::consts::ARCH, std::env::consts::OS),
+    };
+    if os != "linux" {
+        bail!(
+            "The service is built for {}, but runtime images are linux. Please pass a linux --rust-target, e.g. x86_64-unknown-linux-musl",
+            os
+        );
+    }
+    let architecture = match arch {
+        "x86_64" => "amd64",
+        "aarch64" => "arm64",
+        "i386" | "i586" | "i686" => "386",
+        "powerpc64" if target.is_none() => "ppc64le",
+        "powerpc64le" => "ppc64le",
+        "powerpc64" => "ppc64",
+        "riscv64gc" => "riscv64",
+        arm if arm.starts_with("arm") || arm.starts_with("thumb") => "arm",
+        other => other,
+    };
+    Ok(Platform {
+        os: os.to_string(),
+        architecture: architecture.to_string(),
+    })
+}
+
+/// The manifest for `platform`, or the only one when the index doesn't say.
+fn pick<'a>(manifests: &'a [Descriptor], platform: &Platform) -> Option<&'a Descriptor> {
+    manifests
+        .iter()
+        .find(|m| {
+            m.platform
+                .as_ref()
+                .is_some_and(|p| p.os == platform.os && p.architecture == platform.architecture)
+        })
+        .or_else(|| match manifests {
+            [only] if only.platform.is_none() => Some(only),
+            _ => None,
+        })
+}
+
+fn header(headers: &[String], name: &str) -> Option<String> {
+    headers.iter().rev().find_map(|line| {
+        let (key, value) = line.split_once(':')?;
+        if key.trim().eq_ignore_ascii_case(name) {
+            Some(value.trim().to_string())
+        } else {
+            None
+        }
+    })
+}
+
+/// The tag part of `image`, `latest` when it has none.
+fn reference(image: &str) -> String {
+    match image.rsplit_once(':') {
+        Some((_, tag)) if !tag.contains('/') => tag.to_string(),
+        _ => "latest".to_string(),
+    }
+}
+
+fn has_entry(file: &[u8], name: &str) -> bool {
+    String::from_utf8_lossy(file)
+        .lines()
+        .any(|line| line.split(':').next() == Some(name))
+}
+
+fn add_line(file: &mut Vec<u8>, line: &str) {
+    if !file.is_empty() && !file.ends_with(b"\n") {
+        file.push(b'\n');
+    }
+    file.extend_from_slice(line.as_bytes());
+    file.push(b'\n');
+}
+
+fn sha256(data: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(data))
+}
+
+fn gzip(data: &[u8]) -> Result<Vec<u8>> {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(data)?;
+    Ok(encoder.finish()?)
+}
+
+fn blob_path(digest: &str) -> String {
+    format!("blobs/{}", digest.replacen(':', "/", 1))
+}
+
+fn blob_file(blobs: &Path, digest: &str) -> PathBuf {
+    blobs.join(digest.replacen(':', "/", 1))
+}
+
+fn read_blob(blobs: &Path, digest: &str) -> Result<Vec<u8>> {
+    let path = blob_file(blobs, digest);
+    fs::read(&path).with_context(|| format!("Couldn't read {}", path.display()))
+}
+
+/// Appends a file owned by `uid` with a fixed timestamp, so the same inputs give the same image.
+fn append<W: Write>(
+    archive: &mut tar::Builder<W>,
+    path: &str,
+    data: &[u8],
+    mode: u32,
+    uid: u32,
+) -> Result<()> {
+    let mut header = tar::Header::new_ustar();
+    header.set_size(data.len() as u64);
+    header.set_mode(mode);
+    header.set_uid(uid.into());
+    header.set_gid(uid.into());
+    header.set_mtime(0);
+    archive
+        .append_data(&mut header, path, data)
+        .with_context(|| format!("Couldn't add {}", path))
+}
+
+fn append_dir<W: Write>(archive: &mut tar::Builder<W>, path: &str, uid: u32) -> Result<()> {
+    let mut header = tar::Header::new_ustar();
+    header.set_entry_type(tar::EntryType::Directory);
+    header.set_size(0);
+    header.set_mode(0o755);
+    header.set_uid(uid.into());
+    header.set_gid(uid.into());
+    header.set_mtime(0);
+    archive
+        .append_data(&mut header, path, std::io::empty())
+        .with_context(|| format!("Couldn't add {}", path))
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Index {
+    schema_version: u32,
+    #[serde(default)]
+    media_type: String,
+    manifests: Vec<Descriptor>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Manifest {
+    schema_version: u32,
+    #[serde(default)]
+    media_type: String,
+    config: Descriptor,
+    layers: Vec<Descriptor>,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Descriptor {
+    #[serde(default)]
+    media_type: String,
+    digest: String,
+    size: u64,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    annotations: BTreeMap<String, String>,
+    #[serde(default, skip_serializing)]
+    platform: Option<Platform>,
+}
+
+impl Descriptor {
+    fn new(media_type: &str, blob: &[u8]) -> Descriptor {
+        Descriptor {
+            media_type: media_type.to_string(),
+            digest: format!("sha256:{}", sha256(blob)),
+            size: blob.len() as u64,
+            annotations: BTreeMap::new(),
+            platform: None,
+        }
+    }
+}
+
+#[derive(Clone, Deserialize)]
+struct Platform {
+    os: String,
+    architecture: String,
+}
+
+impl fmt::Display for Platform {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}/{}", self.os, self.architecture)
+    }
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct DockerArchive {
+    config: String,
+    repo_tags: Vec<String>,
+    layers: Vec<String>,
+}
@@ -0,0 +1,608 @@
+//! OpenAPI documents for the routes a function registers.
+//!
+//! The document is a skeleton built from the source: the paths and methods `handler()`
+//! registers, with request and response bodies described by the serde structs an endpoint
+//! reads with `body_json` or writes with `Body::from_json`.
+
+use crate::handler::{self, Route};
+use anyhow::{bail, Context, Result};
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::Path;
+use syn::visit::{self, Visit};
+
+/// The OpenAPI version of the generated documents.
+pub const OPENAPI_VERSION: &str = "3.0.3";
+
+/// Renders the OpenAPI document of the function in `dir` as YAML.
+///
+/// Routes registered with `.all()` or `.nest()` have no single method and are left out. Two
+/// routes that end up as the same operation are an error rather than one hiding the other.
+pub fn generate(
+    dir: &Path,
+    prefixes: &BTreeMap<String, String>,
+    title: &str,
+    version: &str,
+) -> Result<String> {
+    let sources = handler::sources(dir, prefixes)?;
+    let mut schemas = Schemas::default();
+    for source in &sources {
+        schemas.collect(&source.syntax);
+    }
+
+    let mut paths: BTreeMap<String, BTreeMap<String, Operation>> = BTreeMap::new();
+    let mut seen: BTreeMap<(String, String), &Route> = BTreeMap::new();
+    for source in &sources {
+        for (route, endpoint) in &source.endpoints {
+            if route.method == "ANY" || route.method == "NEST" {
+                continue;
+            }
+            let bodies = match endpoint {
+                Some(endpoint) => Bodies::of(endpoint, &source.syntax),
+                None => Bodies::default(),
+            };
+            let path = openapi_path(&route.path);
+            let method = route.method.to_lowercase();
+            if let Some(other) = seen.insert((path.clone(), method.clone()), route) {
+                bail!(
+                    "route collision: {} is registered at {}:{} and at {}:{}",
+                    route,
+                    other.file.display(),
+                    other.line,
+                    route.file.display(),
+                    route.line
+                );
+            }
+            paths
+                .entry(path)
+                .or_default()
+                .insert(method, operation(route, &bodies, &mut schemas));
+        }
+    }
+
+    let document = Document {
+        openapi: OPENAPI_VERSION,
+        info: Info {
+            title: title.to_string(),
+            version: version.to_string(),
+        },
+        paths,
+        components: Components {
+            schemas: schemas.used(),
+        },
+    };
+    serde_yaml::to_string(&document).context("Couldn't serialize the OpenAPI document")
+}
+
+#[derive(Serialize)]
+struct Document {
+    openapi: &'static str,
+    info: Info,
+    paths: BTreeMap<String, BTreeMap<String, Operation>>,
+    #[serde(skip_serializing_if = "Components::is_empty")]
+    components: Components,
+}
+
+#[derive(Serialize)]
+struct Info {
+    title: String,
+    version: String,
+}
+
+#[derive(Serialize)]
+struct Operation {
+    #[serde(rename = "operationId")]
+    operation_id: String,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    parameters: Vec<Parameter>,
+    #[serde(rename = "requestBody", skip_serializing_if = "Option::is_none")]
+    request_body: Option<RequestBody>,
+    responses: BTreeMap<String, Response>,
+}
+
+#[derive(Serialize)]
+struct Parameter {
+    name: String,
+    #[serde(rename = "in")]
+    location: &'static str,
+    required: bool,
+    schema: Schema,
+}
+
+#[derive(Serialize)]
+struct RequestBody {
+    required: bool,
+    content: BTreeMap<String, MediaType>,
+}
+
+#[derive(Serialize)]
+struct Response {
+    description: String,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    content: BTreeMap<String, MediaType>,
+}
+
+#[derive(Serialize)]
+struct MediaType {
+    schema: Schema,
+}
+
+#[derive(Serialize)]
+struct Components {
+    schemas: BTreeMap<String, Schema>,
+}
+
+impl Components {
+    fn is_empty(&self) -> bool {
+        self.schemas.is_empty()
+    }
+}
+
+#[derive(Serialize, Default, Clone)]
+struct Schema {
+    #[serde(rename = "$ref", skip_serializing_if = "Option::is_none")]
+    reference: Option<String>,
+    #[serde(rename = "type", skip_serializing_if = "Option::is_none")]
+    kind: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    format: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    items: Option<Box<Schema>>,
+    #[serde(
+        rename = "additionalProperties",
+        skip_serializing_if = "Option::is_none"
+    )]
+    additional_properties: Option<Box<Schema>>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    properties: BTreeMap<String, Schema>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    required: Vec<String>,
+}
+
+impl Schema {
+    fn of(kind: &'static str, format: Option<&'static str>) -> Schema {
+        Schema {
+            kind: Some(kind),
+            format,
+            ..Default::default()
+        }
+    }
+}
+
+fn operation(route: &Route, bodies: &Bodies, schemas: &mut Schemas) -> Operation {
+    let parameters = route
+        .path
+        .split('/')
+        .filter_map(parameter_name)
+        .map(|name| Parameter {
+            name: name.to_string(),
+            location: "path",
+            required: true,
+            schema: Schema::of("string", None),
+        })
+        .collect();
+    let request_body = bodies.request.as_ref().map(|ty| RequestBody {
+        required: true,
+        content: json_content(schemas.schema(ty)),
+    });
+    let content = match &bodies.response {
+        Some(Some(ty)) => json_content(schemas.schema(ty)),
+        Some(None) => json_content(Schema::default()),
+        None => BTreeMap::new(),
+    };
+    let status = bodies.status.clone().unwrap_or_else(|| "200".to_string());
+    let description = match status.as_str() {
+        "200" => "OK",
+        "201" => "Created",
+        "202" => "Accepted",
+        _ => "Response",
+    };
+    let mut responses = BTreeMap::new();
+    responses.insert(
+        status,
+        Response {
+            description: description.to_string(),
+            content,
+        },
+    );
+    Operation {
+        operation_id: operation_id(route),
+        parameters,
+        request_body,
+        responses,
+    }
+}
+
+fn json_content(schema: Schema) -> BTreeMap<String, MediaType> {
+    let mut content = BTreeMap::new();
+    content.insert("application/json".to_string(), MediaType { schema });
+    content
+}
+
+/// `get_users_id` for `GET /users/:id`.
+fn operation_id(route: &Route) -> String {
+    let mut id = route.method.to_lowercase();
+    for segment in route.path.split('/') {
+        let segment = parameter_name(segment).unwrap_or(segment);
+        if !segment.is_empty() {
+            id.push('_');
+            id.extend(segment.chars().map(|c| match c {
+                'a'..='z' | 'A'..='Z' | '0'..='9' => c,
+                _ => '_',
+            }));
+        }
+    }
+    id
+}
+
+/// `id` for the segment `:id`, and `wildcard` for a bare `*` that names no parameter.
+fn parameter_name(segment: &str) -> Option<&str> {
+    match segment {
+        "*" => Some("wildcard"),
+        _ => segment
+            .strip_prefix(':')
+            .or_else(|| segment.strip_prefix('*')),
+    }
+}
+
+/// `/users/{id}` for the tide route `/users/:id`.
+fn openapi_path(path: &str) -> String {
+    path.split('/')
+        .map(|segment| match parameter_name(segment) {
+            Some(name) => format!("{{{}}}", name),
+            None => segment.to_string(),
+        })
+        .collect::<Vec<String>>()
+        .join("/")
+}
+
+/// The bodies an endpoint reads and writes.
+#[derive(Default)]
+struct Bodies {
+    /// The type read with `body_json`.
+    request: Option<syn::Type>,
+    /// Set when the endpoint writes `Body::from_json`, with the type written if it is known.
+    response: Option<Option<syn::Type>>,
+    /// The status of a `Response::new(201)` or `Response::builder(201)`.
+    status: Option<String>,
+}
+
+impl Bodies {
+    /// Looks for bodies in a closure endpoint, or in the `fn` of the file a path endpoint names.
+    fn of(endpoint: &syn::Expr, syntax: &syn::File) -> Bodies {
+        let mut finder = BodyFinder::default();
+        match endpoint {
+            syn::Expr::Path(path) => {
+                let function = syntax.items.iter().find_map(|item| match item {
+                    syn::Item::Fn(function) if path.path.is_ident(&function.sig.ident) => {
+                        Some(function)
+                    }
+                    _ => None,
+                });
+                match function {
+                    Some(function) => finder.visit_block(&function.block),
+                    None => return Bodies::default(),
+                }
+            }
+            endpoint => finder.visit_expr(endpoint),
+        }
+
+        let bindings = finder.bindings;
+        let response = finder.written.map(|written| match written {
+            Written::Type(ty) => Some(*ty),
+            Written::Binding(name) => bindings.get(&name).cloned(),
+            Written::Unknown => None,
+        });
+        Bodies {
+            request: finder.read,
+            response,
+            status: finder.status,
+        }
+    }
+}
+
+enum Written {
+    Type(Box<syn::Type>),
+    Binding(String),
+    Unknown,
+}
+
+#[derive(Default)]
+struct BodyFinder {
+    /// Types of the `let` bindings, from their annotation or struct literal.
+    bindings: BTreeMap<String, syn::Type>,
+    read: Option<syn::Type>,
+    written: Option<Written>,
+    status: Option<String>,
+}
+
+impl<'ast> Visit<'ast> for BodyFinder {
+    fn visit_local(&mut self, local: &'ast syn::Local) {
+        let init = local.init.as_ref().map(|(_, init)| &**init);
+        match &local.pat {
+            syn::Pat::Type(typed) => {
+                if let syn::Pat::Ident(name) = &*typed.pat {
+                    self.bindings
+                        .insert(name.ident.to_string(), (*typed.ty).clone());
+                }
+                if self.read.is_none() && init.is_some_and(reads_json) {
+                    self.read = Some((*typed.ty).clone());
+                }
+            }
+            syn::Pat::Ident(name) => {
+                if let Some(syn::Expr::Struct(literal)) = init {
+                    self.bindings
+                        .insert(name.ident.to_string(), struct_type(&literal.path));
+                }
+            }
+            _ => {}
+        }
+        visit::visit_local(self, local);
+    }
+
+    fn visit_expr_method_call(&mut self, call: &'ast syn::ExprMethodCall) {
+        if call.method == "body_json" && self.read.is_none() {
+            if let Some(turbofish) = &call.turbofish {
+                if let Some(syn::GenericMethodArgument::Type(ty)) = turbofish.args.first() {
+                    self.read = Some(ty.clone());
+                }
+            }
+        }
+        visit::visit_expr_method_call(self, call);
+    }
+
+    fn visit_expr_call(&mut self, call: &'ast syn::ExprCall) {
+        let segments: Vec<String> = match &*call.func {
+            syn::Expr::Path(path) => path
+                .path
+                .segments
+                .iter()
+                .map(|segment| segment.ident.to_string())
+                .collect(),
+            _ => Vec::new(),
+        };
+        let from_json = segments.last().is_some_and(|name| name == "from_json");
+        if let [.., kind, constructor] = segments.as_slice() {
+            if kind == "Response" && (constructor == "new" || constructor == "builder") {
+                if let Some(syn::Expr::Lit(syn::ExprLit {
+                    lit: syn::Lit::Int(status),
+                    ..
+                })) = call.args.first()
+                {
+                    self.status
+                        .get_or_insert_with(|| status.base10_digits().to_string());
+                }
+            }
+        }
+        if from_json && self.written.is_none() {
+            self.written = Some(match call.args.first().map(strip) {
+                Some(syn::Expr::Path(path)) => match path.path.get_ident() {
+                    Some(name) => Written::Binding(name.to_string()),
+                    None => Written::Unknown,
+                },
+                Some(syn::Expr::Struct(literal)) => {
+                    Written::Type(Box::new(struct_type(&literal.path)))
+                }
+                _ => Written::Unknown,
+            });
+        }
+        visit::visit_expr_call(self, call);
+    }
+}
+
+/// Whether `expr` reads the request body as JSON.
+fn reads_json(expr: &syn::Expr) -> bool {
+    struct Reads(bool);
+    impl<'ast> Visit<'ast> for Reads {
+        fn visit_expr_method_call(&mut self, call: &'ast syn::ExprMethodCall) {
+            self.0 |= call.method == "body_json";
+            visit::visit_expr_method_call(self, call);
+        }
+    }
+    let mut reads = Reads(false);
+    reads.visit_expr(expr);
+    reads.0
+}
+
+/// `expr` without references and parentheses.
+fn strip(expr: &syn::Expr) -> &syn::Expr {
+    match expr {
+        syn::Expr::Reference(reference) => strip(&reference.expr),
+        syn::Expr::Paren(paren) => strip(&paren.expr),
+        expr => expr,
+    }
+}
+
+fn struct_type(path: &syn::Path) -> syn::Type {
+    syn::Type::Path(syn::TypePath {
+        qself: None,
+        path: path.clone(),
+    })
+}
+
+/// The serde structs of the function and the ones the document refers to.
+#[derive(Default)]
+struct Schemas {
+    structs: BTreeMap<String, syn::ItemStruct>,
+    used: BTreeSet<String>,
+}
+
+impl Schemas {
+    /// Remembers the structs in `syntax` that derive `Serialize` or `Deserialize`.
+    fn collect(&mut self, syntax: &syn::File) {
+        for item in &syntax.items {
+            if let syn::Item::Struct(item) = item {
+                if derives_serde(&item.attrs) {
+                    self.structs
+                        .entry(item.ident.to_string())
+                        .or_insert_with(|| item.clone());
+                }
+            }
+        }
+    }
+
+    /// The component schemas of the structs referred to so far, and of the structs they use.
+    fn used(&mut self) -> BTreeMap<String, Schema> {
+        let mut schemas = BTreeMap::new();
+        while let Some(name) = self
+            .used
+            .iter()
+            .find(|name| !schemas.contains_key(*name))
+            .cloned()
+        {
+            let item = self.structs[&name].clone();
+            let schema = self.object(&item);
+            schemas.insert(name, schema);
+        }
+        schemas
+    }
+
+    fn object(&mut self, item: &syn::ItemStruct) -> Schema {
+        match &item.fields {
+            syn::Fields::Named(fields) => {
+                let mut schema = Schema::of("object", None);
+                for field in &fields.named {
+                    let (rename, skip) = serde_field(&field.attrs);
+                    if skip {
+                        continue;
+                    }
+                    let name = match (rename, &field.ident) {
+                        (Some(rename), _) => rename,
+                        (None, Some(ident)) => ident.to_string(),
+                        (None, None) => continue,
+                    };
+                    if option_inner(&field.ty).is_none() {
+                        schema.required.push(name.clone());
+                    }
+                    schema.properties.insert(name, self.schema(&field.ty));
+                }
+                schema
+            }
+            // A newtype serializes as the type it wraps.
+            syn::Fields::Unnamed(fields) if fields.unnamed.len() == 1 => {
+                self.schema(&fields.unnamed[0].ty)
+            }
+            syn::Fields::Unnamed(_) => Schema::of("array", None),
+            syn::Fields::Unit => Schema::of("object", None),
+        }
+    }
+
+    /// The schema of a Rust type, referring to the serde structs of the function by name.
+    fn schema(&mut self, ty: &syn::Type) -> Schema {
+        let segment = match ty {
+            syn::Type::Reference(reference) => return self.schema(&reference.elem),
+            syn::Type::Paren(paren) => return self.schema(&paren.elem),
+            syn::Type::Slice(slice) => return self.array(&slice.elem),
+            syn::Type::Array(array) => return self.array(&array.elem),
+            syn::Type::Path(path) => match path.path.segments.last() {
+                Some(segment) => segment,
+                None => return Schema::default(),
+            },
+            _ => return Schema::default(),
+        };
+        let args = generic_types(&segment.arguments);
+        match segment.ident.to_string().as_str() {
+            "String" | "str" | "char" => Schema::of("string", None),
+            "Uuid" => Schema::of("string", Some("uuid")),
+            "i8" | "i16" | "i32" | "u8" | "u16" | "u32" => Schema::of("integer", Some("int32")),
+            "i64" | "i128" | "isize" | "u64" | "u128" | "usize" => {
+                Schema::of("integer", Some("int64"))
+            }
+            "f32" => Schema::of("number", Some("float")),
+            "f64" => Schema::of("number", Some("double")),
+            "bool" => Schema::of("boolean", None),
+            "Vec" | "VecDeque" | "HashSet" | "BTreeSet" if args.len() == 1 => self.array(args[0]),
+            "Option" | "Box" | "Rc" | "Arc" if args.len() == 1 => self.schema(args[0]),
+            "HashMap" | "BTreeMap" if args.len() == 2 => Schema {
+                additional_properties: Some(Box::new(self.schema(args[1]))),
+                ..Schema::of("object", None)
+            },
+            name if self.structs.contains_key(name) => {
+                self.used.insert(name.to_string());
+                Schema {
+                    reference: Some(format!("#/components/schemas/{}", name)),
+                    ..Default::default()
+                }
+            }
+            // Anything else, like serde_json::Value, could be any JSON.
+            _ => Schema::default(),
+        }
+    }
+
+    fn array(&mut self, items: &syn::Type) -> Schema {
+        Schema {
+            items: Some(Box::new(self.schema(items))),
+            ..Schema::of("array", None)
+        }
+    }
+}
+
+fn generic_types(arguments: &syn::PathArguments) -> Vec<&syn::Type> {
+    match arguments {
+        syn::PathArguments::AngleBracketed(args) => args
+            .args
+            .iter()
+            .filter_map(|arg| match arg {
+                syn::GenericArgument::Type(ty) => Some(ty),
+                _ => None,
+            })
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+fn option_inner(ty: &syn::Type) -> Option<&syn::Type> {
+    match ty {
+        syn::Type::Path(path) => {
+            let segment = path.path.segments.last()?;
+            if segment.ident != "Option" {
+                return None;
+            }
+            generic_types(&segment.arguments).first().copied()
+        }
+        _ => None,
+    }
+}
+
+fn derives_serde(attrs: &[syn::Attribute]) -> bool {
+    attrs.iter().any(|attr| {
+        if !attr.path.is_ident("derive") {
+            return false;
+        }
+        match attr.parse_meta() {
+            Ok(syn::Meta::List(list)) => list.nested.iter().any(|nested| match nested {
+                syn::NestedMeta::Meta(meta) => meta.path().segments.last().is_some_and(|segment| {
+                    segment.ident == "Serialize" || segment.ident == "Deserialize"
+                }),
+                _ => false,
+            }),
+            _ => false,
+        }
+    })
+}
+
+/// The `#[serde(rename = "...")]` of a field and whether it has `#[serde(skip)]`.
+fn serde_field(attrs: &[syn::Attribute]) -> (Option<String>, bool) {
+    let mut rename = None;
+    let mut skip = false;
+    for attr in attrs.iter().filter(|attr| attr.path.is_ident("serde")) {
+        if let Ok(syn::Meta::List(list)) = attr.parse_meta() {
+            for nested in &list.nested {
+                match nested {
+                    syn::NestedMeta::Meta(syn::Meta::NameValue(value))
+                        if value.path.is_ident("rename") =>
+                    {
+                        if let syn::Lit::Str(name) = &value.lit {
+                            rename = Some(name.value());
+                        }
+                    }
+                    syn::NestedMeta::Meta(syn::Meta::Path(path)) if path.is_ident("skip") => {
+                        skip = true
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+    (rename, skip)
+}
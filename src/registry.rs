@@ -0,0 +1,235 @@
+//! Pushing release images to a registry.
+//!
+//! The engine does the push with the credentials from its own auth config. roche only reads that
+//! config to report which login is used and to suggest `<engine> login` when there is none.
+
+use crate::engine::ContainerEngine;
+use anyhow::{bail, Context, Result};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::io::{BufRead, BufReader};
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+
+/// Registry of images whose name doesn't start with a registry host, e.g. `myorg/hello`.
+pub const DEFAULT_REGISTRY: &str = "docker.io";
+
+/// The registry host `image` is pushed to, e.g. `quay.io` for `quay.io/myorg/hello:1.0.0`.
+pub fn host(image: &str) -> &str {
+    match image.split_once('/') {
+        Some((first, _)) if first.contains(['.', ':']) || first == "localhost" => first,
+        _ => DEFAULT_REGISTRY,
+    }
+}
+
+/// The auth config files `engine` reads, in the order it reads them.
+///
+/// docker uses `$DOCKER_CONFIG/config.json` or `~/.docker/config.json`. podman and buildah use
+/// `$REGISTRY_AUTH_FILE`, then `$XDG_RUNTIME_DIR/containers/auth.json` and
+/// `~/.config/containers/auth.json`, and fall back to the docker config.
+pub fn auth_files(engine: &str) -> Vec<PathBuf> {
+    let home = env::var_os("HOME").map(PathBuf::from);
+    let mut files = Vec::new();
+    if engine != "docker" {
+        if let Some(file) = env::var_os("REGISTRY_AUTH_FILE") {
+            files.push(PathBuf::from(file));
+        }
+        if let Some(dir) = env::var_os("XDG_RUNTIME_DIR") {
+            files.push(Path::new(&dir).join("containers").join("auth.json"));
+        }
+        if let Some(home) = &home {
+            files.push(home.join(".config").join("containers").join("auth.json"));
+        }
+    }
+    match env::var_os("DOCKER_CONFIG") {
+        Some(dir) => files.push(Path::new(&dir).join("config.json")),
+        None => {
+            if let Some(home) = &home {
+                files.push(home.join(".docker").join("config.json"));
+            }
+        }
+    }
+    files
+}
+
+/// A login for a registry found in an engine's auth config.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Credentials {
+    /// The auth config the login was found in.
+    pub file: PathBuf,
+    /// The user, when the config stores the login itself rather than a credential helper.
+    pub username: Option<String>,
+    /// The credential helper holding the login, e.g. `desktop` or `ecr-login`.
+    pub helper: Option<String>,
+}
+
+/// Finds the login `engine` uses for the registry `host`.
+pub fn credentials(engine: &str, host: &str) -> Result<Option<Credentials>> {
+    for file in auth_files(engine) {
+        if !file.exists() {
+            continue;
+        }
+        let source = fs::read_to_string(&file)
+            .with_context(|| format!("Couldn't read {}", file.display()))?;
+        let config: AuthConfig = serde_json::from_str(&source)
+            .with_context(|| format!("Couldn't parse {}", file.display()))?;
+        if let Some(helper) = config.cred_helpers.get(host) {
+            return Ok(Some(Credentials {
+                file,
+                username: None,
+                helper: Some(helper.clone()),
+            }));
+        }
+        let auth = config
+            .auths
+            .iter()
+            .find(|(key, _)| normalize(key) == host)
+            .map(|(_, auth)| auth);
+        if let Some(auth) = auth {
+            let username = auth
+                .auth
+                .as_ref()
+                .and_then(|auth| base64::decode(auth).ok())
+                .and_then(|decoded| {
+                    let decoded = String::from_utf8_lossy(&decoded).to_string();
+                    decoded.split_once(':').map(|(user, _)| user.to_string())
+                });
+            return Ok(Some(Credentials {
+                file,
+                username,
+                helper: config.creds_store.clone(),
+            }));
+        }
+        if let Some(helper) = config.creds_store {
+            return Ok(Some(Credentials {
+                file,
+                username: None,
+                helper: Some(helper),
+            }));
+        }
+    }
+    Ok(None)
+}
+
+/// The registry host of an auth config key such as `https://index.docker.io/v1/`.
+fn normalize(key: &str) -> &str {
+    let key = key
+        .trim_start_matches("https://")
+        .trim_start_matches("http://");
+    let key = key.split('/').next().unwrap_or(key);
+    match key {
+        "index.docker.io" | "registry-1.docker.io" => DEFAULT_REGISTRY,
+        key => key,
+    }
+}
+
+/// Pushes a built image to its registry.
+#[derive(Debug, Clone)]
+pub struct PushRequest {
+    pub tag: String,
+    /// Whether `tag` is a manifest list built by `release --platform`, pushed with all its images.
+    pub manifest: bool,
+}
+
+impl PushRequest {
+    /// Pushes the image with `engine`, streaming its output, and returns the pushed digest when
+    /// the engine reported one.
+    pub fn execute(&self, engine: &dyn ContainerEngine) -> Result<Option<String>> {
+        let host = host(&self.tag);
+        let login = credentials(engine.binary(), host)?;
+        match &login {
+            Some(Credentials {
+                username: Some(username),
+                ..
+            }) => println!("Roche: Pushing {} to {} as {}", self.tag, host, username),
+            Some(Credentials {
+                helper: Some(helper),
+                ..
+            }) => println!(
+                "Roche: Pushing {} to {} with the {} credential helper",
+                self.tag, host, helper
+            ),
+            _ => println!(
+                "Roche: No login for {} found in the {} auth config, pushing anonymously",
+                host,
+                engine.binary()
+            ),
+        }
+
+        let digest_file = env::temp_dir().join(format!("roche-{}.digest", std::process::id()));
+        let mut command = if self.manifest {
+            engine.push_manifest(&self.tag, &digest_file.display().to_string())
+        } else {
+            engine.push(&self.tag, &digest_file.display().to_string())
+        };
+        let mut process = command
+            .stdout(Stdio::piped())
+            .spawn()
+            .with_context(|| format!("couldn't spawn {}", engine.binary()))?;
+        let mut digest = None;
+        for line in BufReader::new(process.stdout.take().unwrap())
+            .lines()
+            .map_while(|l| l.ok())
+        {
+            println!("{}", line);
+            if let Some(found) = parse_digest(&line) {
+                digest = Some(found);
+            }
+        }
+        let status = process
+            .wait()
+            .with_context(|| format!("couldn't wait for {}", engine.binary()))?;
+        if let Ok(found) = fs::read_to_string(&digest_file) {
+            digest = Some(found.trim().to_string());
+        }
+        let _ = fs::remove_file(&digest_file);
+        if !status.success() {
+            if login.is_none() {
+                bail!(
+                    "{} push failed for {} ({}). Please log in with '{} login {}' and try again",
+                    engine.binary(),
+                    self.tag,
+                    status,
+                    engine.binary(),
+                    host
+                );
+            }
+            bail!(
+                "{} push failed for {} ({})",
+                engine.binary(),
+                self.tag,
+                status
+            );
+        }
+        match &digest {
+            Some(digest) => println!("Roche: Pushed {}@{}", self.tag, digest),
+            None => println!("Roche: Pushed {}", self.tag),
+        }
+        Ok(digest)
+    }
+}
+
+/// The digest in a line like `1.0.0: digest: sha256:ab12... size: 1573` from `docker push`.
+fn parse_digest(line: &str) -> Option<String> {
+    let (_, rest) = line.split_once("digest: ")?;
+    rest.split_whitespace()
+        .next()
+        .filter(|digest| digest.starts_with("sha256:"))
+        .map(str::to_string)
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+struct AuthConfig {
+    auths: BTreeMap<String, Auth>,
+    cred_helpers: BTreeMap<String, String>,
+    creds_store: Option<String>,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default)]
+struct Auth {
+    auth: Option<String>,
+}
@@ -0,0 +1,119 @@
+//! Running a built function image locally.
+
+use crate::engine::ContainerEngine;
+use anyhow::{bail, Context, Result};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+/// Describes a container started from a function image.
+#[derive(Debug, Clone, Default)]
+pub struct RunRequest {
+    pub tag: String,
+    /// Container name, used to clean up on Ctrl-C.
+    pub name: String,
+    pub host_port: u16,
+    /// The port the service listens on inside the container, passed in as `PORT`.
+    pub container_port: u16,
+    /// Files forwarded with `--env-file`. Missing files are skipped.
+    pub env_files: Vec<PathBuf>,
+}
+
+impl RunRequest {
+    /// Flags passed to the engine before the image tag.
+    pub fn engine_flags(&self) -> Vec<String> {
+        let mut flags = vec![
+            "-p".to_string(),
+            format!("{}:{}", self.host_port, self.container_port),
+            "-e".to_string(),
+            format!("PORT={}", self.container_port),
+        ];
+        for file in self.env_files.iter().filter(|file| file.exists()) {
+            flags.push("--env-file".to_string());
+            flags.push(file.display().to_string());
+        }
+        flags
+    }
+
+    /// The engine command that runs the container in the foreground.
+    pub fn command(&self, engine: &dyn ContainerEngine) -> Result<Command> {
+        let mut command = Command::new(engine.binary());
+        command.args(engine.run_args(&self.name, &self.tag, &self.engine_flags())?);
+        Ok(command)
+    }
+
+    /// Runs the container in the foreground with its logs on our stdout and stderr.
+    pub fn execute(&self, engine: &dyn ContainerEngine) -> Result<()> {
+        let mut command = self.command(engine)?;
+        remove_on_interrupt(engine, &self.name)?;
+
+        println!(
+            "Roche: Running {} as {} on http://localhost:{}/",
+            self.tag, self.name, self.host_port
+        );
+        let status = command
+            .status()
+            .with_context(|| format!("Couldn't run {}", engine.binary()))?;
+
+        if interrupted() {
+            println!("Roche: Stopped {}", self.name);
+        } else if !status.success() {
+            bail!(
+                "{} run failed for {} with {}",
+                engine.binary(),
+                self.tag,
+                status
+            );
+        }
+        Ok(())
+    }
+
+    /// Stops and removes the container, ignoring a container that has already gone.
+    pub fn stop(&self, engine: &dyn ContainerEngine) -> Result<()> {
+        Command::new(engine.binary())
+            .args(engine.remove_args(&self.name))
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()
+            .with_context(|| format!("Couldn't remove {}", self.name))?;
+        Ok(())
+    }
+}
+
+/// Force removes the container called `name` when roche receives Ctrl-C.
+///
+/// The handler can only be installed once per process.
+pub fn remove_on_interrupt(engine: &dyn ContainerEngine, name: &str) -> Result<()> {
+    let binary = engine.binary();
+    let args = engine.remove_args(name);
+    ctrlc::set_handler(move || {
+        INTERRUPTED.store(true, Ordering::SeqCst);
+        let _ = Command::new(binary)
+            .args(&args)
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status();
+    })
+    .context("Couldn't install the Ctrl-C handler")
+}
+
+/// Whether roche has received Ctrl-C since [`remove_on_interrupt`] was called.
+pub fn interrupted() -> bool {
+    INTERRUPTED.load(Ordering::SeqCst)
+}
+
+/// The container name for the project in `dir`, e.g. `roche-hello`.
+pub fn container_name(dir: &Path) -> String {
+    let project: String = crate::project_name(dir)
+        .unwrap_or_default()
+        .to_lowercase()
+        .chars()
+        .map(|c| match c {
+            'a'..='z' | '0'..='9' | '_' | '.' | '-' => c,
+            _ => '-',
+        })
+        .collect();
+    format!("roche-{}", project)
+}
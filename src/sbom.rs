@@ -0,0 +1,719 @@
+//! Software bills of materials for release images.
+//!
+//! An SBOM lists the crates compiled into the service, read from the `Cargo.lock` the build
+//! stage produced and the release image keeps in `/app`, and the OS packages of the runtime
+//! image, read from its apk or dpkg database. It is written as CycloneDX and SPDX JSON and can
+//! be attached to the pushed image as an OCI artifact with `oras`.
+
+use crate::engine::{self, ContainerEngine};
+use anyhow::{anyhow, bail, Context, Result};
+use serde::Deserialize;
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::env;
+use std::fmt;
+use std::fs;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::str::FromStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Where the release templates put the service's `Cargo.lock`.
+pub const LOCK_FILE: &str = "/app/Cargo.lock";
+/// The package database of alpine based runtime images.
+pub const APK_DB: &str = "/lib/apk/db/installed";
+/// The package database of debian based runtime images.
+pub const DPKG_STATUS: &str = "/var/lib/dpkg/status";
+pub const OS_RELEASE: &str = "/etc/os-release";
+
+/// File name the SBOMs of engine builds are written to, before the format's extension.
+pub const DEFAULT_NAME: &str = "sbom";
+
+/// An SBOM document format.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    CycloneDx,
+    Spdx,
+}
+
+impl Format {
+    pub const ALL: [Format; 2] = [Format::CycloneDx, Format::Spdx];
+
+    /// The extension of files in this format, e.g. `sbom.cdx.json`.
+    pub fn extension(self) -> &'static str {
+        match self {
+            Format::CycloneDx => "cdx.json",
+            Format::Spdx => "spdx.json",
+        }
+    }
+
+    /// The artifact type of the SBOM when it is attached to an image.
+    pub fn media_type(self) -> &'static str {
+        match self {
+            Format::CycloneDx => "application/vnd.cyclonedx+json",
+            Format::Spdx => "application/spdx+json",
+        }
+    }
+}
+
+impl FromStr for Format {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Format> {
+        match s.to_ascii_lowercase().as_str() {
+            "cyclonedx" => Ok(Format::CycloneDx),
+            "spdx" => Ok(Format::Spdx),
+            _ => Err(anyhow!(
+                "'{}' is not an SBOM format, use cyclonedx or spdx",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Format {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Format::CycloneDx => write!(f, "CycloneDX"),
+            Format::Spdx => write!(f, "SPDX"),
+        }
+    }
+}
+
+/// A crate or OS package in the image.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Component {
+    pub name: String,
+    pub version: String,
+    /// Package URL, e.g. `pkg:cargo/serde@1.0.130`, which also identifies it in the documents.
+    /// Crates from git or another registry carry their source as a qualifier, as they may share
+    /// their name and version with one from crates.io.
+    pub purl: String,
+    /// Where it came from, e.g. `registry+https://github.com/rust-lang/crates.io-index`.
+    pub source: Option<String>,
+    /// SHA-256 of the downloaded crate.
+    pub checksum: Option<String>,
+    /// SPDX license expression, when the package database records one.
+    pub license: Option<String>,
+    /// The purls of the components it depends on.
+    pub dependencies: Vec<String>,
+}
+
+impl Component {
+    /// Where the component can be downloaded, for SPDX.
+    fn download_location(&self) -> String {
+        match self.source.as_deref() {
+            Some(source) if is_crates_io(source) => {
+                format!(
+                    "https://crates.io/api/v1/crates/{}/{}/download",
+                    self.name, self.version
+                )
+            }
+            Some(source) if source.starts_with("git+") => {
+                source.trim_start_matches("git+").to_string()
+            }
+            _ => "NOASSERTION".to_string(),
+        }
+    }
+}
+
+/// The crates in a `Cargo.lock`, with the dependencies between them.
+pub fn crates(lock: &str) -> Result<Vec<Component>> {
+    let lock: Lock = toml::from_str(lock).context("Couldn't parse Cargo.lock")?;
+    let purl = |package: &LockPackage| {
+        let purl = format!(
+            "pkg:cargo/{}@{}",
+            escape(&package.name),
+            escape(&package.version)
+        );
+        match package.source.as_deref() {
+            Some(source) if source.starts_with("git+") => {
+                format!("{}?vcs_url={}", purl, escape(source))
+            }
+            Some(source) if !is_crates_io(source) => {
+                let url = source.split_once('+').map_or(source, |(_, url)| url);
+                format!("{}?repository_url={}", purl, escape(url))
+            }
+            _ => purl,
+        }
+    };
+    // Dependencies are `name`, or `name version` when the lock has several versions of it and
+    // `name version (source)` when they also come from several sources.
+    let find = |dependency: &str| {
+        let mut parts = dependency.splitn(3, ' ');
+        let name = parts.next().unwrap_or_default();
+        let version = parts.next();
+        let source = parts
+            .next()
+            .map(|source| source.trim_start_matches('(').trim_end_matches(')'));
+        lock.package
+            .iter()
+            .find(|p| {
+                p.name == name
+                    && (version.is_none() || version == Some(p.version.as_str()))
+                    && (source.is_none() || source == p.source.as_deref())
+            })
+            .map(purl)
+    };
+    Ok(lock
+        .package
+        .iter()
+        .map(|package| Component {
+            name: package.name.clone(),
+            version: package.version.clone(),
+            purl: purl(package),
+            source: package.source.clone(),
+            checksum: package.checksum.clone(),
+            license: None,
+            dependencies: package
+                .dependencies
+                .iter()
+                .filter_map(|dependency| find(dependency))
+                .collect(),
+        })
+        .collect())
+}
+
+/// The packages in an apk database such as `/lib/apk/db/installed`, for the distribution
+/// `distro`.
+pub fn apk_packages(db: &str, distro: &str) -> Vec<Component> {
+    db.split("\n\n")
+        .filter_map(|record| {
+            let field = |key: &str| {
+                record
+                    .lines()
+                    .find_map(|line| line.strip_prefix(key))
+                    .map(str::to_string)
+            };
+            let name = field("P:")?;
+            let version = field("V:")?;
+            let mut purl = format!("pkg:apk/{}/{}@{}", distro, escape(&name), escape(&version));
+            if let Some(arch) = field("A:") {
+                purl.push_str(&format!("?arch={}", arch));
+            }
+            Some(Component {
+                name,
+                version,
+                purl,
+                source: None,
+                checksum: None,
+                license: field("L:").map(|license| license_expression(&license)),
+                dependencies: Vec::new(),
+            })
+        })
+        .collect()
+}
+
+/// apk lists several licenses separated by spaces, which SPDX expressions join with `AND`.
+fn license_expression(license: &str) -> String {
+    if license.contains(" AND ") || license.contains(" OR ") {
+        return license.to_string();
+    }
+    license.split_whitespace().collect::<Vec<_>>().join(" AND ")
+}
+
+/// The installed packages in a dpkg status file such as `/var/lib/dpkg/status`, for the
+/// distribution `distro`.
+pub fn deb_packages(status: &str, distro: &str) -> Vec<Component> {
+    status
+        .split("\n\n")
+        .filter_map(|record| {
+            let field = |key: &str| {
+                record
+                    .lines()
+                    .find_map(|line| line.strip_prefix(key))
+                    .map(|value| value.trim().to_string())
+            };
+            if !field("Status:")?.ends_with(" installed") {
+                return None;
+            }
+            let name = field("Package:")?;
+            let version = field("Version:")?;
+            let mut purl = format!("pkg:deb/{}/{}@{}", distro, escape(&name), escape(&version));
+            if let Some(arch) = field("Architecture:") {
+                purl.push_str(&format!("?arch={}", arch));
+            }
+            Some(Component {
+                name,
+                version,
+                purl,
+                source: None,
+                checksum: None,
+                license: None,
+                dependencies: Vec::new(),
+            })
+        })
+        .collect()
+}
+
+/// The `ID` of an `/etc/os-release`, e.g. `alpine`.
+pub fn os_id(os_release: &str) -> Option<String> {
+    os_release
+        .lines()
+        .find_map(|line| line.strip_prefix("ID="))
+        .map(|id| id.trim_matches('"').to_string())
+}
+
+/// The files of an image an SBOM is made from.
+#[derive(Debug, Clone, Default)]
+pub struct ImageFiles {
+    /// The contents of [`LOCK_FILE`].
+    pub lock: Option<String>,
+    pub apk_db: Option<String>,
+    pub dpkg_status: Option<String>,
+    pub os_release: Option<String>,
+}
+
+impl ImageFiles {
+    /// Reads the files of the local image `tag` through a container `engine` creates from it,
+    /// without starting it.
+    pub fn from_engine(engine: &dyn ContainerEngine, tag: &str) -> Result<ImageFiles> {
+        let output = Command::new(engine.binary())
+            .args(engine.create_args(tag)?)
+            .stdin(Stdio::null())
+            .stderr(Stdio::inherit())
+            .output()
+            .with_context(|| format!("couldn't run {}", engine.binary()))?;
+        if !output.status.success() {
+            bail!(
+                "{} couldn't create a container from {} ({})",
+                engine.binary(),
+                tag,
+                output.status
+            );
+        }
+        let container = String::from_utf8_lossy(&output.stdout).trim().to_string();
+        let read = |path: &str| -> Result<Option<String>> {
+            let output = Command::new(engine.binary())
+                .args(engine.copy_out_args(&container, path))
+                .stdin(Stdio::null())
+                .stderr(Stdio::null())
+                .output()
+                .with_context(|| format!("couldn't run {}", engine.binary()))?;
+            // A missing file fails the copy.
+            if !output.status.success() {
+                return Ok(None);
+            }
+            let mut archive = tar::Archive::new(output.stdout.as_slice());
+            for entry in archive.entries()? {
+                let mut entry = entry?;
+                if entry.header().entry_type().is_file() {
+                    let mut contents = String::new();
+                    entry
+                        .read_to_string(&mut contents)
+                        .with_context(|| format!("Couldn't read {} from {}", path, tag))?;
+                    return Ok(Some(contents));
+                }
+            }
+            Ok(None)
+        };
+        let files = (|| {
+            Ok(ImageFiles {
+                lock: read(LOCK_FILE)?,
+                apk_db: read(APK_DB)?,
+                dpkg_status: read(DPKG_STATUS)?,
+                os_release: read(OS_RELEASE)?,
+            })
+        })();
+        let _ = Command::new(engine.binary())
+            .args(engine.remove_args(&container))
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status();
+        files
+    }
+}
+
+/// The contents of a release image.
+#[derive(Debug, Clone)]
+pub struct Sbom {
+    /// The image described, e.g. `quay.io/myorg/hello:1.0.0`.
+    pub image: String,
+    /// The image the service was added to.
+    pub runtime_image: String,
+    pub crates: Vec<Component>,
+    pub packages: Vec<Component>,
+    /// When the SBOM was made, in RFC 3339.
+    pub timestamp: String,
+}
+
+impl Sbom {
+    /// Collects the crates and packages of `image` from its `files`.
+    pub fn new(image: &str, runtime_image: &str, files: &ImageFiles) -> Result<Sbom> {
+        let lock = match &files.lock {
+            Some(lock) => lock,
+            None => bail!(
+                "{} has no {}. Please build it with 'roche release --sbom', which keeps the lock file, or copy /app-build/Cargo.lock to /app in your Release.Dockerfile",
+                image,
+                LOCK_FILE
+            ),
+        };
+        let distro = files.os_release.as_deref().and_then(os_id);
+        let packages = match (&files.apk_db, &files.dpkg_status) {
+            (Some(db), _) => apk_packages(db, distro.as_deref().unwrap_or("alpine")),
+            (None, Some(status)) => deb_packages(status, distro.as_deref().unwrap_or("debian")),
+            (None, None) => Vec::new(),
+        };
+        Ok(Sbom {
+            image: image.to_string(),
+            runtime_image: runtime_image.to_string(),
+            crates: crates(lock)?,
+            packages,
+            timestamp: timestamp()?,
+        })
+    }
+
+    /// The document in `format` as pretty printed JSON.
+    pub fn render(&self, format: Format) -> Result<String> {
+        let document = match format {
+            Format::CycloneDx => self.cyclonedx(),
+            Format::Spdx => self.spdx(),
+        };
+        let mut out = serde_json::to_string_pretty(&document)
+            .with_context(|| format!("Couldn't serialize the {} SBOM", format))?;
+        out.push('\n');
+        Ok(out)
+    }
+
+    /// A CycloneDX 1.5 document.
+    pub fn cyclonedx(&self) -> serde_json::Value {
+        let components: Vec<serde_json::Value> = self
+            .components()
+            .map(|component| {
+                let mut value = json!({
+                    "type": "library",
+                    "bom-ref": component.purl,
+                    "name": component.name,
+                    "version": component.version,
+                    "purl": component.purl,
+                });
+                if let Some(license) = &component.license {
+                    value["licenses"] = json!([{ "expression": license }]);
+                }
+                if let Some(checksum) = &component.checksum {
+                    value["hashes"] = json!([{ "alg": "SHA-256", "content": checksum }]);
+                }
+                value
+            })
+            .chain(std::iter::once(json!({
+                "type": "container",
+                "bom-ref": "runtime-image",
+                "name": self.runtime_image,
+            })))
+            .collect();
+        let mut dependencies = vec![json!({
+            "ref": "image",
+            "dependsOn": self
+                .components()
+                .map(|component| component.purl.as_str())
+                .chain(std::iter::once("runtime-image"))
+                .collect::<Vec<_>>(),
+        })];
+        dependencies.extend(self.crates.iter().map(
+            |component| json!({ "ref": component.purl, "dependsOn": component.dependencies }),
+        ));
+        json!({
+            "bomFormat": "CycloneDX",
+            "specVersion": "1.5",
+            "serialNumber": format!("urn:uuid:{}", self.uuid()),
+            "version": 1,
+            "metadata": {
+                "timestamp": self.timestamp,
+                "tools": {
+                    "components": [{
+                        "type": "application",
+                        "name": "roche",
+                        "version": env!("CARGO_PKG_VERSION"),
+                    }],
+                },
+                "component": {
+                    "type": "container",
+                    "bom-ref": "image",
+                    "name": self.image,
+                },
+            },
+            "components": components,
+            "dependencies": dependencies,
+        })
+    }
+
+    /// An SPDX 2.3 document.
+    pub fn spdx(&self) -> serde_json::Value {
+        let mut packages = vec![
+            json!({
+                "name": self.image,
+                "SPDXID": "SPDXRef-Image",
+                "downloadLocation": "NOASSERTION",
+                "filesAnalyzed": false,
+                "primaryPackagePurpose": "CONTAINER",
+            }),
+            json!({
+                "name": self.runtime_image,
+                "SPDXID": "SPDXRef-RuntimeImage",
+                "downloadLocation": "NOASSERTION",
+                "filesAnalyzed": false,
+                "primaryPackagePurpose": "CONTAINER",
+            }),
+        ];
+        let mut relationships = vec![
+            relationship("SPDXRef-DOCUMENT", "DESCRIBES", "SPDXRef-Image"),
+            relationship("SPDXRef-Image", "DESCENDANT_OF", "SPDXRef-RuntimeImage"),
+        ];
+        let ids: BTreeMap<&str, String> = self
+            .components()
+            .map(|component| (component.purl.as_str(), spdx_id(component)))
+            .collect();
+        for component in self.components() {
+            let id = &ids[component.purl.as_str()];
+            let mut package = json!({
+                "name": component.name,
+                "SPDXID": id,
+                "versionInfo": component.version,
+                "downloadLocation": component.download_location(),
+                "filesAnalyzed": false,
+                "licenseConcluded": "NOASSERTION",
+                "licenseDeclared": component.license.as_deref().unwrap_or("NOASSERTION"),
+                "externalRefs": [{
+                    "referenceCategory": "PACKAGE-MANAGER",
+                    "referenceType": "purl",
+                    "referenceLocator": component.purl,
+                }],
+            });
+            if let Some(checksum) = &component.checksum {
+                package["checksums"] =
+                    json!([{ "algorithm": "SHA256", "checksumValue": checksum }]);
+            }
+            packages.push(package);
+            relationships.push(relationship("SPDXRef-Image", "CONTAINS", id));
+            for dependency in &component.dependencies {
+                if let Some(dependency) = ids.get(dependency.as_str()) {
+                    relationships.push(relationship(id, "DEPENDS_ON", dependency));
+                }
+            }
+        }
+        json!({
+            "spdxVersion": "SPDX-2.3",
+            "dataLicense": "CC0-1.0",
+            "SPDXID": "SPDXRef-DOCUMENT",
+            "name": self.image,
+            "documentNamespace": format!("https://github.com/roche-rs/roche/sbom/{}", self.uuid()),
+            "creationInfo": {
+                "created": self.timestamp,
+                "creators": [format!("Tool: roche-{}", env!("CARGO_PKG_VERSION"))],
+            },
+            "packages": packages,
+            "relationships": relationships,
+        })
+    }
+
+    /// The crates and then the packages.
+    fn components(&self) -> impl Iterator<Item = &Component> {
+        self.crates.iter().chain(self.packages.iter())
+    }
+
+    /// A UUID named after the image and its contents, the same for the same image. It is a
+    /// version 8 UUID of RFC 9562 made from SHA-256, as version 5 is tied to SHA-1.
+    fn uuid(&self) -> String {
+        let mut hasher = Sha256::new();
+        hasher.update(self.image.as_bytes());
+        for component in self.components() {
+            hasher.update(component.purl.as_bytes());
+        }
+        let mut hex: Vec<char> = format!("{:x}", hasher.finalize())
+            .chars()
+            .take(32)
+            .collect();
+        // Version 8 and the RFC 9562 variant.
+        hex[12] = '8';
+        hex[16] = ['8', '9', 'a', 'b'][hex[16].to_digit(16).unwrap_or(0) as usize % 4];
+        let hex: String = hex.into_iter().collect();
+        format!(
+            "{}-{}-{}-{}-{}",
+            &hex[..8],
+            &hex[8..12],
+            &hex[12..16],
+            &hex[16..20],
+            &hex[20..]
+        )
+    }
+}
+
+/// Writes the SBOM of a release image and optionally attaches it to the pushed image.
+#[derive(Debug, Clone)]
+pub struct SbomRequest {
+    pub tag: String,
+    pub runtime_image: String,
+    pub formats: Vec<Format>,
+    /// The files are this path with each format's extension, e.g. `./sbom.cdx.json`.
+    pub output: PathBuf,
+    /// Whether the files are pushed to the registry as artifacts referring to the image.
+    pub attach: bool,
+}
+
+impl SbomRequest {
+    /// The file the document in `format` is written to.
+    pub fn file(&self, format: Format) -> PathBuf {
+        let name = self
+            .output
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_else(|| DEFAULT_NAME.to_string());
+        self.output
+            .with_file_name(format!("{}.{}", name, format.extension()))
+    }
+
+    /// Writes the SBOMs made from `files`, then attaches them when asked, returning the files.
+    pub fn execute(&self, files: &ImageFiles) -> Result<Vec<PathBuf>> {
+        let written = self.write(files)?;
+        if self.attach {
+            self.attach()?;
+        }
+        Ok(written)
+    }
+
+    /// Writes the SBOMs made from `files` and returns the files. Fails before writing anything
+    /// when they are to be attached and `oras` is missing.
+    pub fn write(&self, files: &ImageFiles) -> Result<Vec<PathBuf>> {
+        if self.attach && !engine::on_path("oras") {
+            bail!("Attaching the SBOM needs oras. Please install it from https://oras.land");
+        }
+        let sbom = Sbom::new(&self.tag, &self.runtime_image, files)?;
+        let mut written = Vec::new();
+        for format in &self.formats {
+            let file = self.file(*format);
+            fs::write(&file, sbom.render(*format)?)
+                .with_context(|| format!("Couldn't write {}", file.display()))?;
+            println!(
+                "Roche: Wrote {} {} SBOM with {} crates and {} packages",
+                file.display(),
+                format,
+                sbom.crates.len(),
+                sbom.packages.len()
+            );
+            written.push(file);
+        }
+        Ok(written)
+    }
+
+    /// Pushes the written documents to the registry as artifacts referring to the pushed image.
+    pub fn attach(&self) -> Result<()> {
+        for format in &self.formats {
+            self.attach_file(*format)?;
+        }
+        Ok(())
+    }
+
+    /// Pushes the document in `format` to the registry as an artifact referring to the image.
+    fn attach_file(&self, format: Format) -> Result<()> {
+        let file = self.file(format);
+        let dir = file.parent().filter(|dir| !dir.as_os_str().is_empty());
+        let name = file.file_name().unwrap_or_default().to_string_lossy();
+        // oras only takes paths below its working folder.
+        let status = Command::new("oras")
+            .arg("attach")
+            .arg("--artifact-type")
+            .arg(format.media_type())
+            .arg(&self.tag)
+            .arg(format!("{}:{}", name, format.media_type()))
+            .current_dir(dir.unwrap_or_else(|| Path::new(".")))
+            .stdin(Stdio::null())
+            .status()
+            .context("couldn't run oras")?;
+        if !status.success() {
+            bail!(
+                "oras couldn't attach {} to {} ({})",
+                file.display(),
+                self.tag,
+                status
+            );
+        }
+        println!("Roche: Attached the {} SBOM to {}", format, self.tag);
+        Ok(())
+    }
+}
+
+/// Now, or `SOURCE_DATE_EPOCH` for reproducible builds, in RFC 3339.
+fn timestamp() -> Result<String> {
+    let seconds = match env::var("SOURCE_DATE_EPOCH") {
+        Ok(epoch) => epoch
+            .trim()
+            .parse::<u64>()
+            .with_context(|| format!("Invalid SOURCE_DATE_EPOCH '{}'", epoch))?,
+        Err(_) => SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default(),
+    };
+    Ok(humantime::format_rfc3339_seconds(UNIX_EPOCH + Duration::from_secs(seconds)).to_string())
+}
+
+fn relationship(element: &str, kind: &str, related: &str) -> serde_json::Value {
+    json!({
+        "spdxElementId": element,
+        "relationshipType": kind,
+        "relatedSpdxElement": related,
+    })
+}
+
+/// An SPDX id for `component`, e.g. `SPDXRef-cargo-serde-1.0.130`. Crates from git or another
+/// registry are told apart from those on crates.io by a hash of their source.
+fn spdx_id(component: &Component) -> String {
+    let kind = component
+        .purl
+        .trim_start_matches("pkg:")
+        .split('/')
+        .next()
+        .unwrap_or_default();
+    let mut id = format!("SPDXRef-{}-{}-{}", kind, component.name, component.version);
+    if let Some(source) = component.source.as_deref().filter(|s| !is_crates_io(s)) {
+        id.push('-');
+        id.push_str(&format!("{:x}", Sha256::digest(source.as_bytes()))[..8]);
+    }
+    id.chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '.' || c == '-' {
+                c
+            } else {
+                '-'
+            }
+        })
+        .collect()
+}
+
+/// Whether the lock file `source` of a crate is crates.io.
+fn is_crates_io(source: &str) -> bool {
+    source == "registry+https://github.com/rust-lang/crates.io-index"
+        || source == "sparse+https://index.crates.io/"
+}
+
+/// Percent-encodes `value` for a package URL.
+fn escape(value: &str) -> String {
+    value
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'.' | b'-' | b'_' | b'~' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+#[derive(Deserialize)]
+struct Lock {
+    #[serde(default)]
+    package: Vec<LockPackage>,
+}
+
+#[derive(Deserialize)]
+struct LockPackage {
+    name: String,
+    version: String,
+    source: Option<String>,
+    checksum: Option<String>,
+    #[serde(default)]
+    dependencies: Vec<String>,
+}
@@ -0,0 +1,457 @@
+//! Scanning release images for known vulnerabilities.
+//!
+//! roche runs a scanner such as `trivy` or `grype` on the image, reads its JSON report and fails
+//! when a vulnerability reaches the severity threshold from `[scan]` in `roche.toml`.
+
+use crate::engine;
+use anyhow::{anyhow, bail, Context, Result};
+use serde::Deserialize;
+use std::collections::BTreeSet;
+use std::convert::TryFrom;
+use std::fmt;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+use std::str::FromStr;
+
+/// The scanners roche knows how to drive, in auto-detection order.
+pub const SCANNERS: [&str; 2] = ["trivy", "grype"];
+
+/// How severe a vulnerability is, ordered from least to most severe.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Deserialize)]
+#[serde(try_from = "String")]
+pub enum Severity {
+    Unknown,
+    Negligible,
+    Low,
+    Medium,
+    High,
+    Critical,
+}
+
+impl Severity {
+    /// All severities, from most to least severe.
+    pub const ALL: [Severity; 6] = [
+        Severity::Critical,
+        Severity::High,
+        Severity::Medium,
+        Severity::Low,
+        Severity::Negligible,
+        Severity::Unknown,
+    ];
+}
+
+impl FromStr for Severity {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Severity> {
+        match s.to_ascii_lowercase().as_str() {
+            "unknown" => Ok(Severity::Unknown),
+            "negligible" => Ok(Severity::Negligible),
+            "low" => Ok(Severity::Low),
+            "medium" => Ok(Severity::Medium),
+            "high" => Ok(Severity::High),
+            "critical" => Ok(Severity::Critical),
+            _ => Err(anyhow!(
+                "'{}' is not a severity, use one of critical, high, medium, low, negligible or unknown",
+                s
+            )),
+        }
+    }
+}
+
+impl TryFrom<String> for Severity {
+    type Error = anyhow::Error;
+
+    fn try_from(s: String) -> Result<Severity> {
+        s.parse()
+    }
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Severity::Unknown => "UNKNOWN",
+            Severity::Negligible => "NEGLIGIBLE",
+            Severity::Low => "LOW",
+            Severity::Medium => "MEDIUM",
+            Severity::High => "HIGH",
+            Severity::Critical => "CRITICAL",
+        };
+        f.write_str(name)
+    }
+}
+
+/// A vulnerability a scanner found in a package of the image.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Vulnerability {
+    /// e.g. `CVE-2021-3711`.
+    pub id: String,
+    pub package: String,
+    pub installed: String,
+    /// The first version fixing it, if there is one.
+    pub fixed: Option<String>,
+    pub severity: Severity,
+}
+
+/// Where the scanner reads the image from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ImageSource {
+    /// The local storage of the engine called this, `docker` or `podman`.
+    Engine(&'static str),
+    /// The registry the image was pushed to.
+    Registry,
+    /// An OCI tarball such as the one `release --oci` writes.
+    Archive(PathBuf),
+}
+
+impl ImageSource {
+    /// The source of images built with `engine`. buildah keeps its images in the same storage as
+    /// podman.
+    pub fn engine(engine: &str) -> ImageSource {
+        match engine {
+            "docker" => ImageSource::Engine("docker"),
+            _ => ImageSource::Engine("podman"),
+        }
+    }
+}
+
+/// A vulnerability scanner with a JSON report.
+pub trait Scanner {
+    /// Name of the executable that is invoked for this scanner.
+    fn binary(&self) -> &'static str;
+
+    /// Arguments that scan `image` from `source` and print a JSON report on stdout.
+    fn scan_args(&self, image: &str, source: &ImageSource) -> Vec<String>;
+
+    /// Reads the vulnerabilities from a JSON report.
+    fn parse(&self, report: &str) -> Result<Vec<Vulnerability>>;
+}
+
+pub struct Trivy;
+pub struct Grype;
+
+impl Scanner for Trivy {
+    fn binary(&self) -> &'static str {
+        "trivy"
+    }
+
+    fn scan_args(&self, image: &str, source: &ImageSource) -> Vec<String> {
+        let mut args = vec![
+            "image".to_string(),
+            "--format".to_string(),
+            "json".to_string(),
+            "--quiet".to_string(),
+        ];
+        match source {
+            ImageSource::Engine(engine) => {
+                args.push("--image-src".to_string());
+                args.push(engine.to_string());
+                args.push(image.to_string());
+            }
+            ImageSource::Registry => {
+                args.push("--image-src".to_string());
+                args.push("remote".to_string());
+                args.push(image.to_string());
+            }
+            ImageSource::Archive(path) => {
+                args.push("--input".to_string());
+                args.push(path.display().to_string());
+            }
+        }
+        args
+    }
+
+    fn parse(&self, report: &str) -> Result<Vec<Vulnerability>> {
+        let report: TrivyReport =
+            serde_json::from_str(report).context("Couldn't parse the trivy report")?;
+        report
+            .results
+            .unwrap_or_default()
+            .into_iter()
+            .flat_map(|result| result.vulnerabilities.unwrap_or_default())
+            .map(|v| {
+                Ok(Vulnerability {
+                    id: v.vulnerability_id,
+                    package: v.pkg_name,
+                    installed: v.installed_version,
+                    fixed: v.fixed_version.filter(|fixed| !fixed.is_empty()),
+                    severity: v.severity.parse()?,
+                })
+            })
+            .collect()
+    }
+}
+
+impl Scanner for Grype {
+    fn binary(&self) -> &'static str {
+        "grype"
+    }
+
+    fn scan_args(&self, image: &str, source: &ImageSource) -> Vec<String> {
+        let image = match source {
+            ImageSource::Engine(engine) => format!("{}:{}", engine, image),
+            ImageSource::Registry => format!("registry:{}", image),
+            ImageSource::Archive(path) => format!("oci-archive:{}", path.display()),
+        };
+        vec![
+            image,
+            "-o".to_string(),
+            "json".to_string(),
+            "-q".to_string(),
+        ]
+    }
+
+    fn parse(&self, report: &str) -> Result<Vec<Vulnerability>> {
+        let report: GrypeReport =
+            serde_json::from_str(report).context("Couldn't parse the grype report")?;
+        report
+            .matches
+            .into_iter()
+            .map(|m| {
+                Ok(Vulnerability {
+                    id: m.vulnerability.id,
+                    package: m.artifact.name,
+                    installed: m.artifact.version,
+                    fixed: m.vulnerability.fix.versions.into_iter().next(),
+                    severity: m.vulnerability.severity.parse()?,
+                })
+            })
+            .collect()
+    }
+}
+
+/// Returns the scanner implementation for a name such as `trivy` or `grype`.
+pub fn from_name(name: &str) -> Result<Box<dyn Scanner>> {
+    match name {
+        "trivy" => Ok(Box::new(Trivy)),
+        "grype" => Ok(Box::new(Grype)),
+        other => bail!(
+            "Unknown scanner '{}'. Supported scanners are {}",
+            other,
+            SCANNERS.join(", ")
+        ),
+    }
+}
+
+/// Selects the scanner from the `--scanner` flag or config, then the first one on the PATH.
+pub fn select(name: Option<&str>) -> Result<Box<dyn Scanner>> {
+    if let Some(name) = name {
+        return from_name(name);
+    }
+    match SCANNERS.iter().find(|scanner| engine::on_path(scanner)) {
+        Some(name) => from_name(name),
+        None => bail!(
+            "No vulnerability scanner found. Please install one of {} or pass --scanner",
+            SCANNERS.join(", ")
+        ),
+    }
+}
+
+/// Scans an image and checks it against the severity threshold.
+#[derive(Debug, Clone)]
+pub struct ScanRequest {
+    pub tag: String,
+    pub source: ImageSource,
+    /// Vulnerabilities at or above this severity fail the scan.
+    pub threshold: Severity,
+    /// Vulnerability ids that never fail the scan, e.g. `CVE-2021-3711`.
+    pub ignore: BTreeSet<String>,
+}
+
+impl ScanRequest {
+    /// Runs `scanner` on the image and prints a summary. Fails when the scanner fails or the
+    /// image has vulnerabilities at or above the threshold.
+    pub fn execute(&self, scanner: &dyn Scanner) -> Result<ScanReport> {
+        println!("Roche: Scanning {} with {}", self.tag, scanner.binary());
+        let output = Command::new(scanner.binary())
+            .args(scanner.scan_args(&self.tag, &self.source))
+            .stdin(Stdio::null())
+            .stderr(Stdio::inherit())
+            .output()
+            .with_context(|| format!("couldn't run {}", scanner.binary()))?;
+        if !output.status.success() {
+            bail!(
+                "{} failed to scan {} ({})",
+                scanner.binary(),
+                self.tag,
+                output.status
+            );
+        }
+        let report = ScanReport {
+            vulnerabilities: scanner.parse(&String::from_utf8_lossy(&output.stdout))?,
+        };
+        let failing = report.failing(self.threshold, &self.ignore);
+        if !failing.is_empty() {
+            print!("{}", table(&failing));
+        }
+        println!("Roche: Scan found {}", report);
+        if !failing.is_empty() {
+            bail!(
+                "{} has {} {} at or above {}. Please update the affected packages or list accepted ids under scan.ignore",
+                self.tag,
+                failing.len(),
+                if failing.len() == 1 {
+                    "vulnerability"
+                } else {
+                    "vulnerabilities"
+                },
+                self.threshold
+            );
+        }
+        println!("Roche: No vulnerabilities at or above {}", self.threshold);
+        Ok(report)
+    }
+}
+
+/// The vulnerabilities found in an image.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ScanReport {
+    pub vulnerabilities: Vec<Vulnerability>,
+}
+
+impl ScanReport {
+    /// How many vulnerabilities have `severity`.
+    pub fn count(&self, severity: Severity) -> usize {
+        self.vulnerabilities
+            .iter()
+            .filter(|v| v.severity == severity)
+            .count()
+    }
+
+    /// The vulnerabilities at or above `threshold` not listed in `ignore`, most severe first.
+    pub fn failing(&self, threshold: Severity, ignore: &BTreeSet<String>) -> Vec<Vulnerability> {
+        let mut failing: Vec<Vulnerability> = self
+            .vulnerabilities
+            .iter()
+            .filter(|v| v.severity >= threshold && !ignore.contains(&v.id))
+            .cloned()
+            .collect();
+        failing.sort_by(|a, b| {
+            b.severity
+                .cmp(&a.severity)
+                .then_with(|| a.package.cmp(&b.package))
+                .then_with(|| a.id.cmp(&b.id))
+        });
+        failing
+    }
+}
+
+impl fmt::Display for ScanReport {
+    /// e.g. `3 vulnerabilities: 1 CRITICAL, 2 LOW`.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let total = self.vulnerabilities.len();
+        write!(
+            f,
+            "{} {}",
+            total,
+            if total == 1 {
+                "vulnerability"
+            } else {
+                "vulnerabilities"
+            }
+        )?;
+        let counts: Vec<String> = Severity::ALL
+            .iter()
+            .map(|severity| (severity, self.count(*severity)))
+            .filter(|(_, count)| *count > 0)
+            .map(|(severity, count)| format!("{} {}", count, severity))
+            .collect();
+        if !counts.is_empty() {
+            write!(f, ": {}", counts.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+/// Renders `vulnerabilities` as a table of severity, id, package, installed and fixed version.
+pub fn table(vulnerabilities: &[Vulnerability]) -> String {
+    let rows: Vec<[String; 5]> = vulnerabilities
+        .iter()
+        .map(|v| {
+            [
+                v.severity.to_string(),
+                v.id.clone(),
+                v.package.clone(),
+                v.installed.clone(),
+                v.fixed.clone().unwrap_or_else(|| "-".to_string()),
+            ]
+        })
+        .collect();
+    let headers = ["SEVERITY", "ID", "PACKAGE", "INSTALLED", "FIXED"];
+    let widths: Vec<usize> = (0..4)
+        .map(|i| {
+            rows.iter()
+                .map(|row| row[i].len())
+                .fold(headers[i].len(), usize::max)
+        })
+        .collect();
+    let line = |cells: [&str; 5]| {
+        let mut line = String::new();
+        for (cell, width) in cells.iter().zip(&widths) {
+            line.push_str(&format!("{:width$}  ", cell, width = width));
+        }
+        line.push_str(cells[4]);
+        line.push('\n');
+        line
+    };
+    let mut out = line(headers);
+    for row in &rows {
+        out.push_str(&line([&row[0], &row[1], &row[2], &row[3], &row[4]]));
+    }
+    out
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct TrivyReport {
+    results: Option<Vec<TrivyResult>>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct TrivyResult {
+    vulnerabilities: Option<Vec<TrivyVulnerability>>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct TrivyVulnerability {
+    #[serde(rename = "VulnerabilityID")]
+    vulnerability_id: String,
+    pkg_name: String,
+    installed_version: String,
+    fixed_version: Option<String>,
+    severity: String,
+}
+
+#[derive(Deserialize)]
+struct GrypeReport {
+    #[serde(default)]
+    matches: Vec<GrypeMatch>,
+}
+
+#[derive(Deserialize)]
+struct GrypeMatch {
+    vulnerability: GrypeVulnerability,
+    artifact: GrypeArtifact,
+}
+
+#[derive(Deserialize)]
+struct GrypeVulnerability {
+    id: String,
+    severity: String,
+    #[serde(default)]
+    fix: GrypeFix,
+}
+
+#[derive(Default, Deserialize)]
+struct GrypeFix {
+    #[serde(default)]
+    versions: Vec<String>,
+}
+
+#[derive(Deserialize)]
+struct GrypeArtifact {
+    name: String,
+    version: String,
+}
@@ -0,0 +1,131 @@
+//! Secrets a build needs, and the files that must never end up in an image.
+//!
+//! Settings in `.env` are handed to the container when it starts, see
+//! [`RunRequest`](crate::run::RunRequest), and are never copied into an image. A secret the
+//! build itself needs, such as the token of a private crate registry, is listed under
+//! `[secrets]` in `roche.toml` and passed to the engine with `--secret`. The cargo steps mount
+//! it and read it into the variable it is named after, so it never reaches an image layer.
+
+use anyhow::{bail, Context, Result};
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use ignore::Match;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::env;
+use std::path::{Path, PathBuf};
+
+/// Where BuildKit mounts a secret during a `RUN` step.
+pub const SECRETS_DIR: &str = "/run/secrets";
+
+/// Names of files holding credentials, written like `.gitignore` lines. A release image or a
+/// generated Dockerfile copying one of them is refused. The last pattern a file matches is the
+/// one reported, so the more specific ones come later, and the `!` patterns at the end let the
+/// example env files projects commit through.
+pub const SECRET_PATTERNS: [&str; 20] = [
+    "*.env",
+    ".env.*",
+    ".env",
+    "*.pem",
+    "*.key",
+    "*.p12",
+    "*.pfx",
+    "id_rsa*",
+    "id_ecdsa*",
+    "id_ed25519*",
+    ".npmrc",
+    ".netrc",
+    ".pgpass",
+    ".git-credentials",
+    ".dockercfg",
+    "credentials",
+    "credentials.json",
+    "!.env.example",
+    "!.env.sample",
+    "!.env.template",
+];
+
+/// Where a build secret comes from, exactly one of `env` or `file`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Secret {
+    /// Environment variable holding the secret when roche runs.
+    pub env: Option<String>,
+    /// File holding the secret, relative to `roche.toml`.
+    pub file: Option<PathBuf>,
+}
+
+impl Secret {
+    /// The value of the engine's `--secret` flag for the secret `name`.
+    pub fn engine_flag(&self, name: &str) -> String {
+        match (&self.env, &self.file) {
+            (Some(var), _) => format!("id={},env={}", name, var),
+            (None, Some(file)) => format!("id={},src={}", name, file.display()),
+            (None, None) => format!("id={}", name),
+        }
+    }
+
+    /// Checks the secret `name` can be read before the build starts.
+    pub fn check(&self, name: &str) -> Result<()> {
+        if let Some(var) = &self.env {
+            if env::var_os(var).is_none() {
+                bail!(
+                    "secrets.{} reads the environment variable {}, which isn't set",
+                    name,
+                    var
+                );
+            }
+        }
+        if let Some(file) = &self.file {
+            if !file.is_file() {
+                bail!("secrets.{}.file {} does not exist", name, file.display());
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Whether `name` can name a secret, which becomes an environment variable of the cargo steps.
+pub fn is_name(name: &str) -> bool {
+    name.chars()
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// The flags and variable assignments that put `secrets` in the environment of a cargo step,
+/// each followed by a space, e.g. `--mount=type=secret,id=TOKEN TOKEN="$(cat /run/secrets/TOKEN)" `.
+pub fn run_prefix(secrets: &BTreeMap<String, Secret>) -> String {
+    let mounts = secrets
+        .keys()
+        .map(|name| format!("--mount=type=secret,id={},required=true ", name));
+    let variables = secrets
+        .keys()
+        .map(|name| format!("{}=\"$(cat {}/{})\" ", name, SECRETS_DIR, name));
+    mounts.chain(variables).collect()
+}
+
+/// Fails when one of `files`, relative to the build context, looks like it holds credentials.
+pub fn check_image_files(files: &[PathBuf]) -> Result<()> {
+    let patterns = patterns()?;
+    for file in files {
+        if let Match::Ignore(glob) = patterns.matched_path_or_any_parents(file, false) {
+            bail!(
+                "{} would be copied into the image but matches the secret pattern '{}'. Please add it to {} and pass runtime settings with an env file or build secrets under [secrets] instead",
+                file.display(),
+                glob.original(),
+                crate::context::IGNORE_FILE
+            );
+        }
+    }
+    Ok(())
+}
+
+fn patterns() -> Result<Gitignore> {
+    let mut builder = GitignoreBuilder::new(Path::new(""));
+    for pattern in SECRET_PATTERNS.iter() {
+        builder
+            .add_line(None, pattern)
+            .with_context(|| format!("Invalid secret pattern {}", pattern))?;
+    }
+    builder.build().context("Invalid secret patterns")
+}
@@ -0,0 +1,183 @@
+//! The service crate that wraps `functions::handler()`.
+//!
+//! The base images build functions in `/app-build`, a crate whose `src/main.rs` nests the
+//! handler under `/` and listens on `PORT`, and start the `roche-service` binary with `run.sh`.
+//! The same files are embedded here so native builds use them and `roche eject` can write them
+//! out next to `functions.rs`, where builds pick them up instead of the ones in the image.
+
+use anyhow::{bail, Context, Result};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const SERVICE_MAIN: &str = include_str!("template/service/main.rs");
+const SERVICE_CARGO: &str = include_str!("template/service/Cargo.toml.liquid");
+const SERVICE_RUN: &str = include_str!("template/service/run.sh");
+
+/// Version of the service crate, matching the base images it was taken from.
+pub const SERVICE_VERSION: &str = "1.4.0";
+
+/// Name of the service binary in the base images.
+pub const SERVICE_BINARY: &str = "roche-service";
+
+/// The files `roche eject` writes next to `functions.rs`.
+pub const SERVICE_FILES: [&str; 3] = ["Cargo.toml", "main.rs", "run.sh"];
+
+/// Renders the service `main.rs`.
+///
+/// `functions` is the path `functions.rs` is included from. Without it the module is found
+/// next to `main.rs`, as it is in the images.
+pub fn render_main(functions: Option<&Path>) -> Result<String> {
+    let functions = match functions {
+        Some(path) => liquid::model::Value::scalar(format!("{:?}", path.display().to_string())),
+        None => liquid::model::Value::Nil,
+    };
+    render(
+        "service main.rs",
+        SERVICE_MAIN,
+        &liquid::object!({ "functions": functions }),
+    )
+}
+
+/// Renders the service `Cargo.toml` with the binary built from `main`.
+pub fn render_manifest(main: &str) -> Result<String> {
+    render(
+        "service Cargo.toml",
+        SERVICE_CARGO,
+        &liquid::object!({
+            "version": SERVICE_VERSION,
+            "main": format!("{:?}", main),
+        }),
+    )
+}
+
+/// Renders `run.sh`, which starts the service in the runtime image.
+pub fn render_run() -> Result<String> {
+    render(
+        "service run.sh",
+        SERVICE_RUN,
+        &liquid::object!({ "version": SERVICE_VERSION }),
+    )
+}
+
+/// Whether the service has been ejected into the function folder `dir`.
+pub fn ejected(dir: &Path) -> bool {
+    dir.join("main.rs").exists()
+}
+
+/// Writes the service files next to `functions.rs` in `dir`, refusing to overwrite existing
+/// ones unless `force` is set.
+pub fn eject(dir: &Path, force: bool) -> Result<Vec<PathBuf>> {
+    let paths: Vec<PathBuf> = SERVICE_FILES.iter().map(|file| dir.join(file)).collect();
+    let existing: Vec<String> = paths
+        .iter()
+        .filter(|path| path.exists())
+        .map(|path| path.display().to_string())
+        .collect();
+    if !existing.is_empty() && !force {
+        bail!(
+            "{} already exists. Please delete it or use --force to overwrite it",
+            existing.join(", ")
+        );
+    }
+
+    let contents = [
+        render_manifest("src/main.rs")?,
+        render_main(None)?,
+        render_run()?,
+    ];
+    for (path, contents) in paths.iter().zip(contents.iter()) {
+        fs::write(path, contents).with_context(|| format!("Couldn't write {}", path.display()))?;
+    }
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let run = dir.join("run.sh");
+        fs::set_permissions(&run, fs::Permissions::from_mode(0o755))
+            .with_context(|| format!("Couldn't make {} executable", run.display()))?;
+    }
+    Ok(paths)
+}
+
+/// The service `Cargo.toml` for the function in `dir` with `dependencies` added, or `None` when
+/// the one in the base image can be used as it is.
+///
+/// The ejected `Cargo.toml` is used as the base when there is one. Otherwise a `Cargo.toml`
+/// beside `functions.rs` may hold just a `[dependencies]` section, which is merged into the
+/// embedded manifest before `dependencies`, so entries in `dependencies` win.
+pub fn manifest(
+    dir: &Path,
+    dependencies: &BTreeMap<String, toml::Value>,
+) -> Result<Option<String>> {
+    let local = dir.join("Cargo.toml");
+    let (base, mut merged) = if ejected(dir) && local.exists() {
+        let base = fs::read_to_string(&local)
+            .with_context(|| format!("Couldn't read {}", local.display()))?;
+        (base, BTreeMap::new())
+    } else if local.exists() {
+        let fragment: toml::Value = fs::read_to_string(&local)
+            .with_context(|| format!("Couldn't read {}", local.display()))?
+            .parse()
+            .with_context(|| format!("Invalid {}", local.display()))?;
+        let fragment = match fragment.get("dependencies") {
+            Some(toml::Value::Table(table)) => table.clone().into_iter().collect(),
+            Some(_) => bail!(
+                "Invalid {}: [dependencies] must be a table",
+                local.display()
+            ),
+            None => BTreeMap::new(),
+        };
+        (render_manifest("src/main.rs")?, fragment)
+    } else {
+        (render_manifest("src/main.rs")?, BTreeMap::new())
+    };
+    merged.extend(dependencies.clone());
+    if merged.is_empty() {
+        // An ejected manifest is used as written, comments included.
+        return Ok(if ejected(dir) { Some(base) } else { None });
+    }
+    for (name, value) in &merged {
+        check_dependency(name, value)?;
+    }
+
+    let mut manifest: toml::Value = base.parse().context("Invalid service Cargo.toml")?;
+    let table = match manifest.as_table_mut() {
+        Some(table) => table,
+        None => bail!("Invalid service Cargo.toml"),
+    };
+    let deps = table
+        .entry("dependencies".to_string())
+        .or_insert_with(|| toml::Value::Table(Default::default()));
+    match deps.as_table_mut() {
+        Some(deps) => deps.extend(merged),
+        None => bail!("Invalid service Cargo.toml: [dependencies] must be a table"),
+    }
+    Ok(Some(toml::to_string(&manifest)?))
+}
+
+/// Checks a dependency can be fetched inside the build image, so it is a version or a table
+/// without a `path`.
+pub fn check_dependency(name: &str, value: &toml::Value) -> Result<()> {
+    match value {
+        toml::Value::String(_) => Ok(()),
+        toml::Value::Table(table) if table.contains_key("path") => bail!(
+            "dependency '{}' uses a path, which isn't available inside the build image",
+            name
+        ),
+        toml::Value::Table(_) => Ok(()),
+        _ => bail!(
+            "dependency '{}' must be a version string or a table like {{ version = \"1.0\" }}",
+            name
+        ),
+    }
+}
+
+fn render(name: &str, source: &str, values: &liquid::Object) -> Result<String> {
+    let template = liquid::ParserBuilder::with_stdlib()
+        .build()?
+        .parse(source)
+        .with_context(|| format!("Couldn't parse template {}", name))?;
+    template
+        .render(values)
+        .with_context(|| format!("Couldn't render template {}", name))
+}
@@ -0,0 +1,12 @@
+{% if functions %}#[path = {{ functions }}]
+{% endif %}mod functions;
+
+#[async_std::main]
+async fn main() -> tide::Result<()> {
+    tide::log::start();
+    let mut app = tide::new();
+    app.at("/").nest(functions::handler());
+    let port = std::env::var("PORT").unwrap_or_else(|_| "8080".to_string());
+    app.listen(format!("0.0.0.0:{}", port)).await?;
+    Ok(())
+}
@@ -0,0 +1,8 @@
+#!/bin/sh
+# Starts roche-service {{ version }}, exporting the values in .env when there is one.
+if [ -f .env ]; then
+    set -a
+    . ./.env
+    set +a
+fi
+exec ./roche-service
@@ -0,0 +1,155 @@
+//! Rebuilding and restarting the dev container whenever the function changes.
+
+use crate::build::BuildRequest;
+use crate::context;
+use crate::engine::ContainerEngine;
+use crate::run::{self, RunRequest};
+use anyhow::{bail, Context, Result};
+use notify::{DebouncedEvent, RecursiveMode, Watcher};
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+use std::process::Child;
+use std::sync::mpsc::{channel, RecvTimeoutError};
+use std::time::{Duration, Instant};
+
+/// How long the files have to be quiet before a rebuild starts.
+pub const DEBOUNCE: Duration = Duration::from_millis(500);
+
+/// A dev loop that rebuilds `build` and restarts `run` when a watched file changes.
+#[derive(Debug, Clone)]
+pub struct WatchRequest {
+    pub build: BuildRequest,
+    pub run: RunRequest,
+    pub debounce: Duration,
+}
+
+impl WatchRequest {
+    /// The files a change to which triggers a rebuild: `functions.rs` or the modules in
+    /// `functions/`, `lib.rs`, the modules they declare with `mod` and the env files.
+    pub fn watched_files(&self) -> Result<Vec<PathBuf>> {
+        let dir = &self.build.context_dir;
+        let mut files: Vec<PathBuf> = context::source_files(dir, &self.build.functions)?
+            .iter()
+            .map(|file| dir.join(file))
+            .collect();
+        // Watched before it exists, so adding one rebuilds.
+        let lib = dir.join("lib.rs");
+        if !files.contains(&lib) {
+            files.push(lib);
+        }
+        files.extend(self.run.env_files.iter().cloned());
+        Ok(files)
+    }
+
+    /// Builds and runs the function, then rebuilds and restarts it on every change until Ctrl-C.
+    pub fn execute(&self, engine: &dyn ContainerEngine) -> Result<()> {
+        // Reject engines that can't run containers before the first build.
+        engine.run_args(&self.run.name, &self.run.tag, &[])?;
+
+        let mut files = self.watched_files()?;
+        let (tx, rx) = channel();
+        let mut watcher =
+            notify::watcher(tx, self.debounce).context("Couldn't start the file watcher")?;
+        let mut dirs = BTreeSet::new();
+        watch_dirs(&mut watcher, &mut dirs, &files)?;
+        run::remove_on_interrupt(engine, &self.run.name)?;
+
+        let mut container = self.rebuild(engine)?;
+        println!("Roche: Watching for changes. Press Ctrl-C to stop");
+        while !run::interrupted() {
+            let event = match rx.recv_timeout(Duration::from_millis(200)) {
+                Ok(event) => event,
+                Err(RecvTimeoutError::Timeout) => continue,
+                Err(RecvTimeoutError::Disconnected) => bail!("The file watcher stopped"),
+            };
+            let path = match changed_path(&event) {
+                Some(path) => path,
+                None => continue,
+            };
+            // A new file may be a module of the function, like functions/orders.rs.
+            if let DebouncedEvent::Create(_) | DebouncedEvent::Rename(_, _) = event {
+                files = self.watched_files()?;
+                watch_dirs(&mut watcher, &mut dirs, &files)?;
+            }
+            if !files.iter().any(|file| file == path) {
+                continue;
+            }
+
+            println!("Roche: {} changed, rebuilding", path.display());
+            if let Some(mut child) = container.take() {
+                self.run.stop(engine)?;
+                let _ = child.wait();
+            }
+            container = self.rebuild(engine)?;
+            // The change may have declared or dropped a module.
+            files = self.watched_files()?;
+            watch_dirs(&mut watcher, &mut dirs, &files)?;
+        }
+
+        if let Some(mut child) = container {
+            let _ = child.wait();
+        }
+        println!("Roche: Stopped {}", self.run.name);
+        Ok(())
+    }
+
+    /// Builds the image and starts it. A failed build is reported and leaves nothing running.
+    fn rebuild(&self, engine: &dyn ContainerEngine) -> Result<Option<Child>> {
+        let started = Instant::now();
+        if let Err(e) = self.build.execute(engine) {
+            eprintln!("Error: {:?}", e);
+            println!(
+                "Roche: Build failed after {:.1}s, waiting for changes",
+                started.elapsed().as_secs_f64()
+            );
+            return Ok(None);
+        }
+        println!("Roche: Built in {:.1}s", started.elapsed().as_secs_f64());
+
+        // Ctrl-C during the build has already cleaned up, so don't start another container.
+        if run::interrupted() {
+            return Ok(None);
+        }
+        println!(
+            "Roche: Running {} as {} on http://localhost:{}/",
+            self.run.tag, self.run.name, self.run.host_port
+        );
+        let child = self
+            .run
+            .command(engine)?
+            .spawn()
+            .with_context(|| format!("Couldn't run {}", engine.binary()))?;
+        Ok(Some(child))
+    }
+}
+
+/// Watches the folders of `files` not in `dirs` yet, so files created in them later, like a new
+/// `.env`, are seen as well. Folders that don't exist yet are left for a later call.
+fn watch_dirs<W: Watcher>(
+    watcher: &mut W,
+    dirs: &mut BTreeSet<PathBuf>,
+    files: &[PathBuf],
+) -> Result<()> {
+    for dir in files.iter().filter_map(|file| file.parent()) {
+        if dirs.contains(dir) || !dir.is_dir() {
+            continue;
+        }
+        watcher
+            .watch(dir, RecursiveMode::NonRecursive)
+            .with_context(|| format!("Couldn't watch {}", dir.display()))?;
+        dirs.insert(dir.to_path_buf());
+    }
+    Ok(())
+}
+
+/// The file an event leaves changed, if any.
+fn changed_path(event: &DebouncedEvent) -> Option<&Path> {
+    match event {
+        DebouncedEvent::Create(path)
+        | DebouncedEvent::Write(path)
+        | DebouncedEvent::Chmod(path)
+        | DebouncedEvent::Remove(path)
+        | DebouncedEvent::Rename(_, path) => Some(path),
+        _ => None,
+    }
+}
@@ -0,0 +1,94 @@
+mod common;
+
+use common::{fake_engine, roche, setup};
+use remove_dir_all::*;
+use roche::build::parse_step;
+use std::process::Command;
+
+const FUNCTION: &str = r#"pub fn handler() -> tide::Server<()> {
+    let mut api = tide::new();
+    api.at("/").get(|_| async { Ok("Hello, world!") });
+    api
+}"#;
+
+#[test]
+fn failed_build_propagates_exit_code() {
+    let path = setup("failed_build_propagates_exit_code", FUNCTION);
+    let bin = path.join("bin");
+    fake_engine(
+        &bin,
+        "docker",
+        "echo 'Step 1/3 : FROM quay.io/roche/dev-default:1.4.0 as builder'\n\
+         echo 'Step 2/3 : RUN cargo build'\n\
+         echo 'error[E0425]: cannot find value `api` in this scope' >&2\n\
+         exit 101\n",
+    );
+
+    let output = Command::new(roche())
+        .arg("build")
+        .arg("--engine")
+        .arg("docker")
+        .arg("-t")
+        .arg("registry/failed")
+        .env("PATH", &bin)
+        .current_dir(&path)
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(101));
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stdout.contains("Step 2/3 : RUN cargo build"));
+    assert!(!stdout.contains("Build complete"));
+    assert!(stderr.contains("error[E0425]"));
+    assert!(stderr.contains("at step 'Step 2/3: RUN cargo build'"));
+
+    remove_dir_all(path).unwrap();
+}
+
+#[test]
+fn successful_build_completes() {
+    let path = setup("successful_build_completes", FUNCTION);
+    let bin = path.join("bin");
+    fake_engine(
+        &bin,
+        "docker",
+        "echo 'Successfully tagged registry/success'\n",
+    );
+
+    let output = Command::new(roche())
+        .arg("build")
+        .arg("--engine")
+        .arg("docker")
+        .arg("-t")
+        .arg("registry/success")
+        .env("PATH", &bin)
+        .current_dir(&path)
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("Successfully tagged registry/success"));
+    assert!(stdout.contains("Roche: Build complete for registry/success"));
+
+    remove_dir_all(path).unwrap();
+}
+
+#[test]
+fn parse_engine_steps() {
+    assert_eq!(
+        parse_step("Step 2/5 : RUN cargo build"),
+        Some("Step 2/5: RUN cargo build".to_string())
+    );
+    assert_eq!(
+        parse_step("STEP 2/5: RUN cargo build --release"),
+        Some("Step 2/5: RUN cargo build --release".to_string())
+    );
+    assert_eq!(
+        parse_step("#7 [builder 2/3] RUN cargo build"),
+        Some("[builder 2/3] RUN cargo build".to_string())
+    );
+    assert_eq!(parse_step("#7 DONE 0.1s"), None);
+    assert_eq!(parse_step("   Compiling roche-service v0.1.0"), None);
+}
@@ -0,0 +1,135 @@
+mod common;
+
+use common::setup;
+use remove_dir_all::*;
+use roche::{BuildKind, BuildRequest};
+use std::fs;
+use std::path::Path;
+
+#[test]
+fn render_dev_dockerfile() {
+    let path = setup("render_dev_dockerfile", "");
+    let request = request(BuildKind::Dev, &path);
+
+    let df = request.render_dockerfile().unwrap();
+    assert!(df.starts_with("FROM build/image as builder\n"));
+    assert!(df.contains("FROM runtime/image\n"));
+    assert!(df.contains("/app-build/target/debug/roche-service ./\n"));
+    assert!(!df.contains(".env"));
+
+    // Settings in .env are given to the container by 'roche run', never baked in.
+    fs::write(path.join(".env"), "MY_VAR=hello").unwrap();
+    let df = request.render_dockerfile().unwrap();
+    assert!(df.contains("/app-build/target/debug/roche-service ./\n"));
+    assert!(!df.contains(".env"));
+
+    remove_dir_all(path).unwrap();
+}
+
+#[test]
+fn render_test_dockerfile() {
+    let path = setup("render_test_dockerfile", "");
+    let request = request(BuildKind::Test, &path);
+
+    let df = request.render_dockerfile().unwrap();
+    assert!(df.starts_with("FROM build/image\n"));
+    assert!(df.contains("RUN cargo test --lib"));
+    assert!(!df.contains("runtime/image"));
+
+    remove_dir_all(path).unwrap();
+}
+
+#[test]
+fn render_release_dockerfile() {
+    let path = setup("render_release_dockerfile", "");
+    let request = request(BuildKind::Release, &path);
+
+    let df = request.render_dockerfile().unwrap();
+    assert!(df.starts_with("FROM build/image as builder\n"));
+    assert!(df.contains("FROM runtime/image\n"));
+    assert!(!df.contains("COPY lib.rs"));
+    assert!(!df.contains("cargo test"));
+
+    fs::write(path.join("lib.rs"), "").unwrap();
+    let df = request.render_dockerfile().unwrap();
+    assert!(df.contains("COPY lib.rs /app-build/src"));
+    assert!(df.contains("RUN cargo test --lib --release"));
+
+    remove_dir_all(path).unwrap();
+}
+
+#[test]
+fn render_user_template_override() {
+    let path = setup("render_user_template_override", "");
+    let templates = path.join(".roche").join("templates");
+    fs::create_dir_all(&templates).unwrap();
+    fs::write(
+        templates.join("Release.Dockerfile"),
+        "FROM {{ build_image }}\n{% if env %}COPY .env /app-build/src\n{% endif %}LABEL tag={{ tag }} kind={{ kind }}\n",
+    )
+    .unwrap();
+
+    let src = path.join("src");
+    fs::create_dir_all(&src).unwrap();
+    fs::write(src.join(".env"), "MY_VAR=hello").unwrap();
+    let request = request(BuildKind::Release, &src);
+
+    assert_eq!(
+        request.template_override(),
+        Some(templates.join("Release.Dockerfile"))
+    );
+    let df = request.render_dockerfile().unwrap();
+    assert_eq!(
+        df,
+        "FROM build/image\nCOPY .env /app-build/src\nLABEL tag=registry/request kind=release\n"
+    );
+
+    // The dev build still uses the template shipped with roche.
+    let dev = BuildRequest {
+        kind: BuildKind::Dev,
+        ..request
+    };
+    assert_eq!(dev.template_override(), None);
+
+    remove_dir_all(path).unwrap();
+}
+
+#[test]
+fn render_invalid_template_fails() {
+    let path = setup("render_invalid_template_fails", "");
+    let templates = path.join(".roche").join("templates");
+    fs::create_dir_all(&templates).unwrap();
+    fs::write(templates.join("Dev.Dockerfile"), "FROM {% if lib %}").unwrap();
+
+    let err = request(BuildKind::Dev, &path)
+        .render_dockerfile()
+        .unwrap_err();
+    assert!(format!("{}", err).contains("Dev.Dockerfile"));
+
+    remove_dir_all(path).unwrap();
+}
+
+#[test]
+fn function_dir_finds_src_folder() {
+    let path = setup("function_dir_finds_src_folder", "");
+    assert_eq!(roche::function_dir(&path), Some(path.clone()));
+
+    let project = path.join("project");
+    fs::create_dir_all(project.join("src")).unwrap();
+    assert_eq!(roche::function_dir(&project), None);
+    fs::write(project.join("src").join("functions.rs"), "").unwrap();
+    assert_eq!(roche::function_dir(&project), Some(project.join("src")));
+
+    remove_dir_all(path).unwrap();
+}
+
+fn request(kind: BuildKind, path: &Path) -> BuildRequest {
+    BuildRequest {
+        kind,
+        build_image: "build/image".to_string(),
+        runtime_image: "runtime/image".to_string(),
+        tag: "registry/request".to_string(),
+        context_dir: path.to_path_buf(),
+        ..Default::default()
+    }
+}
@@ -0,0 +1,189 @@
+mod common;
+
+use common::{fake_engine, roche, setup, FUNCTION};
+use remove_dir_all::*;
+use roche::build::{BuildCache, BuildKind, BuildRequest, CacheStats};
+use std::fs;
+use std::process::{Command, Stdio};
+
+const REGISTRY_MOUNT: &str = "--mount=type=cache,id=roche-cargo-registry,target=/usr/local/cargo/registry,from=build/image,source=/usr/local/cargo/registry,sharing=shared ";
+const TARGET_MOUNT: &str = "--mount=type=cache,id=roche-target,target=/app-build/target,from=build/image,source=/app-build/target,sharing=locked ";
+
+#[test]
+fn cache_mounts_wrap_cargo() {
+    let path = setup("cache_mounts_wrap_cargo", FUNCTION);
+    fs::write(path.join("lib.rs"), "").unwrap();
+    fs::write(
+        path.join("Cargo.toml"),
+        "[dependencies]\nserde_yaml = \"0.8\"\n",
+    )
+    .unwrap();
+    let release = BuildRequest {
+        kind: BuildKind::Release,
+        build_image: "build/image".to_string(),
+        runtime_image: "runtime/image".to_string(),
+        tag: "registry/cache".to_string(),
+        context_dir: path.clone(),
+        cache: BuildCache {
+            mounts: true,
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let mounts = format!("{}{}", REGISTRY_MOUNT, TARGET_MOUNT);
+
+    let df = release.render_dockerfile().unwrap();
+    assert!(df.contains(&format!("RUN {}cargo fetch\n", mounts)));
+    assert!(df.contains(&format!(
+        "RUN {}cargo build --release && cp /app-build/target/release/roche-service /app-build/\n",
+        mounts
+    )));
+    assert!(df.contains(&format!("RUN {}cargo test --lib --release\n", mounts)));
+    assert!(df.contains(
+        "COPY --from=builder --chown=rocheuser /app-build/run.sh /app-build/Cargo.toml /app-build/roche-service ./\n"
+    ));
+
+    let dev = BuildRequest {
+        kind: BuildKind::Dev,
+        cache: BuildCache {
+            mounts: true,
+            cargo_home: Some("/root/.cargo/".to_string()),
+            ..Default::default()
+        },
+        ..release.clone()
+    };
+    let df = dev.render_dockerfile().unwrap();
+    assert!(df.contains(
+        "RUN --mount=type=cache,id=roche-cargo-registry,target=/root/.cargo/registry,from=build/image,source=/root/.cargo/registry,sharing=shared "
+    ));
+    assert!(df.contains("cargo build && cp /app-build/target/debug/roche-service /app-build/\n"));
+    assert!(df.contains(" /app-build/Cargo.toml /app-build/roche-service ./\n"));
+
+    let test = BuildRequest {
+        kind: BuildKind::Test,
+        ..release.clone()
+    };
+    assert!(test
+        .render_dockerfile()
+        .unwrap()
+        .contains(&format!("RUN {}cargo test --lib\n", mounts)));
+
+    // Without mounts the binary stays in the target dir.
+    let plain = BuildRequest {
+        cache: BuildCache::default(),
+        ..release
+    };
+    let df = plain.render_dockerfile().unwrap();
+    assert!(df.contains("RUN cargo build --release\n"));
+    assert!(df.contains(" /app-build/target/release/roche-service ./\n"));
+
+    remove_dir_all(path).unwrap();
+}
+
+#[test]
+fn cache_settings_reach_the_engine() {
+    let path = setup("cache_settings_reach_the_engine", FUNCTION);
+    fs::write(
+        path.join("roche.toml"),
+        r#"
+[cache]
+mounts = true
+from = "type=registry,ref=quay.io/myorg/hello:cache"
+to = "type=registry,ref=quay.io/myorg/hello:cache,mode=max"
+"#,
+    )
+    .unwrap();
+    let bin = path.join("bin");
+    fake_engine(
+        &bin,
+        "docker",
+        "echo '#1 [internal] load build definition from Dockerfile' >&2\n\
+         echo '#2 [builder 1/4] FROM quay.io/roche/default:1.4.0' >&2\n\
+         echo '#2 CACHED' >&2\n\
+         echo '#3 [builder 2/4] COPY functions.rs /app-build/src' >&2\n\
+         echo '#3 CACHED' >&2\n\
+         echo '#4 [builder 3/4] RUN cargo build --release' >&2\n\
+         echo '#4 0.512    Compiling roche-service v0.1.0 (/app-build)' >&2\n\
+         echo '#4 9.871     Finished release [optimized] target(s) in 9.80s' >&2\n\
+         echo '#4 DONE 10.1s' >&2\n\
+         echo '#5 [stage-1 2/3] RUN addgroup -S -g 10001 rocheuser' >&2\n\
+         echo '#5 CACHED' >&2\n",
+    );
+
+    let output = Command::new(roche())
+        .arg("release")
+        .arg("-e")
+        .arg("docker")
+        .arg("-t")
+        .arg("quay.io/myorg/hello:1.0.0")
+        .env("PATH", &bin)
+        .current_dir(&path)
+        .stdin(Stdio::null())
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let args = fs::read_to_string(bin.join("docker.args")).unwrap();
+    assert_eq!(
+        args.trim(),
+        "build -tquay.io/myorg/hello:1.0.0 -f- \
+         --cache-from type=registry,ref=quay.io/myorg/hello:cache \
+         --cache-to type=registry,ref=quay.io/myorg/hello:cache,mode=max ."
+    );
+    let df = fs::read_to_string(bin.join("docker.dockerfile")).unwrap();
+    assert!(df.contains(
+        "RUN --mount=type=cache,id=roche-cargo-registry,target=/usr/local/cargo/registry,from=quay.io/roche/default:1.4.0,"
+    ));
+    assert!(String::from_utf8_lossy(&output.stdout)
+        .contains("Roche: Cache hits 2 of 3 steps (66%), 1 crate compiled"));
+
+    // .rocherc settings win over roche.toml.
+    fs::write(path.join(".rocherc"), "cache_mounts=false\n").unwrap();
+    let output = Command::new(roche())
+        .arg("config")
+        .arg("show")
+        .current_dir(&path)
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("cache.mounts           = false  # environment variable cache_mounts"));
+    assert!(stdout.contains(
+        "cache.to               = \"type=registry,ref=quay.io/myorg/hello:cache,mode=max\""
+    ));
+
+    remove_dir_all(path).unwrap();
+}
+
+#[test]
+fn cache_stats_from_engine_output() {
+    let mut podman = CacheStats::default();
+    for line in &[
+        "STEP 1/9: FROM quay.io/roche/default:1.4.0 AS builder",
+        "STEP 2/9: COPY functions.rs /app-build/src",
+        "--> Using cache 3f2a9c0d1e",
+        "--> 3f2a9c0d1e",
+        "STEP 3/9: RUN cargo build --release",
+        "   Compiling functions v0.1.0",
+        "   Compiling roche-service v0.1.0 (/app-build)",
+        "    Finished release [optimized] target(s) in 31.02s",
+    ] {
+        podman.track(line);
+    }
+    assert_eq!((podman.steps, podman.cached, podman.compiled), (2, 1, 2));
+    assert_eq!(podman.hit_rate(), 50);
+
+    let mut docker = CacheStats::default();
+    for line in &[
+        "Step 1/3 : FROM quay.io/roche/default:1.4.0 as builder",
+        " ---> 5d4e1f",
+        "Step 2/3 : COPY functions.rs /app-build/src",
+        " ---> Using cache",
+        "Step 3/3 : RUN cargo build --release",
+        " ---> Using cache",
+    ] {
+        docker.track(line);
+    }
+    assert_eq!(
+        format!("{}", docker),
+        "hits 2 of 2 steps (100%), 0 crates compiled"
+    );
+}
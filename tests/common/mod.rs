@@ -0,0 +1,101 @@
+//! Fixtures shared by the integration tests. Each test crate uses a few of them.
+#![allow(dead_code)]
+
+use remove_dir_all::*;
+use std::env;
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::*;
+
+static CNT: AtomicUsize = AtomicUsize::new(0);
+thread_local!(static IDX: usize = CNT.fetch_add(1, Ordering::SeqCst));
+
+/// The smallest function roche builds.
+pub const FUNCTION: &str = "pub fn handler() -> tide::Server<()> {\n    tide::new()\n}\n";
+
+/// An empty folder for the test `name`, replacing what an earlier run left behind.
+pub fn fresh_dir(name: &str) -> PathBuf {
+    let path = root(name);
+    if Path::new(&path).exists() {
+        remove_dir_all(&path).unwrap();
+    }
+    fs::create_dir_all(&path)
+        .unwrap_or_else(|_| panic!("couldn't create {:?} directory", path.display()));
+    path
+}
+
+/// A folder for the test `name` holding `function` as its `functions.rs`.
+pub fn setup(name: &str, function: &str) -> PathBuf {
+    let path = fresh_dir(name);
+    fs::write(path.join("functions.rs"), function).unwrap();
+    path
+}
+
+/// The roche binary under test.
+pub fn roche() -> PathBuf {
+    let mut me = env::current_exe().expect("couldn't find current exe");
+    me.pop(); // chop off exe name
+    me.pop(); // chop off `deps`
+    me.push("roche");
+    me
+}
+
+pub fn root(name: &str) -> PathBuf {
+    let idx = IDX.with(|x| *x);
+
+    let mut me = env::current_exe().expect("couldn't find current exe");
+    me.pop(); // chop off exe name
+    me.pop(); // chop off `deps`
+    me.pop(); // chop off `debug` / `release`
+    me.push("generated-tests");
+    me.push(format!("test-{}-{}", idx, name));
+    me
+}
+
+/// Writes the executable `script` to `bin/name`.
+pub fn write_script(bin: &Path, name: &str, script: &str) {
+    fs::create_dir_all(bin).unwrap();
+    let file = bin.join(name);
+    fs::write(&file, script).unwrap();
+    fs::set_permissions(&file, fs::Permissions::from_mode(0o755)).unwrap();
+}
+
+/// Writes a stand-in for the engine `name` that appends its arguments to `bin/<name>.args`,
+/// keeps the Dockerfile of its last build in `bin/<name>.dockerfile` and then runs the shell
+/// `body`.
+pub fn fake_engine(bin: &Path, name: &str, body: &str) {
+    write_script(
+        bin,
+        name,
+        &format!(
+            "#!/bin/sh\n\
+             echo \"$@\" >> {dir}/{name}.args\n\
+             case \"$1\" in\n\
+             build|bud|buildx) /bin/rm -f {dir}/{name}.dockerfile\n\
+             while IFS= read -r line; do echo \"$line\" >> {dir}/{name}.dockerfile; done ;;\n\
+             esac\n\
+             {body}",
+            dir = bin.display(),
+            name = name,
+            body = body
+        ),
+    );
+}
+
+/// Compares `actual` with `tests/golden/<name>`, rewriting the golden file instead when
+/// `UPDATE_GOLDEN` is set. Roche's version is written as `VERSION` so releases don't change
+/// them.
+pub fn assert_golden(name: &str, actual: &Path) {
+    let golden = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests")
+        .join("golden")
+        .join(name);
+    let actual = fs::read_to_string(actual)
+        .unwrap()
+        .replace(env!("CARGO_PKG_VERSION"), "VERSION");
+    if env::var_os("UPDATE_GOLDEN").is_some() {
+        fs::write(&golden, &actual).unwrap();
+    }
+    assert_eq!(actual, fs::read_to_string(&golden).unwrap(), "{}", name);
+}
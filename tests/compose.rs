@@ -0,0 +1,96 @@
+mod common;
+
+use common::{assert_golden, roche, setup, FUNCTION};
+use remove_dir_all::*;
+use std::fs;
+use std::process::Command;
+
+const MANIFEST: &str = r#"
+[tag]
+name = "hello"
+
+[ports]
+host = 3000
+
+[services.mongodb]
+
+[services.postgres]
+env = { POSTGRES_USER = "app", POSTGRES_PASSWORD = "s3cr@t/:", POSTGRES_DB = "hello" }
+
+[services.cache]
+kind = "redis"
+image = "redis:6"
+
+[services.mail]
+image = "mailhog/mailhog"
+port = 1025
+"#;
+
+#[test]
+fn gen_compose_matches_golden_file() {
+    let path = setup("gen_compose_matches_golden_file", FUNCTION);
+    fs::write(path.join("roche.toml"), MANIFEST).unwrap();
+    fs::write(path.join(".env"), "RUST_LOG=info\n").unwrap();
+
+    let output = Command::new(roche())
+        .arg("gen")
+        .arg("compose")
+        .current_dir(&path)
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert_golden("docker-compose.yml", &path.join("docker-compose.yml"));
+    // The stack builds the function from the release Dockerfile.
+    assert!(fs::read_to_string(path.join("Dockerfile"))
+        .unwrap()
+        .contains("cargo build --release"));
+
+    let output = Command::new(roche())
+        .arg("gen")
+        .arg("compose")
+        .current_dir(&path)
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("Dockerfile already exists refusing to overwrite"));
+    assert!(stdout.contains("docker-compose.yml already exists refusing to overwrite"));
+
+    remove_dir_all(path).unwrap();
+}
+
+#[test]
+fn services_need_an_image() {
+    let path = setup("services_need_an_image", FUNCTION);
+    for (manifest, error) in &[
+        (
+            "[services.queue]\n",
+            "services.queue needs an image, roche only knows mongodb, postgres and redis",
+        ),
+        (
+            "[services.db]\nkind = \"mysql\"\n",
+            "services.db.kind 'mysql' is not one of mongodb, postgres or redis",
+        ),
+        (
+            "[tag]\nname = \"redis\"\n\n[services.redis]\n",
+            "services.redis has the same name as the function",
+        ),
+    ] {
+        fs::write(path.join("roche.toml"), manifest).unwrap();
+        let output = Command::new(roche())
+            .arg("gen")
+            .arg("compose")
+            .current_dir(&path)
+            .output()
+            .unwrap();
+        assert!(!output.status.success());
+        assert!(
+            String::from_utf8_lossy(&output.stderr).contains(error),
+            "{}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+        assert!(!path.join("docker-compose.yml").exists());
+        assert!(!path.join("Dockerfile").exists());
+    }
+
+    remove_dir_all(path).unwrap();
+}
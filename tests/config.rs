@@ -0,0 +1,226 @@
+mod common;
+
+use common::{fake_engine, roche, setup};
+use remove_dir_all::*;
+use roche::config::{Manifest, Source};
+use roche::{BuildKind, Config};
+use std::fs;
+use std::path::PathBuf;
+use std::process::Command;
+
+const FUNCTION: &str = r#"pub fn handler() -> tide::Server<()> {
+    let mut api = tide::new();
+    api.at("/").get(|_| async { Ok("Hello, world!") });
+    api
+}"#;
+
+const MANIFEST: &str = r#"
+[images]
+dev_build = "quay.io/myorg/dev:1.0"
+runtime = "quay.io/myorg/runtime:1.0"
+
+[tag]
+registry = "quay.io/myorg"
+name = "hello"
+version = "1.0.0"
+
+[build]
+engine = "docker"
+args = { RUST_LOG = "debug" }
+
+[ports]
+host = 9090
+
+[deploy.production]
+platform = "knative"
+namespace = "functions"
+"#;
+
+#[test]
+fn manifest_resolves_with_sources() {
+    let path = setup("manifest_resolves_with_sources", FUNCTION);
+    fs::write(path.join("roche.toml"), MANIFEST).unwrap();
+
+    let manifest_path = Manifest::find(&path).unwrap();
+    let manifest = Manifest::load(&manifest_path).unwrap();
+    let config = Config::resolve(&manifest, Some(manifest_path.clone())).unwrap();
+
+    let from_manifest = Source::Manifest(manifest_path);
+    assert_eq!(config.dev_build_image.value, "quay.io/myorg/dev:1.0");
+    assert_eq!(config.dev_build_image.source, from_manifest);
+    assert_eq!(config.release_build_image.value, roche::RELEASE_BUILD_IMAGE);
+    assert_eq!(config.release_build_image.source, Source::Default);
+    assert_eq!(config.host_port.value, 9090);
+    assert_eq!(config.container_port.value, 8080);
+    assert_eq!(config.build_args["RUST_LOG"], "debug");
+    assert_eq!(config.env_files.value, vec![PathBuf::from(".env")]);
+    assert_eq!(config.env_files.source, Source::Default);
+
+    let cli = config.build_image(BuildKind::Dev, Some("cli/image"));
+    assert_eq!(cli.value, "cli/image");
+    assert_eq!(cli.source, Source::Cli);
+
+    let engine = roche::engine::from_name("podman").unwrap();
+    assert_eq!(
+        config.image_tag(BuildKind::Dev, &path, engine.as_ref()),
+        Some("quay.io/myorg/dev-hello:1.0.0".to_string())
+    );
+
+    remove_dir_all(path).unwrap();
+}
+
+#[test]
+fn invalid_manifest_reports_the_key() {
+    let path = setup("invalid_manifest_reports_the_key", FUNCTION);
+    let manifest = path.join("roche.toml");
+
+    for (contents, expected) in &[
+        ("[images]\nruntim = \"x\"\n", "unknown field `runtim`"),
+        ("[build]\nengine = \"rkt\"\n", "build.engine 'rkt'"),
+        ("[ports]\nhost = 0\n", "ports.host"),
+        ("[ports]\nhost = 70000\n", "host"),
+        ("[tag]\nregistry = \"quay.io/\"\n", "tag.registry"),
+        ("[env]\nfiles = [\"missing.env\"]\n", "missing.env"),
+        ("[deploy.prod]\nplatform = \"lambda\"\n", "lambda"),
+    ] {
+        fs::write(&manifest, contents).unwrap();
+        let err = format!("{:?}", Manifest::load(&manifest).unwrap_err());
+        assert!(err.contains("roche.toml"), "{}", err);
+        assert!(
+            err.contains(expected),
+            "{} should mention {}",
+            err,
+            expected
+        );
+    }
+
+    remove_dir_all(path).unwrap();
+}
+
+#[test]
+fn config_show_prints_sources() {
+    let path = setup("config_show_prints_sources", FUNCTION);
+    fs::write(path.join("roche.toml"), MANIFEST).unwrap();
+    fs::write(path.join(".rocherc"), "runtime_image=rocherc/runtime\n").unwrap();
+
+    let output = Command::new(roche())
+        .arg("config")
+        .arg("show")
+        .current_dir(&path)
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let manifest = path.join("roche.toml");
+    assert!(stdout.contains(&format!(
+        "images.dev_build       = \"quay.io/myorg/dev:1.0\"  # {}",
+        manifest.display()
+    )));
+    assert!(stdout.contains(
+        "images.runtime         = \"rocherc/runtime\"  # environment variable runtime_image"
+    ));
+    assert!(stdout.contains(&format!(
+        "images.release_build   = \"{}\"  # default",
+        roche::RELEASE_BUILD_IMAGE
+    )));
+    assert!(stdout.contains("deploy.production.platform = knative"));
+
+    // The flags build and release take win over every other source.
+    let output = Command::new(roche())
+        .args(["config", "show", "-e", "podman", "-r", "cli/runtime"])
+        .current_dir(&path)
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("build.engine           = \"podman\"  # command line"));
+    assert!(stdout.contains("images.runtime         = \"cli/runtime\"  # command line"));
+
+    // Values from the environment and the command line are checked like the manifest's.
+    for (args, vars, expected) in &[
+        (
+            vec![],
+            vec![("tag_registry", "quay.io/")],
+            "tag_registry 'quay.io/'",
+        ),
+        (
+            vec![],
+            vec![("engine", "rkt")],
+            "engine 'rkt' is not supported",
+        ),
+        (
+            vec![],
+            vec![("host_port", "0")],
+            "host_port must be between 1 and 65535",
+        ),
+        (vec!["--scanner", "clair"], vec![], "scanner 'clair'"),
+    ] {
+        let output = Command::new(roche())
+            .args(["config", "show"])
+            .args(args)
+            .envs(vars.iter().cloned())
+            .current_dir(&path)
+            .output()
+            .unwrap();
+        assert!(!output.status.success());
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(stderr.contains(expected), "{}", stderr);
+    }
+
+    remove_dir_all(path).unwrap();
+}
+
+#[test]
+fn broken_manifest_leaves_help_and_init_working() {
+    let path = setup("broken_manifest_leaves_help_and_init_working", FUNCTION);
+    fs::write(path.join("roche.toml"), "[images]\nruntim = \"x\"\n").unwrap();
+    let roche_in = |args: &[&str]| {
+        Command::new(roche())
+            .args(args)
+            .current_dir(&path)
+            .output()
+            .unwrap()
+    };
+
+    assert!(roche_in(&["--help"]).status.success());
+    assert!(roche_in(&["--version"]).status.success());
+    fs::remove_file(path.join("functions.rs")).unwrap();
+    assert!(roche_in(&["init"]).status.success());
+    assert!(path.join("functions.rs").exists());
+
+    let output = roche_in(&["config", "show"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("unknown field `runtim`"));
+
+    remove_dir_all(path).unwrap();
+}
+
+#[test]
+fn build_uses_manifest() {
+    let path = setup("build_uses_manifest", FUNCTION);
+    fs::write(path.join("roche.toml"), MANIFEST).unwrap();
+    let bin = path.join("bin");
+    fake_engine(&bin, "docker", "");
+
+    let status = Command::new(roche())
+        .arg("build")
+        .arg("-b")
+        .arg("cli/build")
+        .env("PATH", &bin)
+        .current_dir(&path)
+        .status()
+        .unwrap();
+    assert!(status.success());
+
+    let args = fs::read_to_string(bin.join("docker.args")).unwrap();
+    assert_eq!(
+        args.trim(),
+        "build -tquay.io/myorg/dev-hello:1.0.0 -f- --build-arg RUST_LOG=debug ."
+    );
+    let df = fs::read_to_string(bin.join("docker.dockerfile")).unwrap();
+    assert!(df.contains("FROM cli/build as builder"));
+    assert!(df.contains("FROM quay.io/myorg/runtime:1.0"));
+
+    remove_dir_all(path).unwrap();
+}
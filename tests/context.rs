@@ -0,0 +1,169 @@
+mod common;
+
+use common::{roche, setup};
+use remove_dir_all::*;
+use roche::build::{BuildKind, BuildRequest};
+use std::collections::BTreeMap;
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+const FUNCTION: &str = "mod db;\nmod util {\n    pub mod format;\n}\n\npub fn handler() -> tide::Server<()> {\n    tide::new()\n}\n";
+
+#[test]
+fn context_holds_what_the_build_uses() {
+    let path = setup("context_holds_what_the_build_uses", FUNCTION);
+    let files: BTreeMap<&str, &str> = [
+        ("functions/db.rs", "mod pool;\n"),
+        ("functions/db/pool.rs", ""),
+        ("functions/util/format.rs", ""),
+        ("lib.rs", "#[path = \"shared/helpers.rs\"]\nmod helpers;\n"),
+        ("shared/helpers.rs", ""),
+        ("unused.rs", ""),
+        (".env", "GREETING=hello\n"),
+        ("notes.md", "# notes\n"),
+        ("secret.key", "hunter2\n"),
+        ("target/debug/roche-service", "binary"),
+        (".git/config", "[core]\n"),
+    ]
+    .iter()
+    .cloned()
+    .collect();
+    for (file, contents) in &files {
+        let file = path.join(file);
+        fs::create_dir_all(file.parent().unwrap()).unwrap();
+        fs::write(file, contents).unwrap();
+    }
+
+    let request = BuildRequest {
+        kind: BuildKind::Release,
+        build_image: "build/image".to_string(),
+        runtime_image: "runtime/image".to_string(),
+        tag: "registry/context".to_string(),
+        context_dir: path.clone(),
+        ..Default::default()
+    };
+    assert_eq!(
+        request.context_files().unwrap(),
+        paths(&[
+            "functions/db/pool.rs",
+            "functions/db.rs",
+            "functions/util/format.rs",
+            "functions.rs",
+            "lib.rs",
+            "shared/helpers.rs",
+        ])
+    );
+    let df = request.render_dockerfile().unwrap();
+    assert!(df.contains(
+        "COPY functions.rs /app-build/src\n\
+         COPY functions/db/pool.rs /app-build/src/functions/db/pool.rs\n\
+         COPY functions/db.rs /app-build/src/functions/db.rs\n\
+         COPY functions/util/format.rs /app-build/src/functions/util/format.rs\n\
+         COPY shared/helpers.rs /app-build/src/shared/helpers.rs\n\
+         COPY lib.rs /app-build/src\n"
+    ));
+    assert!(!df.contains(".env"));
+
+    // Ignored files stay out, and the templates stop copying them.
+    fs::write(
+        path.join(".rocheignore"),
+        "# secrets\n.env\nfunctions/db/\n",
+    )
+    .unwrap();
+    assert_eq!(
+        request.context_files().unwrap(),
+        paths(&[
+            "functions/db.rs",
+            "functions/util/format.rs",
+            "functions.rs",
+            "lib.rs",
+            "shared/helpers.rs",
+        ])
+    );
+    assert!(!request.render_dockerfile().unwrap().contains(".env"));
+
+    // A user template may copy anything, so it gets the whole folder less the build output.
+    let templates = path.join(".roche").join("templates");
+    fs::create_dir_all(&templates).unwrap();
+    fs::write(
+        templates.join("Release.Dockerfile"),
+        "FROM build/image\nCOPY . /src\n",
+    )
+    .unwrap();
+    fs::write(path.join(".rocheignore"), "*.key\n").unwrap();
+    assert_eq!(
+        request.context_files().unwrap(),
+        paths(&[
+            ".env",
+            ".rocheignore",
+            "functions/db/pool.rs",
+            "functions/db.rs",
+            "functions/util/format.rs",
+            "functions.rs",
+            "lib.rs",
+            "notes.md",
+            "shared/helpers.rs",
+            "unused.rs",
+        ])
+    );
+    // That includes .env, so the release is refused until it is ignored.
+    let err = request.check_image().unwrap_err();
+    assert!(format!("{}", err).starts_with(".env would be copied into the image"));
+    fs::write(path.join(".rocheignore"), "*.key\n.env\n").unwrap();
+    assert!(request.check_image().is_ok());
+
+    remove_dir_all(path).unwrap();
+}
+
+#[test]
+fn engine_builds_in_the_context() {
+    let path = setup("engine_builds_in_the_context", FUNCTION);
+    fs::create_dir_all(path.join("functions")).unwrap();
+    fs::write(path.join("functions").join("db.rs"), "").unwrap();
+    fs::create_dir_all(path.join("functions").join("util")).unwrap();
+    fs::write(path.join("functions").join("util").join("format.rs"), "").unwrap();
+    fs::create_dir_all(path.join("target")).unwrap();
+    fs::write(path.join("target").join("big"), vec![0; 4096]).unwrap();
+    let bin = path.join("bin");
+    fs::create_dir_all(&bin).unwrap();
+    let engine = bin.join("podman");
+    fs::write(
+        &engine,
+        format!(
+            "#!/bin/sh\nwhile IFS= read -r line; do :; done\n/usr/bin/find . -type f | /usr/bin/sort > {}/context\necho \"$PWD\" > {}/pwd\n",
+            bin.display(),
+            bin.display()
+        ),
+    )
+    .unwrap();
+    fs::set_permissions(&engine, fs::Permissions::from_mode(0o755)).unwrap();
+
+    let output = Command::new(roche())
+        .arg("build")
+        .arg("-e")
+        .arg("podman")
+        .arg("-t")
+        .arg("registry/context")
+        .env("PATH", &bin)
+        .current_dir(&path)
+        .stdin(Stdio::null())
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stdout).contains("Roche: Build context 3 files, 99 B"));
+    assert_eq!(
+        fs::read_to_string(bin.join("context")).unwrap(),
+        "./functions.rs\n./functions/db.rs\n./functions/util/format.rs\n"
+    );
+    // The copy is removed once the build is done.
+    let pwd = fs::read_to_string(bin.join("pwd")).unwrap();
+    assert!(!Path::new(pwd.trim()).exists());
+
+    remove_dir_all(path).unwrap();
+}
+
+fn paths(files: &[&str]) -> Vec<PathBuf> {
+    files.iter().map(PathBuf::from).collect()
+}
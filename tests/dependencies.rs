@@ -0,0 +1,133 @@
+mod common;
+
+use common::{roche, setup};
+use remove_dir_all::*;
+use roche::config::Manifest;
+use roche::{BuildKind, BuildRequest};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+#[test]
+fn dependencies_are_fetched_before_the_source_is_copied() {
+    let path = setup("dependencies_are_fetched_before_the_source_is_copied", "");
+    let dev = request(BuildKind::Dev, &path, "serde_yaml = \"0.8\"");
+
+    let df = dev.render_dockerfile().unwrap();
+    assert!(df.starts_with("FROM build/image as builder\nRUN printf '%s\\n' '"));
+    assert!(df.contains(
+        " > /app-build/Cargo.toml\nRUN cargo fetch\nCOPY . /app-build/src/\nRUN cargo build\n"
+    ));
+
+    // The printf line writes exactly the merged manifest.
+    let manifest = roche::service::manifest(&path, &dev.dependencies)
+        .unwrap()
+        .unwrap();
+    assert!(manifest.contains("serde_yaml = \"0.8\""));
+    assert!(manifest.contains("tide = "));
+    let start = df.find("printf").unwrap();
+    let end = df.find(" > /app-build/Cargo.toml").unwrap();
+    let output = Command::new("/bin/sh")
+        .arg("-c")
+        .arg(&df[start..end])
+        .output()
+        .unwrap();
+    assert_eq!(String::from_utf8_lossy(&output.stdout), manifest);
+
+    for kind in &[BuildKind::Test, BuildKind::Release] {
+        let df = request(*kind, &path, "serde_yaml = \"0.8\"")
+            .render_dockerfile()
+            .unwrap();
+        let fetch = df.find("RUN cargo fetch").unwrap();
+        let copy = df.find("COPY").unwrap();
+        assert!(fetch < copy, "{}", df);
+    }
+
+    remove_dir_all(path).unwrap();
+}
+
+#[test]
+fn no_dependencies_keeps_the_image_manifest() {
+    let path = setup("no_dependencies_keeps_the_image_manifest", "");
+    let df = request(BuildKind::Dev, &path, "")
+        .render_dockerfile()
+        .unwrap();
+    assert!(!df.contains("printf"));
+    assert!(!df.contains("cargo fetch"));
+    assert_eq!(
+        roche::service::manifest(&path, &BTreeMap::new()).unwrap(),
+        None
+    );
+
+    remove_dir_all(path).unwrap();
+}
+
+#[test]
+fn cargo_toml_beside_functions_is_merged() {
+    let path = setup("cargo_toml_beside_functions_is_merged", "");
+    fs::write(
+        path.join("Cargo.toml"),
+        "[dependencies]\nchrono = \"0.4\"\nuuid = \"0.7\"\n",
+    )
+    .unwrap();
+
+    let request = request(BuildKind::Dev, &path, "uuid = \"0.8.2\"");
+    let manifest = roche::service::manifest(&path, &request.dependencies)
+        .unwrap()
+        .unwrap();
+    let manifest: toml::Value = manifest.parse().unwrap();
+    let deps = manifest["dependencies"].as_table().unwrap();
+    assert_eq!(deps["chrono"].as_str(), Some("0.4"));
+    // roche.toml wins over the Cargo.toml fragment.
+    assert_eq!(deps["uuid"].as_str(), Some("0.8.2"));
+    assert!(deps.contains_key("tide"));
+
+    remove_dir_all(path).unwrap();
+}
+
+#[test]
+fn path_dependencies_are_rejected() {
+    let path = setup("path_dependencies_are_rejected", "");
+    let manifest = path.join("roche.toml");
+    fs::write(
+        &manifest,
+        "[dependencies]\nmylib = { path = \"../mylib\" }\n",
+    )
+    .unwrap();
+
+    let err = format!("{:?}", Manifest::load(&manifest).unwrap_err());
+    assert!(err.contains("dependencies.mylib"), "{}", err);
+    assert!(err.contains("path"), "{}", err);
+
+    fs::write(
+        &manifest,
+        "[dependencies]\nreqwest = { version = \"0.11\", features = [\"json\"] }\n",
+    )
+    .unwrap();
+    let output = Command::new(roche())
+        .arg("config")
+        .arg("show")
+        .current_dir(&path)
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stdout).contains(&format!(
+        "dependencies.reqwest   = {{ features = [\"json\"], version = \"0.11\" }}  # {}",
+        manifest.display()
+    )));
+
+    remove_dir_all(path).unwrap();
+}
+
+fn request(kind: BuildKind, path: &Path, dependencies: &str) -> BuildRequest {
+    BuildRequest {
+        kind,
+        build_image: "build/image".to_string(),
+        runtime_image: "runtime/image".to_string(),
+        tag: "registry/request".to_string(),
+        context_dir: path.to_path_buf(),
+        dependencies: toml::from_str(dependencies).unwrap(),
+        ..Default::default()
+    }
+}
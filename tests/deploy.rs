@@ -0,0 +1,264 @@
+mod common;
+
+use common::{assert_golden, roche, setup, write_script, FUNCTION};
+use remove_dir_all::*;
+use roche::config::{DeployTarget, Platform};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+const MANIFEST: &str = r#"
+[tag]
+name = "hello"
+
+[deploy.production]
+platform = "knative"
+namespace = "functions"
+env_file = ".env.production"
+cpu = "500m"
+memory = "256Mi"
+concurrency = 80
+min_scale = 1
+max_scale = 10
+"#;
+
+#[test]
+fn gen_knative_matches_golden_files() {
+    let path = setup("gen_knative_matches_golden_files", FUNCTION);
+    fs::write(path.join("roche.toml"), MANIFEST).unwrap();
+    fs::write(
+        path.join(".env.production"),
+        "RUST_LOG=info\nGREETING=\"hello: world\"\n",
+    )
+    .unwrap();
+
+    let output = Command::new(roche())
+        .arg("gen")
+        .arg("knative")
+        .arg("-t")
+        .arg("quay.io/myorg/hello:1.0.0")
+        .current_dir(&path)
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert_golden("knative-service.yaml", &path.join("service.yaml"));
+
+    // An existing manifest is left alone.
+    let output = Command::new(roche())
+        .arg("gen")
+        .arg("knative")
+        .arg("-t")
+        .arg("quay.io/myorg/other:2.0.0")
+        .current_dir(&path)
+        .output()
+        .unwrap();
+    assert!(String::from_utf8_lossy(&output.stdout).contains("refusing to overwrite"));
+    assert_golden("knative-service.yaml", &path.join("service.yaml"));
+
+    fs::write(
+        path.join("roche.toml"),
+        "[tag]\nname = \"my_func.api\"\n\n[ports]\ncontainer = 9000\n",
+    )
+    .unwrap();
+    fs::remove_file(path.join("service.yaml")).unwrap();
+    let status = Command::new(roche())
+        .arg("gen")
+        .arg("knative")
+        .arg("-t")
+        .arg("registry/my-func")
+        .current_dir(&path)
+        .status()
+        .unwrap();
+    assert!(status.success());
+    assert_golden("knative-minimal.yaml", &path.join("service.yaml"));
+    assert!(!path.join("Dockerfile").exists());
+
+    remove_dir_all(path).unwrap();
+}
+
+#[test]
+fn deploy_knative_applies_the_manifest() {
+    let path = setup("deploy_knative_applies_the_manifest", FUNCTION);
+    fs::write(path.join("roche.toml"), MANIFEST).unwrap();
+    fs::write(
+        path.join(".env.production"),
+        "RUST_LOG=info\nGREETING=\"hello: world\"\n",
+    )
+    .unwrap();
+    let bin = path.join("bin");
+    fake_tool(&bin, "kubectl");
+
+    let output = Command::new(roche())
+        .arg("deploy")
+        .arg("knative")
+        .arg("-t")
+        .arg("quay.io/myorg/hello:1.0.0")
+        .env("PATH", &bin)
+        .current_dir(&path)
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stdout)
+        .contains("Roche: Deployed quay.io/myorg/hello:1.0.0 as hello"));
+    let args = fs::read_to_string(bin.join("kubectl.args")).unwrap();
+    assert_eq!(args.trim(), "apply -f -");
+    assert_golden("knative-service.yaml", &bin.join("kubectl.stdin"));
+
+    // Without kubectl the manifest goes to kn.
+    fs::remove_file(bin.join("kubectl")).unwrap();
+    fake_tool(&bin, "kn");
+    let status = Command::new(roche())
+        .arg("deploy")
+        .arg("knative")
+        .arg("--target")
+        .arg("production")
+        .arg("-t")
+        .arg("quay.io/myorg/hello:1.0.0")
+        .env("PATH", &bin)
+        .current_dir(&path)
+        .stdin(Stdio::null())
+        .status()
+        .unwrap();
+    assert!(status.success());
+    let args = fs::read_to_string(bin.join("kn.args")).unwrap();
+    assert!(
+        args.starts_with("service create hello --force --filename "),
+        "{}",
+        args
+    );
+    assert!(args.trim().ends_with("--namespace functions"), "{}", args);
+
+    remove_dir_all(path).unwrap();
+}
+
+#[test]
+fn gen_k8s_matches_golden_files() {
+    let path = setup("gen_k8s_matches_golden_files", FUNCTION);
+    fs::write(
+        path.join("roche.toml"),
+        r#"
+[tag]
+name = "hello"
+
+[deploy.production]
+platform = "knative"
+
+[deploy.cluster]
+platform = "kubernetes"
+namespace = "functions"
+env_file = ".env.production"
+memory = "256Mi"
+replicas = 3
+host = "hello.example.com"
+health_path = "/health"
+"#,
+    )
+    .unwrap();
+    fs::write(path.join(".env.production"), "RUST_LOG=info\n").unwrap();
+
+    let output = Command::new(roche())
+        .arg("gen")
+        .arg("k8s")
+        .arg("-t")
+        .arg("quay.io/myorg/hello:1.0.0")
+        .current_dir(&path)
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let k8s = path.join("k8s");
+    for file in &[
+        "deployment.yaml",
+        "service.yaml",
+        "ingress.yaml",
+        "kustomization.yaml",
+    ] {
+        assert_golden(&format!("k8s-{}", file), &k8s.join(file));
+    }
+
+    // Without a host there is no Ingress, and without a health path the port is probed.
+    fs::write(path.join("roche.toml"), "[tag]\nname = \"hello\"\n").unwrap();
+    remove_dir_all(&k8s).unwrap();
+    let status = Command::new(roche())
+        .arg("gen")
+        .arg("k8s")
+        .arg("-t")
+        .arg("quay.io/myorg/hello:1.0.0")
+        .current_dir(&path)
+        .status()
+        .unwrap();
+    assert!(status.success());
+    assert!(!k8s.join("ingress.yaml").exists());
+    assert_golden("k8s-minimal-deployment.yaml", &k8s.join("deployment.yaml"));
+    let kustomization = fs::read_to_string(k8s.join("kustomization.yaml")).unwrap();
+    assert!(kustomization.ends_with("resources:\n  - deployment.yaml\n  - service.yaml\n"));
+
+    let output = Command::new(roche())
+        .arg("gen")
+        .arg("k8s")
+        .arg("-t")
+        .arg("quay.io/myorg/hello:1.0.0")
+        .current_dir(&path)
+        .output()
+        .unwrap();
+    assert!(String::from_utf8_lossy(&output.stdout).contains("refusing to overwrite"));
+
+    remove_dir_all(path).unwrap();
+}
+
+#[test]
+fn deploy_target_selection() {
+    let mut deploy = BTreeMap::new();
+    let target = roche::deploy::target(&deploy, Platform::Knative, None).unwrap();
+    assert_eq!(target.platform, Platform::Knative);
+    assert_eq!(target.namespace, None);
+
+    let mut production = DeployTarget::new(Platform::Knative);
+    production.namespace = Some("functions".to_string());
+    deploy.insert("production".to_string(), production);
+    deploy.insert(
+        "cluster".to_string(),
+        DeployTarget::new(Platform::Kubernetes),
+    );
+    let target = roche::deploy::target(&deploy, Platform::Knative, None).unwrap();
+    assert_eq!(target.namespace.as_deref(), Some("functions"));
+
+    let err = roche::deploy::target(&deploy, Platform::Knative, Some("cluster")).unwrap_err();
+    assert_eq!(
+        format!("{}", err),
+        "deploy.cluster is a kubernetes target, not knative"
+    );
+    let err = roche::deploy::target(&deploy, Platform::Knative, Some("staging")).unwrap_err();
+    assert!(format!("{}", err).contains("No [deploy.staging]"));
+
+    deploy.insert("staging".to_string(), DeployTarget::new(Platform::Knative));
+    let err = roche::deploy::target(&deploy, Platform::Knative, None).unwrap_err();
+    assert!(format!("{}", err).contains("(production, staging). Please pick one with --target"));
+
+    assert_eq!(
+        roche::deploy::service_name("My_Func.api").unwrap(),
+        "my-func-api"
+    );
+    assert_eq!(roche::deploy::service_name("_hello-").unwrap(), "hello");
+    for name in &["", "_-.", "2048-game", &"a".repeat(64)] {
+        let err = roche::deploy::service_name(name).unwrap_err();
+        assert!(
+            format!("{}", err).contains("must start with a letter"),
+            "{}",
+            err
+        );
+    }
+}
+
+/// Writes a stand-in for `name` that records its arguments and what it reads on stdin.
+fn fake_tool(bin: &Path, name: &str) {
+    write_script(
+        bin,
+        name,
+        &format!(
+            "#!/bin/sh\necho \"$@\" >> {dir}/{name}.args\nwhile IFS= read -r line; do echo \"$line\" >> {dir}/{name}.stdin; done\n",
+            dir = bin.display(),
+            name = name
+        ),
+    );
+}
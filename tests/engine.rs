@@ -0,0 +1,112 @@
+mod common;
+
+use common::{fake_engine, roche};
+use remove_dir_all::*;
+use std::fs;
+use std::path::PathBuf;
+use std::process::Command;
+
+const FUNCTION: &str = r#"pub fn handler() -> tide::Server<()> {
+    let mut api = tide::new();
+    api.at("/").get(|_| async { Ok("Hello, world!") });
+    api
+}"#;
+
+#[test]
+fn build_with_engine_flag() {
+    let path = setup("build_with_engine_flag");
+    let bin = path.join("bin");
+    fake_engine(&bin, "docker", "");
+    fake_engine(&bin, "podman", "");
+
+    let status = Command::new(roche())
+        .arg("build")
+        .arg("--engine")
+        .arg("podman")
+        .arg("-t")
+        .arg("registry/engine-flag")
+        .env("PATH", &bin)
+        .current_dir(&path)
+        .status()
+        .unwrap();
+    assert!(status.success());
+
+    let args = fs::read_to_string(bin.join("podman.args")).unwrap();
+    assert_eq!(args.trim(), "build -t registry/engine-flag -f - .");
+    assert!(!bin.join("docker.args").exists());
+    let df = fs::read_to_string(bin.join("podman.dockerfile")).unwrap();
+    assert!(df.contains("FROM quay.io/roche/dev-default:1.4.0 as builder"));
+
+    remove_dir_all(path).unwrap();
+}
+
+#[test]
+fn build_detects_engine_on_path() {
+    let path = setup("build_detects_engine_on_path");
+    let bin = path.join("bin");
+    fake_engine(&bin, "buildah", "");
+
+    let status = Command::new(roche())
+        .arg("release")
+        .arg("-t")
+        .arg("registry/detected")
+        .env("PATH", &bin)
+        .current_dir(&path)
+        .status()
+        .unwrap();
+    assert!(status.success());
+
+    let args = fs::read_to_string(bin.join("buildah.args")).unwrap();
+    assert_eq!(args.trim(), "bud -t registry/detected -f - .");
+
+    remove_dir_all(path).unwrap();
+}
+
+#[test]
+fn build_uses_engine_from_rocherc() {
+    let path = setup("build_uses_engine_from_rocherc");
+    let bin = path.join("bin");
+    fake_engine(&bin, "docker", "");
+    fake_engine(&bin, "podman", "");
+    fs::write(path.join(".rocherc"), "engine=podman\n").unwrap();
+
+    let status = Command::new(roche())
+        .arg("test")
+        .arg("-t")
+        .arg("registry/rocherc")
+        .env("PATH", &bin)
+        .current_dir(&path)
+        .status()
+        .unwrap();
+    assert!(status.success());
+
+    assert!(bin.join("podman.args").exists());
+    assert!(!bin.join("docker.args").exists());
+
+    remove_dir_all(path).unwrap();
+}
+
+#[test]
+fn build_with_unknown_engine_fails() {
+    let path = setup("build_with_unknown_engine_fails");
+
+    let status = Command::new(roche())
+        .arg("build")
+        .arg("--engine")
+        .arg("rocket")
+        .arg("-t")
+        .arg("registry/unknown")
+        .current_dir(&path)
+        .status()
+        .unwrap();
+    assert!(!status.success());
+
+    remove_dir_all(path).unwrap();
+}
+
+/// A function with an empty `lib.rs` beside it.
+fn setup(name: &str) -> PathBuf {
+    let path = common::setup(name, FUNCTION);
+    fs::write(path.join("lib.rs"), "").unwrap();
+    path
+}
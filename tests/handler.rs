@@ -0,0 +1,176 @@
+mod common;
+
+use common::{fake_engine, roche, setup};
+use remove_dir_all::*;
+use roche::handler::{self, Route};
+use std::collections::BTreeMap;
+use std::fs;
+use std::process::Command;
+
+const FUNCTION: &str = r#"use tide::Request;
+
+pub fn handler() -> tide::Server<()> {
+    let mut api = tide::new();
+    api.at("/").get(|_| async { Ok("Hello, world!") });
+    api.at("/users")
+        .get(list)
+        .post(|_| async { Ok("created") });
+    api.at("/users").at(":id").delete(|_| async { Ok("deleted") });
+    api
+}
+
+async fn list(_req: Request<()>) -> tide::Result {
+    Ok("[]".into())
+}
+"#;
+
+#[test]
+fn routes_are_listed() {
+    let path = setup("routes_are_listed", FUNCTION);
+    let file = path.join("functions.rs");
+
+    let routes = handler::check(&path, &BTreeMap::new()).unwrap();
+    let listed: Vec<String> = routes.iter().map(ToString::to_string).collect();
+    assert_eq!(
+        listed,
+        vec!["GET /", "GET /users", "POST /users", "DELETE /users/:id"]
+    );
+    assert_eq!(
+        routes[2],
+        Route {
+            method: "POST".to_string(),
+            path: "/users".to_string(),
+            file,
+            line: 8,
+        }
+    );
+
+    remove_dir_all(path).unwrap();
+}
+
+#[test]
+fn signature_mistakes_point_at_the_line() {
+    let path = setup("signature_mistakes_point_at_the_line", "");
+    let file = path.join("functions.rs");
+    let cases = &[
+        ("fn main() {}\n", "no handler() found"),
+        (
+            "pub fn handler() -> tide::Server<State> {\n    tide::with_state(State)\n}\n",
+            ":1:21: handler() must return tide::Server<()>, found `tide::Server<State>`",
+        ),
+        (
+            "\npub async fn handler() -> tide::Server<()> {\n    tide::new()\n}\n",
+            ":2:5: handler() must not be async",
+        ),
+        (
+            "pub fn handler(state: State) -> tide::Server<()> {\n    tide::new()\n}\n",
+            ":1:16: handler() must take no arguments",
+        ),
+        (
+            "fn handler() -> tide::Server<()> {\n    tide::new()\n}\n",
+            ":1:1: handler() must be pub",
+        ),
+        (
+            "pub fn handler() -> tide::Server<()> {\n    tide::new(\n}\n",
+            "couldn't parse",
+        ),
+    ];
+    for (source, expected) in cases {
+        fs::write(&file, source).unwrap();
+        let err = format!("{}", handler::check_file(&file, "/").unwrap_err());
+        assert!(err.starts_with(&file.display().to_string()), "{}", err);
+        assert!(err.contains(expected), "{}", err);
+    }
+
+    fs::write(
+        &file,
+        "use tide::Server;\npub fn handler() -> Server<()> {\n    tide::new()\n}\n",
+    )
+    .unwrap();
+    assert_eq!(handler::check_file(&file, "/").unwrap(), vec![]);
+
+    remove_dir_all(path).unwrap();
+}
+
+#[test]
+fn module_routes_are_prefixed_and_must_not_collide() {
+    let path = setup("module_routes_are_prefixed_and_must_not_collide", "");
+    fs::remove_file(path.join("functions.rs")).unwrap();
+    let functions = path.join("functions");
+    fs::create_dir_all(&functions).unwrap();
+    fs::write(functions.join("users.rs"), FUNCTION).unwrap();
+    fs::write(
+        functions.join("orders.rs"),
+        "pub fn handler() -> tide::Server<()> {\n    let mut api = tide::new();\n    api.at(\"/\").get(|_| async { Ok(\"[]\") });\n    api\n}\n",
+    )
+    .unwrap();
+
+    let routes = handler::check(&path, &BTreeMap::new()).unwrap();
+    let listed: Vec<String> = routes.iter().map(ToString::to_string).collect();
+    assert_eq!(
+        listed,
+        vec![
+            "GET /orders",
+            "GET /users",
+            "GET /users/users",
+            "POST /users/users",
+            "DELETE /users/users/:id"
+        ]
+    );
+
+    let mut prefixes = BTreeMap::new();
+    prefixes.insert("orders".to_string(), "/shop/users".to_string());
+    prefixes.insert("users".to_string(), "/shop".to_string());
+    let err = format!("{}", handler::check(&path, &prefixes).unwrap_err());
+    assert!(
+        err.starts_with("route collision: GET /shop/users is registered at"),
+        "{}",
+        err
+    );
+    assert!(err.contains(&format!("{}:3", functions.join("orders.rs").display())));
+
+    remove_dir_all(path).unwrap();
+}
+
+#[test]
+fn build_and_gen_fail_fast_without_a_handler() {
+    let path = setup(
+        "build_and_gen_fail_fast_without_a_handler",
+        "pub fn handle() -> tide::Server<()> {\n    tide::new()\n}\n",
+    );
+    let bin = path.join("bin");
+    fake_engine(&bin, "docker", "");
+
+    for args in &[
+        &["build", "-t", "registry/handler"][..],
+        &["release"],
+        &["gen"],
+    ] {
+        let output = Command::new(roche())
+            .args(*args)
+            .env("PATH", &bin)
+            .current_dir(&path)
+            .output()
+            .unwrap();
+        assert_eq!(output.status.code(), Some(1), "{:?}", args);
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(stderr.contains("no handler() found"), "{}", stderr);
+    }
+    assert!(!bin.join("docker.args").exists());
+    assert!(!path.join("Dockerfile").exists());
+
+    fs::write(path.join("functions.rs"), FUNCTION).unwrap();
+    let output = Command::new(roche())
+        .arg("build")
+        .arg("-t")
+        .arg("registry/handler")
+        .env("PATH", &bin)
+        .current_dir(&path)
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stdout)
+        .contains("Roche: Routes GET /, GET /users, POST /users, DELETE /users/:id"));
+
+    remove_dir_all(path).unwrap();
+}
@@ -0,0 +1,166 @@
+mod common;
+
+use common::{fake_engine, fresh_dir, roche};
+use remove_dir_all::*;
+use roche::modules::{self, Module};
+use roche::native::NativeRequest;
+use roche::{BuildKind, BuildRequest};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+const HANDLER: &str = "pub fn handler() -> tide::Server<()> {\n    tide::new()\n}\n";
+
+#[test]
+fn functions_folder_mounts_each_module() {
+    let path = setup("functions_folder_mounts_each_module");
+    assert_eq!(roche::function_dir(&path), Some(path.join("src")));
+    let src = path.join("src");
+
+    let found = modules::modules(&src, &BTreeMap::new()).unwrap().unwrap();
+    assert_eq!(
+        found,
+        vec![
+            Module {
+                name: "orders".to_string(),
+                prefix: "/orders".to_string()
+            },
+            Module {
+                name: "users".to_string(),
+                prefix: "/users".to_string()
+            },
+        ]
+    );
+    let functions = modules::render_functions(&found, None);
+    assert!(functions.contains("pub mod orders;\npub mod users;\n"));
+    assert!(functions.contains("    api.at(\"/orders\").nest(orders::handler());\n"));
+    assert!(functions.contains("    api.at(\"/users\").nest(users::handler());\n"));
+
+    let dev = request(BuildKind::Dev, &src);
+    let df = dev.render_dockerfile().unwrap();
+    assert!(df.contains("COPY . /app-build/src/\nRUN printf '%s\\n' '// Generated by roche"));
+    let start = df.find("printf").unwrap();
+    let end = df.find(" > /app-build/src/functions.rs").unwrap();
+    let output = Command::new("/bin/sh")
+        .arg("-c")
+        .arg(&df[start..end])
+        .output()
+        .unwrap();
+    assert_eq!(String::from_utf8_lossy(&output.stdout), functions);
+
+    let df = request(BuildKind::Release, &src)
+        .render_dockerfile()
+        .unwrap();
+    assert!(df.contains("COPY functions /app-build/src/functions\n"));
+    assert!(!df.contains("COPY functions.rs"));
+
+    let native = NativeRequest {
+        context_dir: src.clone(),
+        cache_dir: path.join("cache"),
+        ..Default::default()
+    };
+    let dir = native.write_crate().unwrap();
+    let generated = fs::read_to_string(dir.join("src").join("functions.rs")).unwrap();
+    assert!(generated.contains(&format!(
+        "#[path = {:?}]\npub mod users;\n",
+        src.join("functions").join("users.rs").display().to_string()
+    )));
+    let main = fs::read_to_string(dir.join("src").join("main.rs")).unwrap();
+    assert!(main.starts_with("mod functions;\n"));
+
+    remove_dir_all(path).unwrap();
+}
+
+#[test]
+fn manifest_prefixes_choose_the_modules() {
+    let path = setup("manifest_prefixes_choose_the_modules");
+    let src = path.join("src");
+    let mut prefixes = BTreeMap::new();
+    prefixes.insert("users".to_string(), "/api/users".to_string());
+
+    let found = modules::modules(&src, &prefixes).unwrap().unwrap();
+    assert_eq!(
+        found,
+        vec![Module {
+            name: "users".to_string(),
+            prefix: "/api/users".to_string()
+        }]
+    );
+
+    prefixes.insert("billing".to_string(), "/billing".to_string());
+    let err = modules::modules(&src, &prefixes).unwrap_err();
+    assert!(format!("{}", err).contains("billing.rs does not exist"));
+
+    fs::write(src.join("functions").join("Admin.rs"), HANDLER).unwrap();
+    let err = modules::modules(&src, &BTreeMap::new()).unwrap_err();
+    assert!(format!("{}", err).contains("'Admin'"));
+
+    remove_dir_all(path).unwrap();
+}
+
+#[test]
+fn build_fails_fast_on_route_collisions() {
+    let path = setup("build_fails_fast_on_route_collisions");
+    fs::write(
+        path.join("roche.toml"),
+        "[functions]\norders = \"/api\"\nusers = \"/api/\"\n",
+    )
+    .unwrap();
+    let bin = path.join("bin");
+    fake_engine(&bin, "docker", "");
+
+    let output = Command::new(roche())
+        .arg("build")
+        .arg("--engine")
+        .arg("docker")
+        .arg("-t")
+        .arg("registry/collision")
+        .env("PATH", &bin)
+        .current_dir(&path)
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr
+            .contains("route collision: functions 'orders' and 'users' are both mounted at /api/"),
+        "{}",
+        stderr
+    );
+    assert!(!bin.join("docker.args").exists());
+
+    fs::write(path.join("roche.toml"), "[functions]\nusers = \"users\"\n").unwrap();
+    let output = Command::new(roche())
+        .arg("config")
+        .arg("show")
+        .current_dir(&path)
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("functions.users"));
+
+    remove_dir_all(path).unwrap();
+}
+
+fn request(kind: BuildKind, path: &Path) -> BuildRequest {
+    BuildRequest {
+        kind,
+        build_image: "build/image".to_string(),
+        runtime_image: "runtime/image".to_string(),
+        tag: "registry/request".to_string(),
+        context_dir: path.to_path_buf(),
+        ..Default::default()
+    }
+}
+
+/// Creates a project with `users` and `orders` handlers in `src/functions/`.
+fn setup(name: &str) -> PathBuf {
+    let path = fresh_dir(name);
+    let functions = path.join("src").join("functions");
+    fs::create_dir_all(&functions).unwrap();
+    fs::write(functions.join("users.rs"), HANDLER).unwrap();
+    fs::write(functions.join("orders.rs"), HANDLER).unwrap();
+    fs::write(functions.join("README.md"), "not a module").unwrap();
+    path
+}
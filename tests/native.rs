@@ -0,0 +1,163 @@
+mod common;
+
+use common::{roche, setup, write_script};
+use remove_dir_all::*;
+use roche::native::NativeRequest;
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+use std::process::Command;
+
+const FUNCTION: &str = r#"pub fn handler() -> tide::Server<()> {
+    let mut api = tide::new();
+    api.at("/").get(|_| async { Ok("Hello, world!") });
+    api
+}"#;
+
+#[test]
+fn native_crate_wraps_the_function() {
+    let path = setup("native_crate_wraps_the_function", FUNCTION);
+    let request = NativeRequest {
+        context_dir: path.clone(),
+        cache_dir: path.join("cache"),
+        release: false,
+        ..Default::default()
+    };
+
+    let dir = request.write_crate().unwrap();
+    let digest = format!("{:x}", Sha256::digest(path.to_string_lossy().as_bytes()));
+    assert_eq!(
+        dir,
+        path.join("cache").join("native").join(format!(
+            "{}-{}",
+            path.file_name().unwrap().to_string_lossy(),
+            &digest[..16]
+        ))
+    );
+    let main = fs::read_to_string(dir.join("src").join("main.rs")).unwrap();
+    assert!(main.starts_with(&format!(
+        "#[path = \"{}\"]\nmod functions;\n",
+        path.join("functions.rs").display()
+    )));
+    assert!(main.contains("app.at(\"/\").nest(functions::handler());"));
+    assert!(main.contains("std::env::var(\"PORT\")"));
+    let manifest = fs::read_to_string(dir.join("Cargo.toml")).unwrap();
+    assert!(manifest.contains("name = \"roche-service\""));
+    assert!(manifest.contains("tide = "));
+    assert!(manifest.contains("[workspace]"));
+
+    assert_eq!(
+        request.binary(),
+        dir.join("target").join("debug").join("roche-service")
+    );
+    let other = NativeRequest {
+        context_dir: path.join("other"),
+        ..request
+    };
+    assert_ne!(other.crate_dir(), dir);
+
+    remove_dir_all(path).unwrap();
+}
+
+#[test]
+fn build_native_uses_host_cargo() {
+    let path = setup("build_native_uses_host_cargo", FUNCTION);
+    let bin = path.join("bin");
+    fake_cargo(&bin);
+
+    let status = Command::new(roche())
+        .arg("build")
+        .arg("--native")
+        .env("PATH", &bin)
+        .env("cache_dir", path.join("cache"))
+        .current_dir(&path)
+        .status()
+        .unwrap();
+    assert!(status.success());
+
+    let crate_dir = NativeRequest {
+        context_dir: path.clone(),
+        cache_dir: path.join("cache"),
+        release: false,
+        ..Default::default()
+    }
+    .crate_dir();
+    let args = fs::read_to_string(bin.join("cargo.args")).unwrap();
+    assert_eq!(
+        args.trim(),
+        format!(
+            "build --manifest-path {} --target-dir {}",
+            crate_dir.join("Cargo.toml").display(),
+            crate_dir.join("target").display()
+        )
+    );
+
+    remove_dir_all(path).unwrap();
+}
+
+#[test]
+fn run_native_passes_port_and_env() {
+    let path = setup("run_native_passes_port_and_env", FUNCTION);
+    fs::write(path.join(".env"), "MY_VAR=hello\n").unwrap();
+    let bin = path.join("bin");
+    fake_cargo(&bin);
+
+    let output = Command::new(roche())
+        .arg("run")
+        .arg("--native")
+        .arg("-p")
+        .arg("9191")
+        .env("PATH", &bin)
+        .env("cache_dir", path.join("cache"))
+        .current_dir(&path)
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stdout).contains("http://localhost:9191/"));
+
+    let service = fs::read_to_string(bin.join("service.out")).unwrap();
+    assert_eq!(service.trim(), "9191 hello");
+
+    remove_dir_all(path).unwrap();
+}
+
+#[test]
+fn failed_native_build_propagates_exit_code() {
+    let path = setup("failed_native_build_propagates_exit_code", FUNCTION);
+    let bin = path.join("bin");
+    fs::create_dir_all(&bin).unwrap();
+    let cargo = bin.join("cargo");
+    fs::write(&cargo, "#!/bin/sh\necho 'error[E0425]' >&2\nexit 101\n").unwrap();
+    fs::set_permissions(&cargo, fs::Permissions::from_mode(0o755)).unwrap();
+
+    let output = Command::new(roche())
+        .arg("run")
+        .arg("--native")
+        .env("PATH", &bin)
+        .env("cache_dir", path.join("cache"))
+        .current_dir(&path)
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(101));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("cargo build failed"));
+
+    remove_dir_all(path).unwrap();
+}
+
+/// Writes a cargo stand-in that records its arguments and "builds" a service recording its
+/// `PORT` and `MY_VAR`.
+fn fake_cargo(bin: &Path) {
+    write_script(
+        bin,
+        "cargo",
+        &format!(
+            "#!/bin/sh\n\
+             echo \"$@\" >> {dir}/cargo.args\n\
+             /bin/mkdir -p $5/debug\n\
+             printf '#!/bin/sh\\necho \"$PORT $MY_VAR\" > {dir}/service.out\\n' > $5/debug/roche-service\n\
+             /bin/chmod +x $5/debug/roche-service\n",
+            dir = bin.display()
+        ),
+    );
+}
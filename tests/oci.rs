@@ -0,0 +1,347 @@
+mod common;
+
+use common::{roche, setup, write_script, FUNCTION};
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use remove_dir_all::*;
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{Read, Write};
+use std::path::Path;
+use std::process::Command;
+
+const TARGET: &str = "x86_64-unknown-linux-musl";
+
+#[test]
+fn release_oci_assembles_the_runtime_image() {
+    let path = setup("release_oci_assembles_the_runtime_image", FUNCTION);
+    let base = path.join("base");
+    write_base(&base);
+    let bin = path.join("bin");
+    fake_cargo(&bin);
+
+    let release = || {
+        Command::new(roche())
+            .arg("release")
+            .arg("--oci")
+            .arg("image.tar")
+            .arg("--rust-target")
+            .arg(TARGET)
+            .arg("-t")
+            .arg("quay.io/myorg/hello:1.0.0")
+            .arg("-r")
+            .arg(format!("oci:{}", base.display()))
+            .env("PATH", &bin)
+            .env("cache_dir", path.join("cache"))
+            .current_dir(&path)
+            .output()
+            .unwrap()
+    };
+    let output = release();
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let args = fs::read_to_string(bin.join("cargo.args")).unwrap();
+    assert!(args
+        .trim()
+        .ends_with(&format!("--release --target {}", TARGET)));
+
+    let image = read_tar(&fs::read(path.join("image.tar")).unwrap());
+    assert_eq!(image["oci-layout"], br#"{"imageLayoutVersion":"1.0.0"}"#);
+    let index = json(&image["index.json"]);
+    let descriptor = &index["manifests"][0];
+    assert_eq!(
+        descriptor["annotations"]["io.containerd.image.name"],
+        "quay.io/myorg/hello:1.0.0"
+    );
+    assert_eq!(
+        descriptor["annotations"]["org.opencontainers.image.ref.name"],
+        "1.0.0"
+    );
+    let manifest = json(&blob(&image, &descriptor["digest"]));
+    let layers = manifest["layers"].as_array().unwrap();
+    assert_eq!(layers.len(), 2);
+    for layer in layers {
+        assert_eq!(
+            layer["mediaType"],
+            "application/vnd.oci.image.layer.v1.tar+gzip"
+        );
+    }
+
+    let config = json(&blob(&image, &manifest["config"]["digest"]));
+    assert_eq!(config["architecture"], "amd64");
+    assert_eq!(
+        config["config"],
+        serde_json::json!({
+            "Env": ["PATH=/usr/local/bin:/usr/bin:/bin", "PORT=8080"],
+            "User": "rocheuser",
+            "WorkingDir": "/app",
+            "Entrypoint": null,
+            "Cmd": ["./run.sh"],
+            "ExposedPorts": { "8080/tcp": {} }
+        })
+    );
+    let service = gunzip(&blob(&image, &layers[1]["digest"]));
+    assert_eq!(
+        config["rootfs"]["diff_ids"][1],
+        format!("sha256:{:x}", Sha256::digest(&service))
+    );
+
+    let mut archive = tar::Archive::new(service.as_slice());
+    let mut files = BTreeMap::new();
+    for entry in archive.entries().unwrap() {
+        let mut entry = entry.unwrap();
+        let header = entry.header().clone();
+        let mut contents = String::new();
+        entry.read_to_string(&mut contents).unwrap();
+        files.insert(
+            entry.path().unwrap().display().to_string(),
+            (header.uid().unwrap(), header.mode().unwrap(), contents),
+        );
+    }
+    assert_eq!(
+        files.keys().collect::<Vec<_>>(),
+        vec![
+            "app/",
+            "app/roche-service",
+            "app/run.sh",
+            "etc/group",
+            "etc/passwd",
+            "home/rocheuser/"
+        ]
+    );
+    assert_eq!(
+        files["app/roche-service"],
+        (10001, 0o755, "roche-service\n".to_string())
+    );
+    assert!(files["app/run.sh"].2.ends_with("exec ./roche-service\n"));
+    assert_eq!(
+        files["etc/passwd"].2,
+        "root:x:0:0:root:/root:/bin/ash\nrocheuser:x:10001:10001::/home/rocheuser:/sbin/nologin\n"
+    );
+    assert_eq!(files["etc/group"].2, "root:x:0:root\nrocheuser:x:10001:\n");
+
+    let docker = json(&image["manifest.json"]);
+    assert_eq!(docker[0]["RepoTags"][0], "quay.io/myorg/hello:1.0.0");
+    assert_eq!(docker[0]["Layers"].as_array().unwrap().len(), 2);
+
+    // The same inputs give the same image.
+    let digest = |stdout: &[u8]| {
+        let stdout = String::from_utf8_lossy(stdout).to_string();
+        stdout
+            .lines()
+            .find(|line| line.starts_with("Roche: Wrote image.tar"))
+            .unwrap()
+            .to_string()
+    };
+    assert_eq!(digest(&release().stdout), digest(&output.stdout));
+    assert!(digest(&output.stdout).ends_with(&descriptor["digest"].as_str().unwrap()));
+
+    remove_dir_all(path).unwrap();
+}
+
+#[test]
+fn release_oci_cannot_push() {
+    let path = setup("release_oci_cannot_push", FUNCTION);
+    let output = Command::new(roche())
+        .arg("release")
+        .arg("--oci")
+        .arg("image.tar")
+        .arg("--push")
+        .arg("-t")
+        .arg("quay.io/myorg/hello:1.0.0")
+        .env("PATH", path.join("bin"))
+        .current_dir(&path)
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&output.stderr).contains(
+        "Please push the tarball with 'skopeo copy oci-archive:image.tar docker://<tag>'"
+    ));
+    assert!(!path.join("image.tar").exists());
+
+    remove_dir_all(path).unwrap();
+}
+
+#[test]
+fn release_oci_builds_for_the_target_platform() {
+    let path = setup("release_oci_builds_for_the_target_platform", FUNCTION);
+    let base = path.join("base");
+    write_base(&base);
+    let bin = path.join("bin");
+    fake_cargo(&bin);
+    let release = |target: Option<&str>| {
+        let mut command = Command::new(roche());
+        command
+            .arg("release")
+            .arg("--oci")
+            .arg("image.tar")
+            .arg("-t")
+            .arg("quay.io/myorg/hello:1.0.0")
+            .arg("-r")
+            .arg(format!("oci:{}", base.display()))
+            .env("PATH", &bin)
+            .env("cache_dir", path.join("cache"))
+            .current_dir(&path);
+        if let Some(target) = target {
+            command.arg("--rust-target").arg(target);
+        }
+        command.output().unwrap()
+    };
+    let cargo_runs = || {
+        fs::read_to_string(bin.join("cargo.args"))
+            .unwrap_or_default()
+            .lines()
+            .map(str::to_string)
+            .collect::<Vec<_>>()
+    };
+
+    // Without --rust-target the service is a static musl build for the host.
+    let output = release(None);
+    assert!(cargo_runs()[0].ends_with(&format!(
+        "--release --target {}",
+        roche::oci::default_target()
+    )));
+    if cfg!(target_arch = "x86_64") {
+        assert!(
+            output.status.success(),
+            "{}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+
+    // The target picks the image of the runtime image's index.
+    let output = release(Some("aarch64-unknown-linux-musl"));
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("has no image for linux/arm64"));
+    assert_eq!(
+        roche::oci::image_platform("armv7-unknown-linux-musleabihf").unwrap(),
+        "linux/arm"
+    );
+
+    let output = release(Some("x86_64-apple-darwin"));
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("runtime images are linux"));
+    assert_eq!(cargo_runs().len(), 2);
+
+    remove_dir_all(path).unwrap();
+}
+
+/// Writes a one layer alpine-like image in the OCI layout `dir`, described with docker media
+/// types as registries often do.
+fn write_base(dir: &Path) {
+    let mut layer = tar::Builder::new(Vec::new());
+    for (file, contents) in &[
+        ("etc/passwd", "root:x:0:0:root:/root:/bin/ash\n"),
+        ("etc/group", "root:x:0:root"),
+        ("bin/sh", "#!/bin/busybox\n"),
+    ] {
+        let mut header = tar::Header::new_ustar();
+        header.set_size(contents.len() as u64);
+        header.set_mode(0o644);
+        layer
+            .append_data(&mut header, file, contents.as_bytes())
+            .unwrap();
+    }
+    let layer = layer.into_inner().unwrap();
+    let mut gzip = GzEncoder::new(Vec::new(), Compression::default());
+    gzip.write_all(&layer).unwrap();
+    let layer_blob = gzip.finish().unwrap();
+
+    let config = serde_json::to_vec(&serde_json::json!({
+        "architecture": "amd64",
+        "os": "linux",
+        "config": { "Env": ["PATH=/usr/local/bin:/usr/bin:/bin", "PORT=1"] },
+        "rootfs": {
+            "type": "layers",
+            "diff_ids": [format!("sha256:{:x}", Sha256::digest(&layer))]
+        }
+    }))
+    .unwrap();
+    let manifest = serde_json::to_vec(&serde_json::json!({
+        "schemaVersion": 2,
+        "mediaType": "application/vnd.docker.distribution.manifest.v2+json",
+        "config": {
+            "mediaType": "application/vnd.docker.container.image.v1+json",
+            "digest": write_blob(dir, &config),
+            "size": config.len()
+        },
+        "layers": [{
+            "mediaType": "application/vnd.docker.image.rootfs.diff.tar.gzip",
+            "digest": write_blob(dir, &layer_blob),
+            "size": layer_blob.len()
+        }]
+    }))
+    .unwrap();
+    let index = serde_json::json!({
+        "schemaVersion": 2,
+        "manifests": [{
+            "mediaType": "application/vnd.docker.distribution.manifest.v2+json",
+            "digest": write_blob(dir, &manifest),
+            "size": manifest.len(),
+            "platform": { "os": "linux", "architecture": "amd64" }
+        }]
+    });
+    fs::write(dir.join("index.json"), index.to_string()).unwrap();
+}
+
+fn write_blob(dir: &Path, blob: &[u8]) -> String {
+    let hex = format!("{:x}", Sha256::digest(blob));
+    let blobs = dir.join("blobs").join("sha256");
+    fs::create_dir_all(&blobs).unwrap();
+    fs::write(blobs.join(&hex), blob).unwrap();
+    format!("sha256:{}", hex)
+}
+
+fn read_tar(data: &[u8]) -> BTreeMap<String, Vec<u8>> {
+    let mut archive = tar::Archive::new(data);
+    let mut files = BTreeMap::new();
+    for entry in archive.entries().unwrap() {
+        let mut entry = entry.unwrap();
+        let mut contents = Vec::new();
+        entry.read_to_end(&mut contents).unwrap();
+        files.insert(entry.path().unwrap().display().to_string(), contents);
+    }
+    files
+}
+
+fn blob(image: &BTreeMap<String, Vec<u8>>, digest: &serde_json::Value) -> Vec<u8> {
+    let path = format!("blobs/{}", digest.as_str().unwrap().replace(':', "/"));
+    let blob = image[&path].clone();
+    assert_eq!(
+        digest.as_str().unwrap(),
+        format!("sha256:{:x}", Sha256::digest(&blob))
+    );
+    blob
+}
+
+fn json(data: &[u8]) -> serde_json::Value {
+    serde_json::from_slice(data).unwrap()
+}
+
+fn gunzip(data: &[u8]) -> Vec<u8> {
+    let mut contents = Vec::new();
+    GzDecoder::new(data).read_to_end(&mut contents).unwrap();
+    contents
+}
+
+/// Writes a cargo stand-in that records its arguments and "builds" the service for the target
+/// it is passed last.
+fn fake_cargo(bin: &Path) {
+    write_script(
+        bin,
+        "cargo",
+        &format!(
+            "#!/bin/sh\n\
+             echo \"$@\" >> {dir}/cargo.args\n\
+             eval target=\\${{$#}}\n\
+             /bin/mkdir -p $5/$target/release\n\
+             echo roche-service > $5/$target/release/roche-service\n",
+            dir = bin.display()
+        ),
+    );
+}
@@ -0,0 +1,191 @@
+mod common;
+
+use common::{roche, setup};
+use remove_dir_all::*;
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+const FUNCTION: &str = r#"use serde::{Deserialize, Serialize};
+use tide::{Body, Request, Response};
+
+#[derive(Deserialize, Serialize)]
+struct Cat {
+    name: String,
+    age: u8,
+    #[serde(rename = "ownerInfo")]
+    owner: Option<Owner>,
+    tags: Vec<String>,
+}
+
+#[derive(Deserialize, Serialize)]
+struct Owner {
+    name: String,
+}
+
+#[derive(Serialize)]
+struct Unused {
+    id: u64,
+}
+
+pub fn handler() -> tide::Server<()> {
+    let mut api = tide::new();
+    api.at("/cats").get(list).post(|mut req: Request<()>| async move {
+        let cat: Cat = req.body_json().await?;
+        Ok(Response::builder(201).body(Body::from_json(&cat)?).build())
+    });
+    api.at("/cats/:id").delete(|_| async { Ok("") });
+    api.at("/health").all(|_| async { Ok("") });
+    api.at("/static/*").get(|_| async { Ok("") });
+    api
+}
+
+async fn list(_req: Request<()>) -> tide::Result<Body> {
+    let cats: Vec<Cat> = Vec::new();
+    Body::from_json(&cats)
+}
+"#;
+
+#[test]
+fn openapi_describes_routes_and_serde_bodies() {
+    let path = setup("openapi_describes_routes_and_serde_bodies", FUNCTION);
+    fs::write(
+        path.join("roche.toml"),
+        "[tag]\nname = \"cats\"\nversion = \"1.2.0\"\n",
+    )
+    .unwrap();
+
+    let output = Command::new(roche())
+        .arg("openapi")
+        .current_dir(&path)
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let document: serde_json::Value = serde_yaml::from_slice(&output.stdout).unwrap();
+
+    assert_eq!(document["openapi"], "3.0.3");
+    assert_eq!(
+        document["info"],
+        serde_json::json!({ "title": "cats", "version": "1.2.0" })
+    );
+    let paths = document["paths"].as_object().unwrap();
+    assert_eq!(
+        paths.keys().collect::<Vec<_>>(),
+        vec!["/cats", "/cats/{id}", "/static/{wildcard}"],
+        "routes registered with .all() have no method to document"
+    );
+
+    let cat = serde_json::json!({ "$ref": "#/components/schemas/Cat" });
+    let list = &paths["/cats"]["get"];
+    assert_eq!(list["operationId"], "get_cats");
+    assert_eq!(
+        list["responses"]["200"]["content"]["application/json"]["schema"],
+        serde_json::json!({ "type": "array", "items": cat })
+    );
+    let create = &paths["/cats"]["post"];
+    assert_eq!(
+        create["requestBody"]["content"]["application/json"]["schema"],
+        cat
+    );
+    assert_eq!(create["responses"]["201"]["description"], "Created");
+    assert_eq!(
+        create["responses"]["201"]["content"]["application/json"]["schema"],
+        cat
+    );
+    assert_eq!(
+        paths["/cats/{id}"]["delete"],
+        serde_json::json!({
+            "operationId": "delete_cats_id",
+            "parameters": [
+                { "name": "id", "in": "path", "required": true, "schema": { "type": "string" } }
+            ],
+            "responses": { "200": { "description": "OK" } }
+        })
+    );
+    let wildcard = &paths["/static/{wildcard}"]["get"];
+    assert_eq!(wildcard["operationId"], "get_static_wildcard");
+    assert_eq!(wildcard["parameters"][0]["name"], "wildcard");
+
+    assert_eq!(
+        document["components"]["schemas"],
+        serde_json::json!({
+            "Cat": {
+                "type": "object",
+                "properties": {
+                    "name": { "type": "string" },
+                    "age": { "type": "integer", "format": "int32" },
+                    "ownerInfo": { "$ref": "#/components/schemas/Owner" },
+                    "tags": { "type": "array", "items": { "type": "string" } }
+                },
+                "required": ["name", "age", "tags"]
+            },
+            "Owner": {
+                "type": "object",
+                "properties": { "name": { "type": "string" } },
+                "required": ["name"]
+            }
+        })
+    );
+
+    remove_dir_all(path).unwrap();
+}
+
+#[test]
+fn openapi_of_the_json_example() {
+    let output = Command::new(roche())
+        .arg("openapi")
+        .current_dir(
+            Path::new(env!("CARGO_MANIFEST_DIR"))
+                .join("examples")
+                .join("json"),
+        )
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "---\n\
+         openapi: 3.0.3\n\
+         info:\n  \
+           title: json\n  \
+           version: 0.1.0\n\
+         paths:\n  \
+           /animals:\n    \
+             get:\n      \
+               operationId: get_animals\n      \
+               responses:\n        \
+                 \"200\":\n          \
+                   description: OK\n"
+    );
+}
+
+#[test]
+fn openapi_refuses_duplicate_operations() {
+    let path = setup(
+        "openapi_refuses_duplicate_operations",
+        "pub fn handler() -> tide::Server<()> {\n    \
+             let mut api = tide::new();\n    \
+             api.at(\"/cats\").get(|_| async { Ok(\"\") });\n    \
+             api.at(\"/cats\").get(|_| async { Ok(\"\") });\n    \
+             api\n}\n",
+    );
+
+    let output = Command::new(roche())
+        .arg("openapi")
+        .current_dir(&path)
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains(&format!(
+            "route collision: GET /cats is registered at {0}:3 and at {0}:4",
+            path.join("functions.rs").display()
+        )),
+        "{}",
+        stderr
+    );
+
+    remove_dir_all(path).unwrap();
+}
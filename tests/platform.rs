@@ -0,0 +1,194 @@
+mod common;
+
+use common::{fake_engine, roche, setup, FUNCTION};
+use remove_dir_all::*;
+use std::fs;
+use std::process::{Command, Stdio};
+
+// Writes the digest of a push.
+const ENGINE: &str = "case \"$1 $2\" in\n\
+    \"manifest push\") while [ \"$1\" != \"--digestfile\" ]; do shift; done; printf 'sha256:9c8b' > \"$2\" ;;\n\
+    esac\n";
+
+#[test]
+fn release_platforms_build_a_manifest_list() {
+    let path = setup("release_platforms_build_a_manifest_list", FUNCTION);
+    let bin = path.join("bin");
+    fake_engine(&bin, "podman", ENGINE);
+    fs::write(
+        path.join("roche.toml"),
+        "[build]\nplatforms = [\"linux/amd64\", \"linux/arm64\"]\n",
+    )
+    .unwrap();
+
+    let output = Command::new(roche())
+        .arg("release")
+        .arg("--push")
+        .arg("-e")
+        .arg("podman")
+        .arg("-t")
+        .arg("quay.io/myorg/hello:1.0.0")
+        .env("PATH", &bin)
+        .env("DOCKER_CONFIG", &path)
+        .env("HOME", &path)
+        .env_remove("REGISTRY_AUTH_FILE")
+        .env_remove("XDG_RUNTIME_DIR")
+        .current_dir(&path)
+        .stdin(Stdio::null())
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert!(String::from_utf8_lossy(&output.stdout)
+        .contains("Roche: Pushed quay.io/myorg/hello:1.0.0@sha256:9c8b"));
+    let args = fs::read_to_string(bin.join("podman.args")).unwrap();
+    let args: Vec<&str> = args.lines().collect();
+    assert_eq!(args.len(), 3, "{:?}", args);
+    assert_eq!(args[0], "manifest rm quay.io/myorg/hello:1.0.0");
+    assert_eq!(
+        args[1],
+        "build --platform linux/amd64,linux/arm64 --manifest quay.io/myorg/hello:1.0.0 -f - ."
+    );
+    assert!(
+        args[2].starts_with("manifest push --all --digestfile "),
+        "{}",
+        args[2]
+    );
+    assert!(args[2].ends_with(" quay.io/myorg/hello:1.0.0 docker://quay.io/myorg/hello:1.0.0"));
+
+    // The command line wins over roche.toml, and one platform is a plain build.
+    fs::remove_file(bin.join("podman.args")).unwrap();
+    let status = Command::new(roche())
+        .arg("release")
+        .arg("-e")
+        .arg("podman")
+        .arg("--platform")
+        .arg("linux/arm64")
+        .arg("-t")
+        .arg("quay.io/myorg/hello:1.0.0")
+        .env("PATH", &bin)
+        .current_dir(&path)
+        .stdin(Stdio::null())
+        .status()
+        .unwrap();
+    assert!(status.success());
+    let args = fs::read_to_string(bin.join("podman.args")).unwrap();
+    assert_eq!(
+        args.trim(),
+        "build -t quay.io/myorg/hello:1.0.0 -f - --platform linux/arm64 ."
+    );
+
+    // A list built earlier is pushed with `push --manifest`.
+    fs::remove_file(bin.join("podman.args")).unwrap();
+    let status = Command::new(roche())
+        .arg("push")
+        .arg("--manifest")
+        .arg("-e")
+        .arg("podman")
+        .arg("-t")
+        .arg("quay.io/myorg/hello:1.0.0")
+        .env("PATH", &bin)
+        .current_dir(&path)
+        .stdin(Stdio::null())
+        .status()
+        .unwrap();
+    assert!(status.success());
+    let args = fs::read_to_string(bin.join("podman.args")).unwrap();
+    assert!(args.starts_with("manifest push --all "), "{}", args);
+
+    remove_dir_all(path).unwrap();
+}
+
+#[test]
+fn docker_pushes_platforms_with_buildx() {
+    let path = setup("docker_pushes_platforms_with_buildx", FUNCTION);
+    let bin = path.join("bin");
+    fake_engine(&bin, "docker", ENGINE);
+
+    let release = |push: bool| {
+        let mut command = Command::new(roche());
+        command
+            .arg("release")
+            .arg("-e")
+            .arg("docker")
+            .arg("--platform")
+            .arg("linux/amd64, linux/arm64")
+            .arg("-t")
+            .arg("quay.io/myorg/hello:1.0.0")
+            .env("PATH", &bin)
+            .current_dir(&path)
+            .stdin(Stdio::null());
+        if push {
+            command.arg("--push");
+        }
+        command.output().unwrap()
+    };
+
+    let output = release(false);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&output.stderr).contains(
+        "docker can't keep an image for linux/amd64, linux/arm64 locally. Please add --push"
+    ));
+    assert!(!bin.join("docker.args").exists());
+
+    let output = release(true);
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stdout)
+        .contains("Roche: Pushed quay.io/myorg/hello:1.0.0 for linux/amd64, linux/arm64"));
+    // buildx pushed the list, there is no separate push.
+    let args = fs::read_to_string(bin.join("docker.args")).unwrap();
+    assert_eq!(
+        args.trim(),
+        "buildx build --platform linux/amd64,linux/arm64 -tquay.io/myorg/hello:1.0.0 --push -f- ."
+    );
+
+    remove_dir_all(path).unwrap();
+}
+
+#[test]
+fn platforms_are_validated() {
+    assert_eq!(
+        roche::build::parse_platforms("linux/amd64,linux/arm/v7").unwrap(),
+        vec!["linux/amd64", "linux/arm/v7"]
+    );
+    let err = roche::build::parse_platforms("linux/amd64,arm64").unwrap_err();
+    assert_eq!(
+        format!("{}", err),
+        "'arm64' is not a platform such as linux/amd64 or linux/arm64/v8"
+    );
+
+    let path = setup("platforms_are_validated", FUNCTION);
+    fs::write(
+        path.join("roche.toml"),
+        "[build]\nplatforms = [\"linux/amd64\", \"Linux/ARM64\"]\n",
+    )
+    .unwrap();
+    let output = Command::new(roche())
+        .arg("config")
+        .arg("show")
+        .current_dir(&path)
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr)
+        .contains("build.platforms 'Linux/ARM64' is not a platform"));
+
+    fs::write(
+        path.join("roche.toml"),
+        "[build]\nplatforms = [\"linux/amd64\", \"linux/arm64\"]\n",
+    )
+    .unwrap();
+    let output = Command::new(roche())
+        .arg("config")
+        .arg("show")
+        .current_dir(&path)
+        .output()
+        .unwrap();
+    assert!(String::from_utf8_lossy(&output.stdout)
+        .contains("build.platforms        = [\"linux/amd64\", \"linux/arm64\"]"));
+
+    remove_dir_all(path).unwrap();
+}
@@ -0,0 +1,161 @@
+mod common;
+
+use common::{fake_engine, roche, setup, FUNCTION};
+use remove_dir_all::*;
+use std::env;
+use std::fs;
+use std::process::Command;
+
+// `myuser:secret`
+const DOCKER_CONFIG: &str = r#"{
+  "auths": {
+    "quay.io": { "auth": "bXl1c2VyOnNlY3JldA==" },
+    "https://index.docker.io/v1/": {}
+  },
+  "credHelpers": { "ghcr.io": "desktop" }
+}"#;
+
+#[test]
+fn push_reports_login_and_digest() {
+    let path = setup("push_reports_login_and_digest", FUNCTION);
+    let bin = path.join("bin");
+    fake_engine(
+        &bin,
+        "docker",
+        "echo 'The push refers to repository [quay.io/myorg/hello]'\n\
+         echo '1.0.0: digest: sha256:4f1e2d size: 1573'\n",
+    );
+    fs::write(path.join("config.json"), DOCKER_CONFIG).unwrap();
+
+    let output = Command::new(roche())
+        .arg("push")
+        .arg("-e")
+        .arg("docker")
+        .arg("-t")
+        .arg("quay.io/myorg/hello:1.0.0")
+        .env("PATH", &bin)
+        .env("DOCKER_CONFIG", &path)
+        .current_dir(&path)
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("Roche: Pushing quay.io/myorg/hello:1.0.0 to quay.io as myuser"));
+    assert!(stdout.contains("Roche: Pushed quay.io/myorg/hello:1.0.0@sha256:4f1e2d"));
+    let args = fs::read_to_string(bin.join("docker.args")).unwrap();
+    assert_eq!(args.trim(), "push quay.io/myorg/hello:1.0.0");
+
+    // podman writes the digest to a file instead.
+    fake_engine(
+        &bin,
+        "podman",
+        "while [ \"$1\" != \"--digestfile\" ]; do shift; done\nprintf 'sha256:77aa' > \"$2\"\n",
+    );
+    fs::write(
+        path.join("roche.toml"),
+        "[tag]\nname = \"hello\"\nversion = \"2.0.0\"\n",
+    )
+    .unwrap();
+    let output = Command::new(roche())
+        .arg("push")
+        .arg("-e")
+        .arg("podman")
+        .arg("--registry")
+        .arg("ghcr.io/myorg")
+        .env("PATH", &bin)
+        .env("DOCKER_CONFIG", &path)
+        .env_remove("REGISTRY_AUTH_FILE")
+        .env_remove("XDG_RUNTIME_DIR")
+        .env("HOME", &path)
+        .current_dir(&path)
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains(
+        "Roche: Pushing ghcr.io/myorg/hello:2.0.0 to ghcr.io with the desktop credential helper"
+    ));
+    assert!(stdout.contains("Roche: Pushed ghcr.io/myorg/hello:2.0.0@sha256:77aa"));
+    let args = fs::read_to_string(bin.join("podman.args")).unwrap();
+    assert!(args.starts_with("push --digestfile "), "{}", args);
+    assert!(
+        args.trim().ends_with(" ghcr.io/myorg/hello:2.0.0"),
+        "{}",
+        args
+    );
+
+    remove_dir_all(path).unwrap();
+}
+
+#[test]
+fn release_push_suggests_a_login() {
+    let path = setup("release_push_suggests_a_login", FUNCTION);
+    let bin = path.join("bin");
+    fake_engine(
+        &bin,
+        "docker",
+        "[ \"$1\" = push ] && echo 'unauthorized: authentication required' >&2 && exit 1\n\
+         while IFS= read -r line; do :; done\n",
+    );
+
+    let output = Command::new(roche())
+        .arg("release")
+        .arg("--push")
+        .arg("-e")
+        .arg("docker")
+        .arg("-t")
+        .arg("registry.example.com:5000/hello")
+        .env("PATH", &bin)
+        .env("DOCKER_CONFIG", &path)
+        .current_dir(&path)
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(1));
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("Roche: Build complete for registry.example.com:5000/hello"));
+    assert!(stdout.contains("No login for registry.example.com:5000 found"));
+    assert!(String::from_utf8_lossy(&output.stderr)
+        .contains("Please log in with 'docker login registry.example.com:5000'"));
+    let args = fs::read_to_string(bin.join("docker.args")).unwrap();
+    assert!(
+        args.ends_with("push registry.example.com:5000/hello\n"),
+        "{}",
+        args
+    );
+
+    remove_dir_all(path).unwrap();
+}
+
+#[test]
+fn registry_hosts_and_credentials() {
+    assert_eq!(
+        roche::registry::host("quay.io/myorg/hello:1.0.0"),
+        "quay.io"
+    );
+    assert_eq!(
+        roche::registry::host("localhost:5000/hello"),
+        "localhost:5000"
+    );
+    assert_eq!(roche::registry::host("localhost/hello"), "localhost");
+    assert_eq!(roche::registry::host("myorg/hello"), "docker.io");
+    assert_eq!(roche::registry::host("hello:1.0.0"), "docker.io");
+
+    let path = setup("registry_hosts_and_credentials", FUNCTION);
+    fs::write(path.join("config.json"), DOCKER_CONFIG).unwrap();
+    env::set_var("DOCKER_CONFIG", &path);
+    let login = roche::registry::credentials("docker", "quay.io")
+        .unwrap()
+        .unwrap();
+    assert_eq!(login.username.as_deref(), Some("myuser"));
+    assert_eq!(login.file, path.join("config.json"));
+    let login = roche::registry::credentials("docker", "docker.io")
+        .unwrap()
+        .unwrap();
+    assert_eq!((login.username, login.helper), (None, None));
+    assert_eq!(
+        roche::registry::credentials("docker", "gcr.io").unwrap(),
+        None
+    );
+
+    remove_dir_all(path).unwrap();
+}
@@ -0,0 +1,88 @@
+mod common;
+
+use common::{roche, root};
+use remove_dir_all::*;
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+#[test]
+fn routes_of_the_examples() {
+    let examples = Path::new(env!("CARGO_MANIFEST_DIR")).join("examples");
+
+    let output = Command::new(roche())
+        .arg("routes")
+        .current_dir(examples.join("json"))
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "METHOD  PATH      SOURCE\nGET     /animals  functions.rs:12\n"
+    );
+
+    let output = Command::new(roche())
+        .arg("routes")
+        .arg("--json")
+        .current_dir(examples.join("event"))
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let routes: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(
+        routes,
+        serde_json::json!([
+            { "method": "POST", "path": "/", "file": "functions.rs", "line": 14 }
+        ])
+    );
+}
+
+#[test]
+fn routes_of_a_multi_function_project() {
+    let path = root("routes_of_a_multi_function_project");
+    if Path::new(&path).exists() {
+        remove_dir_all(&path).unwrap();
+    }
+    let functions = path.join("src").join("functions");
+    fs::create_dir_all(&functions).unwrap();
+    fs::write(
+        functions.join("users.rs"),
+        "pub fn handler() -> tide::Server<()> {\n    let mut api = tide::new();\n    api.at(\"/\").get(|_| async { Ok(\"[]\") }).post(|_| async { Ok(\"\") });\n    api.at(\"/:id\").delete(|_| async { Ok(\"\") });\n    api\n}\n",
+    )
+    .unwrap();
+    fs::write(
+        path.join("roche.toml"),
+        "[functions]\nusers = \"/api/users\"\n",
+    )
+    .unwrap();
+
+    let output = Command::new(roche())
+        .arg("routes")
+        .current_dir(&path)
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "METHOD  PATH            SOURCE\n\
+         GET     /api/users      functions/users.rs:3\n\
+         POST    /api/users      functions/users.rs:3\n\
+         DELETE  /api/users/:id  functions/users.rs:4\n"
+    );
+
+    fs::write(functions.join("users.rs"), "pub fn handler() {}\n").unwrap();
+    let output = Command::new(roche())
+        .arg("routes")
+        .arg("--json")
+        .current_dir(&path)
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(1));
+    assert!(output.stdout.is_empty());
+    assert!(
+        String::from_utf8_lossy(&output.stderr).contains("users.rs:1:15: handler() must return")
+    );
+
+    remove_dir_all(path).unwrap();
+}
@@ -0,0 +1,120 @@
+mod common;
+
+use common::{fake_engine, roche, setup};
+use remove_dir_all::*;
+use roche::RunRequest;
+use std::fs;
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+#[test]
+fn run_flags_map_ports_and_env_files() {
+    let path = setup("run_flags_map_ports_and_env_files", "");
+    fs::write(path.join(".env"), "MY_VAR=hello").unwrap();
+
+    let request = RunRequest {
+        tag: "dev-hello".to_string(),
+        name: roche::run::container_name(&path.join("My Project").join("src")),
+        host_port: 9090,
+        container_port: 8080,
+        env_files: vec![path.join(".env"), path.join("missing.env")],
+    };
+    assert_eq!(request.name, "roche-my-project");
+    assert_eq!(
+        request.engine_flags(),
+        vec![
+            "-p".to_string(),
+            "9090:8080".to_string(),
+            "-e".to_string(),
+            "PORT=8080".to_string(),
+            "--env-file".to_string(),
+            path.join(".env").display().to_string(),
+        ]
+    );
+
+    let buildah = roche::engine::from_name("buildah").unwrap();
+    let err = buildah
+        .run_args(&request.name, &request.tag, &request.engine_flags())
+        .unwrap_err();
+    assert!(format!("{}", err).contains("podman"));
+
+    remove_dir_all(path).unwrap();
+}
+
+#[test]
+fn run_builds_then_removes_container_on_interrupt() {
+    let path = setup("run_builds_then_removes_container_on_interrupt", "");
+    fs::write(path.join(".env"), "MY_VAR=hello").unwrap();
+    let bin = path.join("bin");
+    fake_docker(&bin);
+
+    let mut child = Command::new(roche())
+        .arg("run")
+        .arg("--engine")
+        .arg("docker")
+        .arg("-t")
+        .arg("registry/hello")
+        .arg("-p")
+        .arg("9090")
+        .env("PATH", &bin)
+        .current_dir(&path)
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+
+    // Wait for the container to start before interrupting roche.
+    let args = bin.join("docker.args");
+    let started = Instant::now();
+    while !fs::read_to_string(&args)
+        .map(|a| a.contains("run "))
+        .unwrap_or(false)
+    {
+        assert!(started.elapsed() < Duration::from_secs(10));
+        thread::sleep(Duration::from_millis(50));
+    }
+    Command::new("kill")
+        .arg("-INT")
+        .arg(child.id().to_string())
+        .status()
+        .unwrap();
+    let status = child.wait().unwrap();
+    assert!(status.success());
+
+    let args = fs::read_to_string(args).unwrap();
+    let lines: Vec<&str> = args.lines().collect();
+    assert_eq!(lines[0], "build -tregistry/hello -f- .");
+    assert_eq!(
+        lines[1],
+        format!(
+            "run --rm --name roche-{} -p 9090:8080 -e PORT=8080 --env-file {} registry/hello",
+            path.file_name().unwrap().to_str().unwrap(),
+            path.join(".env").display()
+        )
+    );
+    assert_eq!(
+        lines[2],
+        format!(
+            "rm -f roche-{}",
+            path.file_name().unwrap().to_str().unwrap()
+        )
+    );
+
+    remove_dir_all(path).unwrap();
+}
+
+/// Writes a docker stand-in whose `run` blocks until `rm` is called for the container.
+fn fake_docker(bin: &Path) {
+    fake_engine(
+        bin,
+        "docker",
+        &format!(
+            "case \"$1\" in\n\
+             run) while [ ! -f {dir}/stopped ]; do /bin/sleep 0.1; done ;;\n\
+             rm) : > {dir}/stopped ;;\n\
+             esac\n",
+            dir = bin.display()
+        ),
+    );
+}
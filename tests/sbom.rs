@@ -0,0 +1,315 @@
+mod common;
+
+use common::{assert_golden, fake_engine, roche, setup, write_script, FUNCTION};
+use remove_dir_all::*;
+use roche::sbom::{self, Format, ImageFiles, Sbom};
+use roche::{BuildKind, BuildRequest};
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::Path;
+use std::process::{Command, Output, Stdio};
+
+const LOCK: &str = r#"# This file is automatically @generated by Cargo.
+# It is not intended for manual editing.
+version = 3
+
+[[package]]
+name = "itoa"
+version = "0.4.8"
+source = "registry+https://github.com/rust-lang/crates.io-index"
+checksum = "b71991ff56294aa922b450139ee08b3bfc70982c6b2c7562771375cf73542dd4"
+
+[[package]]
+name = "itoa"
+version = "1.0.1"
+source = "registry+https://github.com/rust-lang/crates.io-index"
+checksum = "1aab8fc367588b89dcee83ab0fd66b72b50b72fa1904d7095045ace2b0c81c35"
+
+[[package]]
+name = "roche-service"
+version = "0.1.0"
+dependencies = [
+ "itoa 1.0.1",
+ "serde_json",
+]
+
+[[package]]
+name = "serde_json"
+version = "1.0.79"
+source = "registry+https://github.com/rust-lang/crates.io-index"
+checksum = "8e8d9fa5c3b304765ce1fd9c4c8a3de2c8db365a5b91be52f186efc675681d95"
+dependencies = [
+ "itoa 0.4.8",
+]
+"#;
+
+const APK_DB: &str = "C:Q1zrvbf6ysmBOwnn7NkmoKPsdaeaI=\n\
+P:musl\n\
+V:1.1.24-r9\n\
+A:x86_64\n\
+L:MIT\n\
+o:musl\n\
+\n\
+C:Q1Nr7Y0ll4I9BVXMf8Bm0xvUqr1i8=\n\
+P:libgcc\n\
+V:9.3.0-r2\n\
+A:x86_64\n\
+L:GPL-2.0-or-later LGPL-2.1-or-later\n\
+o:gcc\n\
+\n";
+
+const OS_RELEASE: &str = "NAME=\"Alpine Linux\"\nID=alpine\nVERSION_ID=3.12.0\n";
+
+#[test]
+fn release_sbom_from_the_image() {
+    let path = setup("release_sbom_from_the_image", FUNCTION);
+    let bin = path.join("bin");
+    fake_podman(
+        &bin,
+        &[
+            (sbom::LOCK_FILE, LOCK),
+            (sbom::APK_DB, APK_DB),
+            (sbom::OS_RELEASE, OS_RELEASE),
+        ],
+    );
+
+    let output = Command::new(roche())
+        .arg("release")
+        .arg("-e")
+        .arg("podman")
+        .arg("-t")
+        .arg("registry/sbom:1.0.0")
+        .arg("--sbom")
+        .env("PATH", &bin)
+        .env("SOURCE_DATE_EPOCH", "1600000000")
+        .current_dir(&path)
+        .stdin(Stdio::null())
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("sbom.cdx.json CycloneDX SBOM with 4 crates and 2 packages"));
+    assert!(stdout.contains("sbom.spdx.json SPDX SBOM with 4 crates and 2 packages"));
+    assert_golden("sbom.cdx.json", &path.join("sbom.cdx.json"));
+    assert_golden("sbom.spdx.json", &path.join("sbom.spdx.json"));
+
+    // The release template keeps the lock file for the SBOM only, and the container is removed
+    // again.
+    let df = fs::read_to_string(bin.join("podman.dockerfile")).unwrap();
+    assert!(df.contains(" /app-build/Cargo.lock "));
+    let request = BuildRequest {
+        kind: BuildKind::Release,
+        context_dir: path.clone(),
+        ..Default::default()
+    };
+    assert!(!request.render_dockerfile().unwrap().contains("Cargo.lock"));
+    let args = fs::read_to_string(bin.join("podman.args")).unwrap();
+    assert!(args.contains("create registry/sbom:1.0.0\n"));
+    assert!(args.contains("cp c0ffee:/lib/apk/db/installed -\n"));
+    assert!(args.ends_with("rm -f c0ffee\n"));
+
+    remove_dir_all(path).unwrap();
+}
+
+#[test]
+fn sbom_subcommand_attaches() {
+    let path = setup("sbom_subcommand_attaches", FUNCTION);
+    let bin = path.join("bin");
+    fake_podman(&bin, &[(sbom::LOCK_FILE, LOCK)]);
+    write_script(
+        &bin,
+        "oras",
+        &format!(
+            "#!/bin/sh\necho \"$@\" >> {dir}/oras.args\n/bin/pwd >> {dir}/oras.args\n",
+            dir = bin.display()
+        ),
+    );
+    fs::create_dir_all(path.join("out")).unwrap();
+
+    let sbom = |args: &[&str]| -> Output {
+        Command::new(roche())
+            .arg("sbom")
+            .args(args)
+            .env("PATH", &bin)
+            .current_dir(&path)
+            .stdin(Stdio::null())
+            .output()
+            .unwrap()
+    };
+    let output = sbom(&[
+        "registry/sbom:1.0.0",
+        "-e",
+        "podman",
+        "--format",
+        "spdx",
+        "-o",
+        "out/app",
+        "--attach",
+    ]);
+    assert!(output.status.success());
+    assert!(path.join("out").join("app.spdx.json").exists());
+    assert!(!path.join("out").join("app.cdx.json").exists());
+    let attached = fs::read_to_string(bin.join("oras.args")).unwrap();
+    assert_eq!(
+        attached.lines().collect::<Vec<_>>(),
+        vec![
+            "attach --artifact-type application/spdx+json registry/sbom:1.0.0 app.spdx.json:application/spdx+json",
+            path.join("out").to_str().unwrap(),
+        ]
+    );
+    assert!(String::from_utf8_lossy(&output.stdout)
+        .contains("Roche: Attached the SPDX SBOM to registry/sbom:1.0.0"));
+
+    // Images from a user template without the lock file can't be described.
+    fake_podman(&bin, &[]);
+    let output = sbom(&["registry/sbom:1.0.0", "-e", "podman"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains(
+        "registry/sbom:1.0.0 has no /app/Cargo.lock. Please build it with 'roche release --sbom', which keeps the lock file, or copy /app-build/Cargo.lock to /app in your Release.Dockerfile"
+    ));
+
+    let output = Command::new(roche())
+        .arg("release")
+        .arg("-e")
+        .arg("podman")
+        .arg("-t")
+        .arg("registry/sbom:1.0.0")
+        .arg("--attach-sbom")
+        .env("PATH", &bin)
+        .current_dir(&path)
+        .stdin(Stdio::null())
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Please add --push"));
+
+    remove_dir_all(path).unwrap();
+}
+
+#[test]
+fn sbom_components() {
+    let crates = sbom::crates(LOCK).unwrap();
+    let service = crates.iter().find(|c| c.name == "roche-service").unwrap();
+    assert_eq!(
+        service.dependencies,
+        vec!["pkg:cargo/itoa@1.0.1", "pkg:cargo/serde_json@1.0.79"]
+    );
+
+    // A crate from git can share its name and version with one from crates.io.
+    let git = "git+https://github.com/dtolnay/itoa?branch=master#5a7e1b2c";
+    let lock = format!(
+        "{}\n[[package]]\nname = \"itoa\"\nversion = \"1.0.1\"\nsource = \"{}\"\n\n\
+         [[package]]\nname = \"patched\"\nversion = \"0.1.0\"\n\
+         dependencies = [\n \"itoa 1.0.1 ({})\",\n]\n",
+        LOCK, git, git
+    );
+    let crates = sbom::crates(&lock).unwrap();
+    let from_git = "pkg:cargo/itoa@1.0.1?vcs_url=git%2Bhttps%3A%2F%2Fgithub.com%2Fdtolnay%2Fitoa%3Fbranch%3Dmaster%235a7e1b2c";
+    assert_eq!(
+        crates.iter().map(|c| c.purl.as_str()).collect::<Vec<_>>(),
+        vec![
+            "pkg:cargo/itoa@0.4.8",
+            "pkg:cargo/itoa@1.0.1",
+            "pkg:cargo/roche-service@0.1.0",
+            "pkg:cargo/serde_json@1.0.79",
+            from_git,
+            "pkg:cargo/patched@0.1.0",
+        ]
+    );
+    assert_eq!(crates[5].dependencies, vec![from_git]);
+    let files = ImageFiles {
+        lock: Some(lock),
+        ..Default::default()
+    };
+    let spdx = Sbom::new("registry/sbom:1.0.0", "alpine:3.12", &files)
+        .unwrap()
+        .render(Format::Spdx)
+        .unwrap();
+    let spdx: serde_json::Value = serde_json::from_str(&spdx).unwrap();
+    let ids: BTreeSet<&str> = spdx["packages"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|p| p["SPDXID"].as_str().unwrap())
+        .collect();
+    assert_eq!(ids.len(), 8, "{:?}", ids);
+
+    let status = "Package: libc6\n\
+                  Status: install ok installed\n\
+                  Architecture: amd64\n\
+                  Version: 2.31-13+deb11u2\n\
+                  Description: GNU C Library: Shared libraries\n \
+                  Contains the standard libraries.\n\
+                  \n\
+                  Package: removed\n\
+                  Status: deinstall ok config-files\n\
+                  Version: 1.0\n\
+                  \n\
+                  Package: tzdata\n\
+                  Status: install ok installed\n\
+                  Architecture: all\n\
+                  Version: 1:2021a-1+deb11u2\n";
+    let packages = sbom::deb_packages(status, "debian");
+    assert_eq!(
+        packages.iter().map(|p| p.purl.as_str()).collect::<Vec<_>>(),
+        vec![
+            "pkg:deb/debian/libc6@2.31-13%2Bdeb11u2?arch=amd64",
+            "pkg:deb/debian/tzdata@1%3A2021a-1%2Bdeb11u2?arch=all",
+        ]
+    );
+
+    let files = ImageFiles {
+        lock: Some(LOCK.to_string()),
+        dpkg_status: Some(status.to_string()),
+        os_release: Some("ID=\"debian\"\n".to_string()),
+        ..Default::default()
+    };
+    let sbom = Sbom::new("registry/sbom:1.0.0", "debian:bullseye-slim", &files).unwrap();
+    assert_eq!((sbom.crates.len(), sbom.packages.len()), (4, 2));
+    let spdx: serde_json::Value =
+        serde_json::from_str(&sbom.render(Format::Spdx).unwrap()).unwrap();
+    assert!(spdx["relationships"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .any(|r| r["spdxElementId"] == "SPDXRef-cargo-serde-json-1.0.79"
+            && r["relationshipType"] == "DEPENDS_ON"
+            && r["relatedSpdxElement"] == "SPDXRef-cargo-itoa-0.4.8"));
+    assert!("swid".parse::<Format>().is_err());
+}
+
+/// Writes a podman stand-in that accepts builds and creates a container `c0ffee` whose `files`,
+/// given as image path and contents, can be copied out.
+fn fake_podman(bin: &Path, files: &[(&str, &str)]) {
+    fs::create_dir_all(bin).unwrap();
+    let mut copies = String::new();
+    for (i, (file, contents)) in files.iter().enumerate() {
+        let archive = bin.join(format!("file-{}.tar", i));
+        let mut builder = tar::Builder::new(fs::File::create(&archive).unwrap());
+        let mut header = tar::Header::new_gnu();
+        header.set_size(contents.len() as u64);
+        header.set_mode(0o644);
+        header.set_cksum();
+        builder
+            .append_data(
+                &mut header,
+                Path::new(file).file_name().unwrap(),
+                contents.as_bytes(),
+            )
+            .unwrap();
+        builder.finish().unwrap();
+        copies.push_str(&format!(
+            "  \"cp c0ffee:{} -\") /bin/cat {} ;;\n",
+            file,
+            archive.display()
+        ));
+    }
+    fake_engine(
+        bin,
+        "podman",
+        &format!(
+            "case \"$*\" in\n  create*) echo c0ffee ;;\n{}  cp*) exit 125 ;;\nesac\n",
+            copies
+        ),
+    );
+}
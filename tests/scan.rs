@@ -0,0 +1,240 @@
+mod common;
+
+use common::{fake_engine, roche, setup, write_script, FUNCTION};
+use remove_dir_all::*;
+use roche::scan::{self, ImageSource, ScanReport, Scanner, Severity};
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::Path;
+use std::process::{Command, Output, Stdio};
+
+const TRIVY_REPORT: &str = r#"{
+  "SchemaVersion": 2,
+  "ArtifactName": "registry/scan:1.0.0",
+  "Results": [
+    {
+      "Target": "registry/scan:1.0.0 (alpine 3.12.0)",
+      "Class": "os-pkgs",
+      "Vulnerabilities": [
+        {
+          "VulnerabilityID": "CVE-2021-3711",
+          "PkgName": "libssl1.1",
+          "InstalledVersion": "1.1.1g-r0",
+          "FixedVersion": "1.1.1l-r0",
+          "Severity": "CRITICAL"
+        },
+        {
+          "VulnerabilityID": "CVE-2021-36159",
+          "PkgName": "apk-tools",
+          "InstalledVersion": "2.10.5-r1",
+          "FixedVersion": "2.10.7-r0",
+          "Severity": "HIGH"
+        },
+        {
+          "VulnerabilityID": "CVE-2020-28928",
+          "PkgName": "musl",
+          "InstalledVersion": "1.1.24-r9",
+          "FixedVersion": "",
+          "Severity": "MEDIUM"
+        }
+      ]
+    },
+    {
+      "Target": "app/roche-service",
+      "Class": "lang-pkgs",
+      "Vulnerabilities": null
+    }
+  ]
+}"#;
+
+const GRYPE_REPORT: &str = r#"{
+  "matches": [
+    {
+      "vulnerability": {
+        "id": "GHSA-5mcr-gq6c-3hq2",
+        "severity": "High",
+        "fix": { "versions": ["0.4.5"], "state": "fixed" }
+      },
+      "artifact": { "name": "hyper", "version": "0.14.2", "type": "rust-crate" }
+    },
+    {
+      "vulnerability": { "id": "CVE-2022-0001", "severity": "Negligible" },
+      "artifact": { "name": "busybox", "version": "1.31.1-r19", "type": "apk" }
+    }
+  ]
+}"#;
+
+#[test]
+fn parse_scanner_reports() {
+    let trivy = ScanReport {
+        vulnerabilities: scan::Trivy.parse(TRIVY_REPORT).unwrap(),
+    };
+    assert_eq!(
+        format!("{}", trivy),
+        "3 vulnerabilities: 1 CRITICAL, 1 HIGH, 1 MEDIUM"
+    );
+    let failing = trivy.failing(Severity::High, &BTreeSet::new());
+    assert_eq!(
+        scan::table(&failing),
+        "SEVERITY  ID              PACKAGE    INSTALLED  FIXED\n\
+         CRITICAL  CVE-2021-3711   libssl1.1  1.1.1g-r0  1.1.1l-r0\n\
+         HIGH      CVE-2021-36159  apk-tools  2.10.5-r1  2.10.7-r0\n"
+    );
+    let ignored: BTreeSet<String> = vec!["CVE-2021-3711".to_string()].into_iter().collect();
+    assert_eq!(trivy.failing(Severity::Critical, &ignored), vec![]);
+    assert_eq!(trivy.failing(Severity::Medium, &ignored).len(), 2);
+
+    let grype = ScanReport {
+        vulnerabilities: scan::Grype.parse(GRYPE_REPORT).unwrap(),
+    };
+    assert_eq!(
+        format!("{}", grype),
+        "2 vulnerabilities: 1 HIGH, 1 NEGLIGIBLE"
+    );
+    assert_eq!(grype.vulnerabilities[0].fixed.as_deref(), Some("0.4.5"));
+    assert_eq!(grype.vulnerabilities[1].fixed, None);
+
+    assert_eq!(
+        scan::Trivy.scan_args("registry/scan:1.0.0", &ImageSource::engine("buildah")),
+        vec![
+            "image",
+            "--format",
+            "json",
+            "--quiet",
+            "--image-src",
+            "podman",
+            "registry/scan:1.0.0"
+        ]
+    );
+    assert_eq!(
+        scan::Grype.scan_args("registry/scan:1.0.0", &ImageSource::Registry),
+        vec!["registry:registry/scan:1.0.0", "-o", "json", "-q"]
+    );
+    assert!("severe".parse::<Severity>().is_err());
+}
+
+#[test]
+fn release_scan_fails_before_push() {
+    let path = setup("release_scan_fails_before_push", FUNCTION);
+    let bin = path.join("bin");
+    fake_engines(&bin);
+    fake_scanner(&bin, "trivy", TRIVY_REPORT);
+
+    let output = release(&path, &bin);
+    assert!(!output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("Roche: Scanning registry/scan:1.0.0 with trivy"));
+    assert!(stdout.contains("CRITICAL  CVE-2021-3711   libssl1.1"));
+    assert!(stdout.contains("Roche: Scan found 3 vulnerabilities: 1 CRITICAL, 1 HIGH, 1 MEDIUM"));
+    assert!(String::from_utf8_lossy(&output.stderr)
+        .contains("registry/scan:1.0.0 has 2 vulnerabilities at or above HIGH"));
+    let scanned = fs::read_to_string(bin.join("trivy.args")).unwrap();
+    assert_eq!(
+        scanned.trim(),
+        "image --format json --quiet --image-src podman registry/scan:1.0.0"
+    );
+    let engine = fs::read_to_string(bin.join("podman.args")).unwrap();
+    assert!(!engine.lines().any(|line| line.starts_with("push")));
+
+    // Accepted vulnerabilities and a higher threshold let the release through to the push.
+    fs::write(
+        path.join("roche.toml"),
+        "[scan]\nscanner = \"trivy\"\nseverity = \"critical\"\nignore = [\"CVE-2021-3711\"]\n",
+    )
+    .unwrap();
+    let output = release(&path, &bin);
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stdout)
+        .contains("Roche: No vulnerabilities at or above CRITICAL"));
+    let engine = fs::read_to_string(bin.join("podman.args")).unwrap();
+    assert!(engine.lines().any(|line| line.starts_with("push")));
+
+    remove_dir_all(path).unwrap();
+}
+
+#[test]
+fn scan_subcommand() {
+    let path = setup("scan_subcommand", FUNCTION);
+    let bin = path.join("bin");
+    fake_engines(&bin);
+    fake_scanner(&bin, "grype", GRYPE_REPORT);
+
+    let scan = |args: &[&str]| -> Output {
+        Command::new(roche())
+            .arg("scan")
+            .args(args)
+            .env("PATH", &bin)
+            .current_dir(&path)
+            .stdin(Stdio::null())
+            .output()
+            .unwrap()
+    };
+    let output = scan(&["registry/scan:1.0.0", "-e", "podman"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stdout)
+        .contains("HIGH      GHSA-5mcr-gq6c-3hq2  hyper    0.14.2     0.4.5"));
+
+    let output = scan(&[
+        "registry/scan:1.0.0",
+        "-e",
+        "docker",
+        "--severity",
+        "critical",
+        "--remote",
+    ]);
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stdout)
+        .contains("Roche: Scan found 2 vulnerabilities: 1 HIGH, 1 NEGLIGIBLE"));
+    let scanned = fs::read_to_string(bin.join("grype.args")).unwrap();
+    assert_eq!(
+        scanned.lines().collect::<Vec<_>>(),
+        vec![
+            "podman:registry/scan:1.0.0 -o json -q",
+            "registry:registry/scan:1.0.0 -o json -q",
+        ]
+    );
+
+    let output = scan(&["registry/scan:1.0.0", "-e", "podman", "--scanner", "clair"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr)
+        .contains("Unknown scanner 'clair'. Supported scanners are trivy, grype"));
+
+    remove_dir_all(path).unwrap();
+}
+
+fn release(path: &Path, bin: &Path) -> Output {
+    Command::new(roche())
+        .arg("release")
+        .arg("-e")
+        .arg("podman")
+        .arg("-t")
+        .arg("registry/scan:1.0.0")
+        .arg("--scan")
+        .arg("--push")
+        .env("PATH", bin)
+        .env("HOME", path)
+        .current_dir(path)
+        .stdin(Stdio::null())
+        .output()
+        .unwrap()
+}
+
+/// Writes podman and docker stand-ins that record their arguments and accept builds and pushes.
+fn fake_engines(bin: &Path) {
+    fake_engine(bin, "podman", "");
+    fake_engine(bin, "docker", "");
+}
+
+/// Writes a stand-in for the scanner `name` that records its arguments and prints `report`.
+fn fake_scanner(bin: &Path, name: &str, report: &str) {
+    fs::write(bin.join(format!("{}.json", name)), report).unwrap();
+    write_script(
+        bin,
+        name,
+        &format!(
+            "#!/bin/sh\necho \"$@\" >> {dir}/{name}.args\n/bin/cat {dir}/{name}.json\n",
+            dir = bin.display(),
+            name = name
+        ),
+    );
+}
@@ -0,0 +1,195 @@
+mod common;
+
+use common::{fake_engine, roche, setup, FUNCTION};
+use remove_dir_all::*;
+use roche::build::{BuildKind, BuildRequest};
+use roche::secrets::Secret;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+#[test]
+fn build_secrets_are_mounted() {
+    let path = setup("build_secrets_are_mounted", FUNCTION);
+    fs::write(path.join("lib.rs"), "").unwrap();
+    fs::write(path.join("Cargo.toml"), "[dependencies]\nprivate = \"1\"\n").unwrap();
+    let secrets: BTreeMap<String, Secret> = vec![
+        (
+            "CARGO_REGISTRIES_MINE_TOKEN".to_string(),
+            Secret {
+                env: Some("MINE_TOKEN".to_string()),
+                file: None,
+            },
+        ),
+        (
+            "NETRC".to_string(),
+            Secret {
+                env: None,
+                file: Some(path.join("netrc")),
+            },
+        ),
+    ]
+    .into_iter()
+    .collect();
+    let release = BuildRequest {
+        kind: BuildKind::Release,
+        build_image: "build/image".to_string(),
+        runtime_image: "runtime/image".to_string(),
+        tag: "registry/secrets".to_string(),
+        context_dir: path.clone(),
+        secrets,
+        ..Default::default()
+    };
+
+    assert_eq!(
+        release.engine_flags(),
+        vec![
+            "--secret".to_string(),
+            "id=CARGO_REGISTRIES_MINE_TOKEN,env=MINE_TOKEN".to_string(),
+            "--secret".to_string(),
+            format!("id=NETRC,src={}", path.join("netrc").display()),
+        ]
+    );
+    let prefix = "--mount=type=secret,id=CARGO_REGISTRIES_MINE_TOKEN,required=true \
+                  --mount=type=secret,id=NETRC,required=true \
+                  CARGO_REGISTRIES_MINE_TOKEN=\"$(cat /run/secrets/CARGO_REGISTRIES_MINE_TOKEN)\" \
+                  NETRC=\"$(cat /run/secrets/NETRC)\" ";
+    let df = release.render_dockerfile().unwrap();
+    assert!(df.contains(&format!("RUN {}cargo fetch\n", prefix)));
+    assert!(df.contains(&format!("RUN {}cargo build --release\n", prefix)));
+    assert!(df.contains(&format!("RUN {}cargo test --lib --release\n", prefix)));
+
+    remove_dir_all(path).unwrap();
+}
+
+#[test]
+fn release_refuses_secret_files() {
+    let path = setup("release_refuses_secret_files", FUNCTION);
+    fs::write(path.join(".env"), "API_KEY=hunter2\n").unwrap();
+    fs::write(path.join(".env.example"), "API_KEY=\n").unwrap();
+    fs::create_dir_all(path.join("certs")).unwrap();
+    fs::write(path.join("certs").join("server.key"), "-----BEGIN").unwrap();
+    let bin = path.join("bin");
+    fake_engine(&bin, "podman", "");
+
+    // The built in template only copies sources, so .env stays out of the image.
+    let output = release(&path, &bin);
+    assert!(output.status.success());
+    let df = fs::read_to_string(bin.join("podman.dockerfile")).unwrap();
+    assert!(!df.contains(".env"));
+
+    // A user template is sent the whole folder, which holds both files.
+    let templates = path.join(".roche").join("templates");
+    fs::create_dir_all(&templates).unwrap();
+    fs::write(
+        templates.join("Release.Dockerfile"),
+        "FROM {{ build_image }}\nCOPY . /app-build/src/\n",
+    )
+    .unwrap();
+    let output = release(&path, &bin);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains(
+        ".env would be copied into the image but matches the secret pattern '.env'. Please add it to .rocheignore"
+    ));
+
+    fs::write(path.join(".rocheignore"), ".env\n").unwrap();
+    let output = release(&path, &bin);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains(
+        "certs/server.key would be copied into the image but matches the secret pattern '*.key'"
+    ));
+
+    // gen refuses to write a Dockerfile that would copy it as well.
+    let output = Command::new(roche())
+        .arg("gen")
+        .current_dir(&path)
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    assert!(!path.join("Dockerfile").exists());
+
+    // The example env file holds no values and may be copied.
+    fs::write(path.join(".rocheignore"), ".env\ncerts/\n").unwrap();
+    let output = release(&path, &bin);
+    assert!(output.status.success());
+
+    remove_dir_all(path).unwrap();
+}
+
+#[test]
+fn build_secrets_come_from_roche_toml() {
+    let path = setup("build_secrets_come_from_roche_toml", FUNCTION);
+    fs::write(
+        path.join("roche.toml"),
+        "[secrets.REGISTRY_TOKEN]\nenv = \"ROCHE_TEST_REGISTRY_TOKEN\"\n",
+    )
+    .unwrap();
+    let bin = path.join("bin");
+    fake_engine(&bin, "podman", "");
+
+    let output = release(&path, &bin);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains(
+        "secrets.REGISTRY_TOKEN reads the environment variable ROCHE_TEST_REGISTRY_TOKEN, which isn't set"
+    ));
+
+    let output = Command::new(roche())
+        .arg("release")
+        .arg("-e")
+        .arg("podman")
+        .arg("-t")
+        .arg("registry/secrets")
+        .env("PATH", &bin)
+        .env("ROCHE_TEST_REGISTRY_TOKEN", "hunter2")
+        .current_dir(&path)
+        .stdin(Stdio::null())
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let args = fs::read_to_string(bin.join("podman.args")).unwrap();
+    assert!(args.contains("--secret id=REGISTRY_TOKEN,env=ROCHE_TEST_REGISTRY_TOKEN"));
+    assert!(!args.contains("hunter2"));
+    let df = fs::read_to_string(bin.join("podman.dockerfile")).unwrap();
+    assert!(!df.contains("hunter2"));
+
+    let output = Command::new(roche())
+        .arg("config")
+        .arg("show")
+        .current_dir(&path)
+        .output()
+        .unwrap();
+    assert!(String::from_utf8_lossy(&output.stdout)
+        .contains("secrets.REGISTRY_TOKEN = \"env ROCHE_TEST_REGISTRY_TOKEN\""));
+
+    fs::write(
+        path.join("roche.toml"),
+        "[secrets.token]\nenv = \"A\"\nfile = \"token.txt\"\n",
+    )
+    .unwrap();
+    let output = Command::new(roche())
+        .arg("config")
+        .arg("show")
+        .current_dir(&path)
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("secrets.token"));
+
+    remove_dir_all(path).unwrap();
+}
+
+fn release(path: &Path, bin: &Path) -> std::process::Output {
+    Command::new(roche())
+        .arg("release")
+        .arg("-e")
+        .arg("podman")
+        .arg("-t")
+        .arg("registry/secrets")
+        .env("PATH", bin)
+        .env_remove("ROCHE_TEST_REGISTRY_TOKEN")
+        .current_dir(path)
+        .stdin(Stdio::null())
+        .output()
+        .unwrap()
+}
@@ -0,0 +1,114 @@
+mod common;
+
+use common::{roche, setup};
+use remove_dir_all::*;
+use roche::native::NativeRequest;
+use roche::service::{self, SERVICE_VERSION};
+use roche::{BuildKind, BuildRequest};
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::process::Command;
+
+#[test]
+fn eject_writes_service_files() {
+    let path = setup("eject_writes_service_files", "");
+    let src = path.join("src");
+    fs::create_dir_all(&src).unwrap();
+    fs::rename(path.join("functions.rs"), src.join("functions.rs")).unwrap();
+
+    let output = Command::new(roche())
+        .arg("eject")
+        .current_dir(&path)
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+
+    let manifest = fs::read_to_string(src.join("Cargo.toml")).unwrap();
+    assert!(manifest.contains(&format!("version = \"{}\"", SERVICE_VERSION)));
+    assert!(manifest.contains("path = \"src/main.rs\""));
+    let main = fs::read_to_string(src.join("main.rs")).unwrap();
+    assert!(main.starts_with("mod functions;\n"));
+    let run = src.join("run.sh");
+    assert!(fs::read_to_string(&run)
+        .unwrap()
+        .contains("exec ./roche-service"));
+    assert_eq!(
+        fs::metadata(&run).unwrap().permissions().mode() & 0o111,
+        0o111
+    );
+
+    // Customisations are kept unless --force is used.
+    fs::write(src.join("main.rs"), "// custom\n").unwrap();
+    let output = Command::new(roche())
+        .arg("eject")
+        .current_dir(&path)
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("already exists"));
+    assert_eq!(
+        fs::read_to_string(src.join("main.rs")).unwrap(),
+        "// custom\n"
+    );
+
+    let status = Command::new(roche())
+        .arg("eject")
+        .arg("--force")
+        .current_dir(&path)
+        .status()
+        .unwrap();
+    assert!(status.success());
+    assert!(fs::read_to_string(src.join("main.rs"))
+        .unwrap()
+        .starts_with("mod functions;\n"));
+
+    remove_dir_all(path).unwrap();
+}
+
+#[test]
+fn builds_use_the_ejected_service() {
+    let path = setup("builds_use_the_ejected_service", "");
+    let dev = BuildRequest {
+        kind: BuildKind::Dev,
+        build_image: "build/image".to_string(),
+        runtime_image: "runtime/image".to_string(),
+        context_dir: path.clone(),
+        ..Default::default()
+    };
+    let release = BuildRequest {
+        kind: BuildKind::Release,
+        ..dev.clone()
+    };
+    assert!(!dev
+        .render_dockerfile()
+        .unwrap()
+        .contains("COPY Cargo.toml run.sh"));
+    assert!(!release.render_dockerfile().unwrap().contains("main.rs"));
+
+    service::eject(&path, false).unwrap();
+    let df = dev.render_dockerfile().unwrap();
+    assert!(df.contains("RUN printf '%s\\n' '# The roche-service"));
+    assert!(df.contains(
+        "RUN cargo fetch\nCOPY . /app-build/src/\nCOPY run.sh /app-build/\nRUN cargo build\n"
+    ));
+    let df = release.render_dockerfile().unwrap();
+    assert!(df.contains("RUN cargo fetch\nCOPY functions.rs"));
+    assert!(df.contains("COPY main.rs /app-build/src\nCOPY run.sh /app-build/\n"));
+
+    let native = NativeRequest {
+        context_dir: path.clone(),
+        cache_dir: path.join("cache"),
+        release: false,
+        ..Default::default()
+    };
+    let dir = native.write_crate().unwrap();
+    let manifest = fs::read_to_string(dir.join("Cargo.toml")).unwrap();
+    assert!(manifest.contains(&format!(
+        "path = {:?}",
+        path.join("main.rs").display().to_string()
+    )));
+    assert!(manifest.contains("[workspace]"));
+    assert!(!dir.join("src").join("main.rs").exists());
+
+    remove_dir_all(path).unwrap();
+}
@@ -0,0 +1,166 @@
+mod common;
+
+use common::{fake_engine, roche, setup};
+use remove_dir_all::*;
+use roche::watch::WatchRequest;
+use roche::{BuildRequest, RunRequest};
+use std::fs;
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+#[test]
+fn watch_covers_function_lib_and_env_files() {
+    let path = setup("watch_covers_function_lib_and_env_files", "");
+    let watch = WatchRequest {
+        build: BuildRequest {
+            context_dir: path.clone(),
+            ..Default::default()
+        },
+        run: RunRequest {
+            env_files: vec![path.join(".env")],
+            ..Default::default()
+        },
+        debounce: roche::watch::DEBOUNCE,
+    };
+    assert_eq!(
+        watch.watched_files().unwrap(),
+        vec![
+            path.join("functions.rs"),
+            path.join("lib.rs"),
+            path.join(".env")
+        ]
+    );
+
+    // Modules declared with `mod` are watched as well.
+    fs::write(path.join("functions.rs"), "mod db;\n").unwrap();
+    fs::create_dir_all(path.join("functions")).unwrap();
+    fs::write(path.join("functions").join("db.rs"), "").unwrap();
+    fs::write(path.join("lib.rs"), "mod util;\n").unwrap();
+    fs::write(path.join("util.rs"), "").unwrap();
+    assert_eq!(
+        watch.watched_files().unwrap(),
+        vec![
+            path.join("functions").join("db.rs"),
+            path.join("functions.rs"),
+            path.join("lib.rs"),
+            path.join("util.rs"),
+            path.join(".env")
+        ]
+    );
+
+    remove_dir_all(path).unwrap();
+}
+
+#[test]
+fn watch_rebuilds_and_restarts_on_change() {
+    let path = setup("watch_rebuilds_and_restarts_on_change", "");
+    let bin = path.join("bin");
+    fake_docker(&bin);
+
+    let child = Command::new(roche())
+        .arg("watch")
+        .arg("--engine")
+        .arg("docker")
+        .arg("-t")
+        .arg("registry/hello")
+        .env("PATH", &bin)
+        .current_dir(&path)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let args = bin.join("docker.args");
+    wait_for(&args, "run ", 1);
+
+    // A compile error is reported and nothing is restarted until it is fixed.
+    thread::sleep(Duration::from_millis(600));
+    fs::write(path.join("functions.rs"), "broken\n").unwrap();
+    wait_for(&args, "build ", 2);
+    thread::sleep(Duration::from_millis(600));
+    fs::write(path.join("functions.rs"), "fixed\n").unwrap();
+    wait_for(&args, "run ", 2);
+
+    // A module declared with `mod` is watched from when it is created.
+    thread::sleep(Duration::from_millis(600));
+    fs::write(path.join("lib.rs"), "mod util;\n").unwrap();
+    wait_for(&args, "run ", 3);
+    thread::sleep(Duration::from_millis(600));
+    fs::write(path.join("util.rs"), "").unwrap();
+    wait_for(&args, "run ", 4);
+    thread::sleep(Duration::from_millis(600));
+    fs::write(path.join("util.rs"), "pub fn helper() {}\n").unwrap();
+    wait_for(&args, "run ", 5);
+
+    Command::new("kill")
+        .arg("-INT")
+        .arg(child.id().to_string())
+        .status()
+        .unwrap();
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success());
+
+    let name = format!("roche-{}", path.file_name().unwrap().to_str().unwrap());
+    let args = fs::read_to_string(args).unwrap();
+    let commands: Vec<&str> = args
+        .lines()
+        .map(|line| line.split(' ').next().unwrap())
+        .collect();
+    assert_eq!(
+        commands,
+        vec![
+            "build", "run", "rm", "build", "build", "run", "rm", "build", "run", "rm", "build",
+            "run", "rm", "build", "run", "rm"
+        ]
+    );
+    assert!(args.contains(&format!("rm -f {}\n", name)));
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stdout.contains("functions.rs changed, rebuilding"));
+    assert!(stdout.contains("util.rs changed, rebuilding"));
+    assert!(stdout.contains("Roche: Built in "));
+    assert!(stdout.contains("Build failed after "));
+    assert!(stderr.contains("error[E0425]"));
+    assert!(stdout.contains(&format!("Roche: Stopped {}", name)));
+
+    remove_dir_all(path).unwrap();
+}
+
+/// Waits until `count` recorded engine invocations start with `command`.
+fn wait_for(args: &Path, command: &str, count: usize) {
+    let started = Instant::now();
+    loop {
+        let recorded = fs::read_to_string(args).unwrap_or_default();
+        if recorded.lines().filter(|l| l.starts_with(command)).count() >= count {
+            return;
+        }
+        assert!(
+            started.elapsed() < Duration::from_secs(20),
+            "timed out waiting for {}{} in {}",
+            command,
+            count,
+            recorded
+        );
+        thread::sleep(Duration::from_millis(50));
+    }
+}
+
+/// Writes a docker stand-in whose builds fail for a `broken` function and whose `run` blocks
+/// until `rm` is called for the container.
+fn fake_docker(bin: &Path) {
+    fake_engine(
+        bin,
+        "docker",
+        &format!(
+            "case \"$1\" in\n\
+             build) read -r first < functions.rs\n\
+             if [ \"$first\" = broken ]; then echo 'error[E0425]: cannot find value `api`' >&2; exit 101; fi ;;\n\
+             run) /bin/rm -f {dir}/stopped; while [ ! -f {dir}/stopped ]; do /bin/sleep 0.1; done ;;\n\
+             rm) : > {dir}/stopped ;;\n\
+             esac\n",
+            dir = bin.display()
+        ),
+    );
+}